@@ -0,0 +1,149 @@
+// a small timing harness rather than a bench framework, so `cargo bench` works without any extra
+// dependencies; `cargo bench -- <filter>` only runs the benchmarks whose names contain the filter
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use img_to_ascii::convert::{
+    direction_and_intensity_convert, img_to_char_rows, intensity_convert, pixels_to_chunks,
+    ConversionAlgorithm, Converter,
+};
+use img_to_ascii::font::Font;
+use img_to_ascii::image::LumaImage;
+use img_to_ascii::metrics::{
+    avg_color_score, dot_score, jaccard_score, movement_toward_clear, occlusion_score, Metric,
+};
+
+const SAMPLE_TIME: Duration = Duration::from_millis(10);
+const BENCH_TIME: Duration = Duration::from_secs(1);
+
+struct Bencher {
+    filter: Option<String>,
+}
+
+impl Bencher {
+    fn bench<T>(&self, name: &str, mut f: impl FnMut() -> T) {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !name.contains(filter.as_str()))
+        {
+            return;
+        }
+        // fast functions are timed in batches, so the timer's resolution doesn't matter
+        let mut iters = 1;
+        loop {
+            let start = Instant::now();
+            for _ in 0..iters {
+                black_box(f());
+            }
+            if start.elapsed() >= SAMPLE_TIME || iters >= 1 << 20 {
+                break;
+            }
+            iters *= 2;
+        }
+        let mut samples = Vec::new();
+        let start = Instant::now();
+        while start.elapsed() < BENCH_TIME || samples.len() < 5 {
+            let sample_start = Instant::now();
+            for _ in 0..iters {
+                black_box(f());
+            }
+            samples.push(sample_start.elapsed() / iters);
+        }
+        samples.sort();
+        println!(
+            "{:<48} {:>12.3?} median {:>12.3?} min ({} samples of {})",
+            name,
+            samples[samples.len() / 2],
+            samples[0],
+            samples.len(),
+            iters
+        );
+    }
+}
+
+fn bench_font() -> Font {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let alphabet: Vec<char> = fs::read_to_string(root.join("alphabets/alphabet.txt"))
+        .unwrap()
+        .chars()
+        .collect();
+    Font::new(
+        &Font::read_bdf_file_chars(&root.join("fonts/bitocra-13.bdf")),
+        &alphabet,
+    )
+}
+
+fn bench_img(width: u32, height: u32) -> LumaImage<f32> {
+    // rings on a diagonal gradient, so there are both edges and smooth regions to convert
+    let img = RgbaImage::from_fn(width, height, |x, y| {
+        let (fx, fy) = (x as f32 / width as f32, y as f32 / height as f32);
+        let ring = (((fx - 0.5).powi(2) + (fy - 0.5).powi(2)).sqrt() * 40.).sin();
+        let v = ((fx + fy) / 2. * 200. + ring * 55.).clamp(0., 255.) as u8;
+        Rgba([v, v / 2, 255 - v, 255])
+    });
+    LumaImage::from(&DynamicImage::ImageRgba8(img))
+}
+
+fn main() {
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let bencher = Bencher { filter };
+    let font = bench_font();
+
+    let chunk: Vec<f32> = (0..font.width * font.height)
+        .map(|i| (i % 7) as f32 / 7.)
+        .collect();
+    let glyph = &font.chars[font.chars.len() / 2].bitmap;
+    let metrics: [(&str, Metric); 5] = [
+        ("jaccard", jaccard_score),
+        ("dot", dot_score),
+        ("occlusion", occlusion_score),
+        ("color", avg_color_score),
+        ("clear", movement_toward_clear),
+    ];
+    for (name, metric) in metrics {
+        bencher.bench(&format!("metric/{}", name), || metric(&chunk, glyph));
+    }
+    let converters: [(&str, Converter); 2] = [
+        ("intensity", intensity_convert),
+        ("direction-and-intensity", direction_and_intensity_convert),
+    ];
+    for (name, convert) in converters {
+        bencher.bench(&format!("convert/{}", name), || convert(&font, &chunk));
+    }
+
+    let img = bench_img(1280, 960);
+    bencher.bench("pixels_to_chunks/1280x960", || {
+        pixels_to_chunks(img.pixels(), 1280, 960, font.width, font.height)
+    });
+    for (width, height) in [(320, 240), (640, 480)] {
+        bencher.bench(&format!("resize/1280x960-to-{}x{}", width, height), || {
+            img.resize(width, height)
+        });
+        bencher.bench(
+            &format!("resize_detail_preserving/1280x960-to-{}x{}", width, height),
+            || img.resize_detail_preserving(width, height),
+        );
+    }
+    let kernel = [vec![0., -1., 0.], vec![-1., 4., -1.], vec![0., -1., 0.]];
+    bencher.bench("convolve_2d/1280x960", || img.convolve_2d(&kernel));
+
+    // whole frames, with the default metric and algorithm
+    for out_width in [80, 160, 320] {
+        bencher.bench(&format!("frame/1280x960-to-{}-columns", out_width), || {
+            img_to_char_rows(
+                &font,
+                &img,
+                direction_and_intensity_convert,
+                Some(out_width),
+                0.,
+                &ConversionAlgorithm::EdgeAugmented,
+                None,
+            )
+        });
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+// .ascz archives bundle everything from a conversion into one file: a gzip compressed tar of
+// named entries, which can be unpacked with standard tools as well as with the extract subcommand
+
+const BLOCK_SIZE: usize = 512;
+
+fn tar_header(name: &str, size: usize) -> [u8; BLOCK_SIZE] {
+    // a ustar header for a regular file
+    let mut header = [0u8; BLOCK_SIZE];
+    if name.len() > 100 {
+        panic!("Unsupported archive entry name {:?}", name);
+    }
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    field(0, name.as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, b"00000000000\0");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+    // the checksum is computed with its own field filled with spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+pub fn write_tar(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut tar = Vec::new();
+    for (name, data) in entries {
+        tar.extend(tar_header(name, data.len()));
+        tar.extend(data);
+        tar.resize(tar.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    }
+    // the end of the archive is marked by two empty blocks
+    tar.resize(tar.len() + 2 * BLOCK_SIZE, 0);
+    tar
+}
+
+pub fn read_tar(tar: &[u8]) -> Vec<(String, Vec<u8>)> {
+    // only regular files are kept
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + BLOCK_SIZE <= tar.len() && tar[offset] != 0 {
+        let header = &tar[offset..offset + BLOCK_SIZE];
+        let text = |start: usize, end: usize| {
+            let field = &header[start..end];
+            let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..len]).trim().to_string()
+        };
+        let size = usize::from_str_radix(&text(124, 136), 8)
+            .unwrap_or_else(|_| panic!("Unsupported archive header at byte {}", offset));
+        let name = match text(345, 500) {
+            prefix if prefix.is_empty() => text(0, 100),
+            prefix => format!("{}/{}", prefix, text(0, 100)),
+        };
+        let start = offset + BLOCK_SIZE;
+        if start + size > tar.len() {
+            panic!("Unsupported archive; {:?} is truncated", name);
+        }
+        if matches!(header[156], b'0' | 0) {
+            entries.push((name, tar[start..start + size].to_vec()));
+        }
+        offset = start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    }
+    entries
+}
+
+pub fn write_archive(path: &Path, entries: &[(String, Vec<u8>)]) {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&write_tar(entries)).unwrap();
+    fs::write(path, encoder.finish().unwrap()).unwrap();
+}
+
+pub fn read_archive(path: &Path) -> Vec<(String, Vec<u8>)> {
+    let file =
+        fs::File::open(path).unwrap_or_else(|err| panic!("Error reading {:?}: {}", path, err));
+    let mut tar = Vec::new();
+    GzDecoder::new(file)
+        .read_to_end(&mut tar)
+        .unwrap_or_else(|err| panic!("Unsupported archive {:?}: {}", path, err));
+    read_tar(&tar)
+}
+
+pub fn archive_entry<'a>(entries: &'a [(String, Vec<u8>)], name: &str) -> &'a [u8] {
+    entries
+        .iter()
+        .find(|(entry_name, _)| entry_name == name)
+        .map(|(_, data)| data.as_slice())
+        .unwrap_or_else(|| panic!("The archive is missing {}", name))
+}
+
+fn entry_path(out_dir: &Path, name: &str) -> PathBuf {
+    // archives can come from anywhere, so an entry may only name a path inside of out_dir
+    let inside = Path::new(name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if name.is_empty() || !inside {
+        panic!("Unsupported archive entry name {:?}", name);
+    }
+    out_dir.join(name)
+}
+
+pub fn extract_archive(path: &Path, out_dir: &Path) -> usize {
+    // every name is checked before anything is written; returns the number of files written
+    let entries = read_archive(path);
+    let paths: Vec<PathBuf> = entries
+        .iter()
+        .map(|(name, _)| entry_path(out_dir, name))
+        .collect();
+    for (path, (_, data)) in paths.iter().zip(&entries) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+    entries.len()
+}
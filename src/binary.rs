@@ -0,0 +1,188 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use serde_json::Value;
+
+// compact binary containers for the json outputs, for programs reading large animations
+pub enum BinaryFormat {
+    MessagePack,
+    Cbor,
+}
+
+pub fn get_binary_format(extension: &str) -> Option<BinaryFormat> {
+    match extension {
+        "msgpack" => Some(BinaryFormat::MessagePack),
+        "cbor" => Some(BinaryFormat::Cbor),
+        _ => None,
+    }
+}
+
+pub enum Compression {
+    Gzip,
+}
+
+pub fn get_compression(compression: &str) -> Compression {
+    match compression {
+        "gzip" => Compression::Gzip,
+        _ => panic!("Unsupported compression {}", compression),
+    }
+}
+
+pub fn compress(bytes: &[u8], compression: &Compression) -> Vec<u8> {
+    match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(bytes).unwrap();
+            encoder.finish().unwrap()
+        }
+    }
+}
+
+pub fn encode(value: &Value, format: &BinaryFormat) -> Vec<u8> {
+    let mut out = Vec::new();
+    match format {
+        BinaryFormat::MessagePack => write_msgpack(&mut out, value),
+        BinaryFormat::Cbor => write_cbor(&mut out, value),
+    }
+    out
+}
+
+fn write_msgpack_len(out: &mut Vec<u8>, len: usize, fix: u8, fix_max: usize, markers: [u8; 3]) {
+    // fixed size, then 8 (strings only, marker 0 otherwise), 16, and 32 bit lengths
+    if len < fix_max {
+        out.push(fix | len as u8);
+    } else if len < 1 << 8 && markers[0] != 0 {
+        out.extend([markers[0], len as u8]);
+    } else if len < 1 << 16 {
+        out.push(markers[1]);
+        out.extend((len as u16).to_be_bytes());
+    } else {
+        out.push(markers[2]);
+        out.extend((len as u32).to_be_bytes());
+    }
+}
+
+fn write_msgpack(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(b) => out.push(if *b { 0xc3 } else { 0xc2 }),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                if n < 128 {
+                    out.push(n as u8);
+                } else if n < 1 << 8 {
+                    out.extend([0xcc, n as u8]);
+                } else if n < 1 << 16 {
+                    out.push(0xcd);
+                    out.extend((n as u16).to_be_bytes());
+                } else if n < 1 << 32 {
+                    out.push(0xce);
+                    out.extend((n as u32).to_be_bytes());
+                } else {
+                    out.push(0xcf);
+                    out.extend(n.to_be_bytes());
+                }
+            } else if let Some(n) = n.as_i64() {
+                if n >= -32 {
+                    out.push(n as i8 as u8);
+                } else if n >= -(1 << 7) {
+                    out.extend([0xd0, n as i8 as u8]);
+                } else if n >= -(1 << 15) {
+                    out.push(0xd1);
+                    out.extend((n as i16).to_be_bytes());
+                } else if n >= -(1 << 31) {
+                    out.push(0xd2);
+                    out.extend((n as i32).to_be_bytes());
+                } else {
+                    out.push(0xd3);
+                    out.extend(n.to_be_bytes());
+                }
+            } else {
+                // floats stored from f32s, like scores and intensities, keep their size
+                let n = n.as_f64().unwrap();
+                if n as f32 as f64 == n {
+                    out.push(0xca);
+                    out.extend((n as f32).to_be_bytes());
+                } else {
+                    out.push(0xcb);
+                    out.extend(n.to_be_bytes());
+                }
+            }
+        }
+        Value::String(s) => {
+            write_msgpack_len(out, s.len(), 0xa0, 32, [0xd9, 0xda, 0xdb]);
+            out.extend(s.as_bytes());
+        }
+        Value::Array(values) => {
+            write_msgpack_len(out, values.len(), 0x90, 16, [0, 0xdc, 0xdd]);
+            for value in values {
+                write_msgpack(out, value);
+            }
+        }
+        Value::Object(map) => {
+            write_msgpack_len(out, map.len(), 0x80, 16, [0, 0xde, 0xdf]);
+            for (key, value) in map {
+                write_msgpack(out, &Value::String(key.clone()));
+                write_msgpack(out, value);
+            }
+        }
+    }
+}
+
+fn write_cbor_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n < 1 << 8 {
+        out.extend([major | 24, n as u8]);
+    } else if n < 1 << 16 {
+        out.push(major | 25);
+        out.extend((n as u16).to_be_bytes());
+    } else if n < 1 << 32 {
+        out.push(major | 26);
+        out.extend((n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend(n.to_be_bytes());
+    }
+}
+
+fn write_cbor(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(b) => out.push(if *b { 0xf5 } else { 0xf4 }),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                write_cbor_head(out, 0, n);
+            } else if let Some(n) = n.as_i64() {
+                write_cbor_head(out, 1, (-1 - n) as u64);
+            } else {
+                let n = n.as_f64().unwrap();
+                if n as f32 as f64 == n {
+                    out.push(0xfa);
+                    out.extend((n as f32).to_be_bytes());
+                } else {
+                    out.push(0xfb);
+                    out.extend(n.to_be_bytes());
+                }
+            }
+        }
+        Value::String(s) => {
+            write_cbor_head(out, 3, s.len() as u64);
+            out.extend(s.as_bytes());
+        }
+        Value::Array(values) => {
+            write_cbor_head(out, 4, values.len() as u64);
+            for value in values {
+                write_cbor(out, value);
+            }
+        }
+        Value::Object(map) => {
+            write_cbor_head(out, 5, map.len() as u64);
+            for (key, value) in map {
+                write_cbor(out, &Value::String(key.clone()));
+                write_cbor(out, value);
+            }
+        }
+    }
+}
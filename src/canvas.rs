@@ -0,0 +1,127 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+// the margins of the action safe and title safe areas on every side, as fractions of the canvas,
+// as used for broadcast and stream overlays
+pub const ACTION_SAFE_MARGIN: f32 = 0.035;
+pub const TITLE_SAFE_MARGIN: f32 = 0.05;
+
+const ACTION_SAFE_COLOR: Rgba<u8> = Rgba([255, 0, 255, 192]);
+const TITLE_SAFE_COLOR: Rgba<u8> = Rgba([0, 255, 255, 192]);
+
+pub fn parse_canvas_size(size: &str) -> (u32, u32) {
+    // e.g. 1920x1080
+    size.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .unwrap_or_else(|| panic!("Unsupported canvas size {}", size))
+}
+
+fn safe_rect(width: u32, height: u32, margin: f32) -> (u32, u32, u32, u32) {
+    // (left, top, width, height) of the area inside the margin
+    let (x, y) = (
+        (width as f32 * margin).round() as u32,
+        (height as f32 * margin).round() as u32,
+    );
+    (x, y, width - 2 * x, height - 2 * y)
+}
+
+fn unpremultiply_black(img: &DynamicImage) -> RgbaImage {
+    // glyphs drawn on black become glyphs on nothing, with the black mixed into them turned into
+    // transparency
+    let mut img = img.to_rgba8();
+    for pixel in img.pixels_mut() {
+        let Rgba([r, g, b, a]) = *pixel;
+        let max = r.max(g).max(b);
+        if max == 0 {
+            *pixel = Rgba([0, 0, 0, 0]);
+        } else {
+            let scale = |c: u8| (c as u32 * 255 / max as u32) as u8;
+            *pixel = Rgba([
+                scale(r),
+                scale(g),
+                scale(b),
+                (max as u32 * a as u32 / 255) as u8,
+            ]);
+        }
+    }
+    img
+}
+
+fn draw_rect_outline(
+    img: &mut RgbaImage,
+    rect: (u32, u32, u32, u32),
+    thickness: u32,
+    color: Rgba<u8>,
+) {
+    let (x0, y0, width, height) = rect;
+    for y in y0..y0 + height {
+        for x in x0..x0 + width {
+            let edge = x < x0 + thickness
+                || x + thickness >= x0 + width
+                || y < y0 + thickness
+                || y + thickness >= y0 + height;
+            if edge {
+                img.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+pub fn place_on_canvas(
+    img: &DynamicImage,
+    canvas: (u32, u32),
+    transparent: bool,
+    safe_area: bool,
+    guides: bool,
+) -> DynamicImage {
+    // centers the rendered output on a canvas, scaled to fit in the whole canvas or only in its
+    // title safe area; it is scaled up by whole factors so the glyphs stay sharp, and only
+    // smoothed when it has to be scaled down
+    let (width, height) = canvas;
+    let (x0, y0, area_width, area_height) = if safe_area {
+        safe_rect(width, height, TITLE_SAFE_MARGIN)
+    } else {
+        (0, 0, width, height)
+    };
+    let scale =
+        (area_width as f32 / img.width() as f32).min(area_height as f32 / img.height() as f32);
+    let art = if scale >= 2. {
+        let scale = scale.floor() as u32;
+        img.resize_exact(
+            img.width() * scale,
+            img.height() * scale,
+            FilterType::Nearest,
+        )
+    } else if scale < 1. {
+        img.resize(area_width, area_height, FilterType::Triangle)
+    } else {
+        img.clone()
+    };
+    let art = if transparent {
+        unpremultiply_black(&art)
+    } else {
+        art.to_rgba8()
+    };
+
+    let mut out = RgbaImage::new(width, height);
+    let x = x0 + (area_width - art.width()) / 2;
+    let y = y0 + (area_height - art.height()) / 2;
+    image::imageops::replace(&mut out, &art, x as i64, y as i64);
+    if guides {
+        let thickness = (height / 540).max(1);
+        draw_rect_outline(
+            &mut out,
+            safe_rect(width, height, ACTION_SAFE_MARGIN),
+            thickness,
+            ACTION_SAFE_COLOR,
+        );
+        draw_rect_outline(
+            &mut out,
+            safe_rect(width, height, TITLE_SAFE_MARGIN),
+            thickness,
+            TITLE_SAFE_COLOR,
+        );
+    }
+    DynamicImage::ImageRgba8(out)
+}
@@ -0,0 +1,397 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde_json::{json, Value};
+
+use crate::convert::CharConfidence;
+use crate::font::WIDE_CONTINUATION;
+use crate::width::char_width;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub value: char,
+    pub color: [u8; 4],
+    pub confidence: Option<CharConfidence>,
+    // the cell's brightness from 0 to 1, stored for the intensity metric so the alphabet can be
+    // changed without the original image
+    pub intensity: Option<f32>,
+}
+
+pub type CellRows = Vec<Vec<Cell>>;
+
+pub fn char_rows_to_cells(char_rows: &[Vec<char>], img: &DynamicImage) -> CellRows {
+    let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
+    let color_resized_image = img
+        .resize_exact(n_cols as u32, n_rows as u32, FilterType::Nearest)
+        .to_rgba8();
+
+    char_rows
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(x, &value)| Cell {
+                    value,
+                    color: color_resized_image.get_pixel(x as u32, y as u32).0,
+                    confidence: None,
+                    intensity: None,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn add_confidence(cells: &mut CellRows, confidence: &[Vec<CharConfidence>]) {
+    for (row, confidence_row) in cells.iter_mut().zip(confidence) {
+        for (cell, &confidence) in row.iter_mut().zip(confidence_row) {
+            cell.confidence = Some(confidence);
+        }
+    }
+}
+
+pub fn add_intensity(cells: &mut CellRows, intensity: &[Vec<f32>]) {
+    for (row, intensity_row) in cells.iter_mut().zip(intensity) {
+        for (cell, &intensity) in row.iter_mut().zip(intensity_row) {
+            cell.intensity = Some(intensity);
+        }
+    }
+}
+
+pub fn cells_to_char_rows(cells: &[Vec<Cell>]) -> Vec<Vec<char>> {
+    cells
+        .iter()
+        .map(|row| row.iter().map(|cell| cell.value).collect())
+        .collect()
+}
+
+pub fn cells_to_color_img(cells: &[Vec<Cell>]) -> DynamicImage {
+    // one pixel per cell, which the color writers sample without any resampling
+    let (n_cols, n_rows) = (cells[0].len(), cells.len());
+    let mut img = RgbaImage::new(n_cols as u32, n_rows as u32);
+    for (y, row) in cells.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            img.put_pixel(x as u32, y as u32, Rgba(cell.color));
+        }
+    }
+    DynamicImage::ImageRgba8(img)
+}
+
+pub fn cells_to_json(frames: &[CellRows], cell_size: Option<(usize, usize)>) -> Value {
+    let (n_cols, n_rows) = frames
+        .first()
+        .map_or((0, 0), |cells| (cells[0].len(), cells.len()));
+    let frames: Vec<Value> = frames
+        .iter()
+        .map(|cells| {
+            let rows: Vec<Value> = cells
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .filter(|cell| cell.value != WIDE_CONTINUATION)
+                        .map(|cell| {
+                            let mut value =
+                                json!({"char": cell.value.to_string(), "color": cell.color});
+                            if let Some(confidence) = cell.confidence {
+                                value["score"] = json!(confidence.score);
+                                value["margin"] = json!(confidence.margin);
+                            }
+                            if let Some(intensity) = cell.intensity {
+                                value["intensity"] = json!(intensity);
+                            }
+                            value
+                        })
+                        .collect()
+                })
+                .collect();
+            json!({ "cells": rows })
+        })
+        .collect();
+    let mut value = json!({
+        "version": 1,
+        "columns": n_cols,
+        "rows": n_rows,
+        "frames": frames,
+    });
+    // the font's cell size lets the grid be resampled for fonts with other aspect ratios
+    if let Some((width, height)) = cell_size {
+        value["cell_width"] = json!(width);
+        value["cell_height"] = json!(height);
+    }
+    value
+}
+
+pub fn cells_to_planes_json(
+    frames: &[CellRows],
+    cell_size: Option<(usize, usize)>,
+    base64: bool,
+) -> Value {
+    // the characters and colors of each frame as separate planes: one string per row, and the
+    // rgb values of every cell row by row (base64 encoded bytes, if base64 is set), which is much
+    // smaller than cells json and can be uploaded straight into a canvas or a texture
+    let (n_cols, n_rows) = frames
+        .first()
+        .map_or((0, 0), |cells| (cells[0].len(), cells.len()));
+    let frames: Vec<Value> = frames
+        .iter()
+        .map(|cells| {
+            let chars: Vec<String> = cells
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|cell| cell.value)
+                        .filter(|&c| c != WIDE_CONTINUATION)
+                        .collect()
+                })
+                .collect();
+            let colors: Vec<u8> = cells
+                .iter()
+                .flatten()
+                .flat_map(|cell| [cell.color[0], cell.color[1], cell.color[2]])
+                .collect();
+            let colors = if base64 {
+                json!(crate::base64::encode(&colors))
+            } else {
+                json!(colors)
+            };
+            json!({ "chars": chars, "colors": colors })
+        })
+        .collect();
+    let mut value = json!({
+        "version": 1,
+        "format": if base64 { "planes-base64" } else { "planes" },
+        "columns": n_cols,
+        "rows": n_rows,
+        "frames": frames,
+    });
+    if let Some((width, height)) = cell_size {
+        value["cell_width"] = json!(width);
+        value["cell_height"] = json!(height);
+    }
+    value
+}
+
+pub fn cell_size_from_json(value: &Value) -> Option<(usize, usize)> {
+    let width = value["cell_width"].as_u64()?;
+    let height = value["cell_height"].as_u64()?;
+    Some((width as usize, height as usize))
+}
+
+fn cell_from_json(value: &Value) -> Cell {
+    let value_char = value["char"]
+        .as_str()
+        .and_then(|s| s.chars().next())
+        .expect("cell is missing a char");
+    let color = value["color"].as_array().expect("cell is missing a color");
+    let channel = |i: usize| color.get(i).and_then(|c| c.as_u64()).unwrap_or(255) as u8;
+    let confidence = value["score"].as_f64().map(|score| CharConfidence {
+        score: score as f32,
+        margin: value["margin"].as_f64().unwrap_or(0.) as f32,
+    });
+    Cell {
+        value: value_char,
+        color: [channel(0), channel(1), channel(2), channel(3)],
+        confidence,
+        intensity: value["intensity"]
+            .as_f64()
+            .map(|intensity| intensity as f32),
+    }
+}
+
+pub fn cells_from_json(value: &Value) -> Vec<CellRows> {
+    let frames = value["frames"]
+        .as_array()
+        .expect("cells json is missing frames");
+    let frames: Vec<CellRows> = frames
+        .iter()
+        .map(|frame| {
+            frame["cells"]
+                .as_array()
+                .expect("frame is missing cells")
+                .iter()
+                .map(|row| {
+                    // double width characters cover a second cell, which isn't stored
+                    let mut cells = Vec::new();
+                    for cell in row.as_array().expect("cell row must be an array") {
+                        let cell = cell_from_json(cell);
+                        cells.push(cell);
+                        if char_width(cell.value) == Some(2) {
+                            cells.push(Cell {
+                                value: WIDE_CONTINUATION,
+                                ..cell
+                            });
+                        }
+                    }
+                    cells
+                })
+                .collect()
+        })
+        .collect();
+
+    // hand edited or truncated json would otherwise fail deep inside of the writers
+    let first_size = frames
+        .first()
+        .and_then(|cells| Some((cells.first()?.len(), cells.len())));
+    let size = |key: &str, first: Option<usize>| {
+        value[key]
+            .as_u64()
+            .map(|n| n as usize)
+            .or(first)
+            .unwrap_or(0)
+    };
+    let n_cols = size("columns", first_size.map(|(n_cols, _)| n_cols));
+    let n_rows = size("rows", first_size.map(|(_, n_rows)| n_rows));
+    if frames.is_empty() || n_cols == 0 || n_rows == 0 {
+        panic!("Unsupported cells json; it has no cells");
+    }
+    for (i, cells) in frames.iter().enumerate() {
+        if cells.len() != n_rows || cells.iter().any(|row| row.len() != n_cols) {
+            panic!(
+                "Unsupported cells json; frame {} isn't {} columns by {} rows",
+                i, n_cols, n_rows
+            );
+        }
+    }
+    frames
+}
+
+pub enum Resample {
+    Nearest,
+    Intensity,
+}
+
+pub fn get_resample(resample: &str) -> Option<Resample> {
+    match resample {
+        "none" => None,
+        "nearest" => Some(Resample::Nearest),
+        "intensity" => Some(Resample::Intensity),
+        _ => panic!("Unsupported resample method {}", resample),
+    }
+}
+
+fn overlap(a0: f32, a1: f32, b0: f32, b1: f32) -> f32 {
+    (a1.min(b1) - a0.max(b0)).max(0.)
+}
+
+pub fn resample_cells<F: Fn(char) -> f32>(
+    cells: &[Vec<Cell>],
+    n_cols: usize,
+    n_rows: usize,
+    resample: &Resample,
+    intensity: F,
+) -> CellRows {
+    let (src_cols, src_rows) = (cells[0].len(), cells.len());
+    let (sx, sy) = (
+        src_cols as f32 / n_cols as f32,
+        src_rows as f32 / n_rows as f32,
+    );
+    // the second cell of a double width character stands for the character itself
+    let lead_cells: CellRows = cells
+        .iter()
+        .map(|row| {
+            let mut lead = row[0];
+            row.iter()
+                .map(|&cell| {
+                    if cell.value != WIDE_CONTINUATION {
+                        lead = cell;
+                    }
+                    Cell {
+                        value: lead.value,
+                        ..cell
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut out: CellRows = (0..n_rows)
+        .map(|y| {
+            (0..n_cols)
+                .map(|x| match resample {
+                    Resample::Nearest => {
+                        let src_x = (((x as f32 + 0.5) * sx) as usize).min(src_cols - 1);
+                        let src_y = (((y as f32 + 0.5) * sy) as usize).min(src_rows - 1);
+                        lead_cells[src_y][src_x]
+                    }
+                    Resample::Intensity => {
+                        // average the intensities and colors of the covered cells by how much of
+                        // them is covered, then use the covered character closest to the average
+                        let (x0, x1) = (x as f32 * sx, (x + 1) as f32 * sx);
+                        let (y0, y1) = (y as f32 * sy, (y + 1) as f32 * sy);
+                        let mut covered = Vec::new();
+                        for (src_y, row) in lead_cells
+                            .iter()
+                            .enumerate()
+                            .take(y1.ceil() as usize)
+                            .skip(y0 as usize)
+                        {
+                            for (src_x, &cell) in row
+                                .iter()
+                                .enumerate()
+                                .take(x1.ceil() as usize)
+                                .skip(x0 as usize)
+                            {
+                                let weight = overlap(x0, x1, src_x as f32, src_x as f32 + 1.)
+                                    * overlap(y0, y1, src_y as f32, src_y as f32 + 1.);
+                                if weight > 0. {
+                                    covered.push((cell, weight));
+                                }
+                            }
+                        }
+                        let total: f32 = covered.iter().map(|(_, w)| w).sum();
+                        let target = covered
+                            .iter()
+                            .map(|(cell, w)| intensity(cell.value) * w)
+                            .sum::<f32>()
+                            / total;
+                        let mut color = [0.; 4];
+                        for (cell, w) in &covered {
+                            for (c, &channel) in color.iter_mut().zip(&cell.color) {
+                                *c += channel as f32 * w / total;
+                            }
+                        }
+                        let (best, _) = covered
+                            .iter()
+                            .min_by(|(a, wa), (b, wb)| {
+                                let da = (intensity(a.value) - target).abs();
+                                let db = (intensity(b.value) - target).abs();
+                                da.total_cmp(&db).then(wb.total_cmp(wa))
+                            })
+                            .unwrap();
+                        // stored intensities are averaged too, if every covered cell has one
+                        let stored_intensity = covered
+                            .iter()
+                            .map(|(cell, w)| cell.intensity.map(|i| i * w / total))
+                            .sum();
+                        Cell {
+                            value: best.value,
+                            color: color.map(|c| c.round() as u8),
+                            confidence: None,
+                            intensity: stored_intensity,
+                        }
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    fix_wide_cells(&mut out);
+    out
+}
+
+pub fn fix_wide_cells(cells: &mut CellRows) {
+    // double width characters need their second cell, and are dropped at the end of a row
+    for row in cells.iter_mut() {
+        let mut x = 0;
+        while x < row.len() {
+            if char_width(row[x].value) == Some(2) {
+                if x + 1 < row.len() {
+                    row[x + 1].value = WIDE_CONTINUATION;
+                    x += 1;
+                } else {
+                    row[x].value = ' ';
+                }
+            }
+            x += 1;
+        }
+    }
+}
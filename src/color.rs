@@ -0,0 +1,411 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage, Rgba, RgbaImage};
+
+pub fn parse_hex_color(color: &str) -> Rgb<u8> {
+    let hex = color.trim_start_matches('#');
+    if hex.len() != 6 {
+        panic!(
+            "Unsupported color {}; expected a hex color like #ff8800",
+            color
+        );
+    }
+    let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16)
+            .unwrap_or_else(|_| panic!("Unsupported color {}", color))
+    };
+    Rgb([channel(0), channel(2), channel(4)])
+}
+
+pub fn parse_hex_colors(colors: &str) -> Vec<Rgb<u8>> {
+    colors.split(',').map(parse_hex_color).collect()
+}
+
+pub fn lerp_color(a: Rgb<u8>, t: f32, b: Rgb<u8>) -> Rgb<u8> {
+    let channel = |i: usize| (a[i] as f32 + t * (b[i] as f32 - a[i] as f32)).round() as u8;
+    Rgb([channel(0), channel(1), channel(2)])
+}
+
+pub fn gradient_color(colors: &[Rgb<u8>], t: f32) -> Rgb<u8> {
+    // evenly spaced color stops over t in [0, 1]
+    if colors.len() == 1 {
+        return colors[0];
+    }
+    let position = t.clamp(0., 1.) * (colors.len() - 1) as f32;
+    let i = (position.floor() as usize).min(colors.len() - 2);
+    lerp_color(colors[i], position - i as f32, colors[i + 1])
+}
+
+pub fn heatmap_color(t: f32) -> Rgb<u8> {
+    // dark blue for 0 through green and yellow to red for 1
+    let stops = [
+        Rgb([0, 0, 128]),
+        Rgb([0, 128, 255]),
+        Rgb([0, 255, 128]),
+        Rgb([255, 255, 0]),
+        Rgb([255, 0, 0]),
+    ];
+    gradient_color(&stops, t)
+}
+
+pub fn hue_color(hue: f32) -> Rgb<u8> {
+    // fully saturated and bright, with the hue in degrees
+    let h = hue.rem_euclid(360.) / 60.;
+    let x = 1. - (h % 2. - 1.).abs();
+    let (r, g, b) = match h as usize {
+        0 => (1., x, 0.),
+        1 => (x, 1., 0.),
+        2 => (0., 1., x),
+        3 => (0., x, 1.),
+        4 => (x, 0., 1.),
+        _ => (1., 0., x),
+    };
+    Rgb([(r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8])
+}
+
+pub fn horizontal_gradient_img(colors: &[Rgb<u8>], width: usize, height: usize) -> DynamicImage {
+    let mut img = RgbImage::new(width as u32, height as u32);
+    for (x, _, pixel) in img.enumerate_pixels_mut() {
+        let t = if width > 1 {
+            x as f32 / (width - 1) as f32
+        } else {
+            0.
+        };
+        *pixel = gradient_color(colors, t);
+    }
+    DynamicImage::ImageRgb8(img)
+}
+
+fn color_distance(a: Rgb<u8>, b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] as f32 - b[i]).powi(2)).sum()
+}
+
+pub fn kmeans_palette(colors: &[Rgb<u8>], n: usize, iterations: usize) -> Vec<Rgb<u8>> {
+    // starts from colors evenly spaced by brightness so that the result is deterministic
+    let mut unique: Vec<Rgb<u8>> = colors.to_vec();
+    unique.sort_by_key(|c| {
+        (
+            c[0] as u32 * 299 + c[1] as u32 * 587 + c[2] as u32 * 114,
+            c.0,
+        )
+    });
+    unique.dedup();
+    if unique.len() <= n {
+        return unique;
+    }
+    let mut centroids: Vec<[f32; 3]> = (0..n)
+        .map(|i| {
+            let c = unique[i * (unique.len() - 1) / (n - 1).max(1)];
+            [c[0] as f32, c[1] as f32, c[2] as f32]
+        })
+        .collect();
+
+    for _ in 0..iterations {
+        let mut sums = vec![[0f32; 3]; n];
+        let mut counts = vec![0usize; n];
+        for &c in colors {
+            let nearest = (0..n)
+                .min_by(|&i, &j| {
+                    color_distance(c, centroids[i])
+                        .partial_cmp(&color_distance(c, centroids[j]))
+                        .unwrap()
+                })
+                .unwrap();
+            for channel in 0..3 {
+                sums[nearest][channel] += c[channel] as f32;
+            }
+            counts[nearest] += 1;
+        }
+
+        let mut moved = false;
+        for i in 0..n {
+            // empty clusters keep their previous centroid
+            if counts[i] > 0 {
+                let mean = sums[i].map(|sum| sum / counts[i] as f32);
+                moved |= (0..3).any(|channel| (mean[channel] - centroids[i][channel]).abs() > 0.5);
+                centroids[i] = mean;
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+
+    centroids
+        .iter()
+        .map(|c| Rgb(c.map(|channel| channel.round() as u8)))
+        .collect()
+}
+
+pub fn nearest_color(palette: &[Rgb<u8>], color: Rgb<u8>) -> Rgb<u8> {
+    *palette
+        .iter()
+        .min_by_key(|p| {
+            (0..3)
+                .map(|i| (p[i] as i32 - color[i] as i32).pow(2))
+                .sum::<i32>()
+        })
+        .unwrap()
+}
+
+pub fn quantize_img(img: &DynamicImage, palette: &[Rgb<u8>]) -> DynamicImage {
+    // alpha is kept as is, since it carries the transparency of the cell rather than its color
+    let mut img = img.to_rgba8();
+    for pixel in img.pixels_mut() {
+        let Rgb([r, g, b]) = nearest_color(palette, Rgb([pixel[0], pixel[1], pixel[2]]));
+        *pixel = Rgba([r, g, b, pixel[3]]);
+    }
+    DynamicImage::ImageRgba8(img)
+}
+
+pub fn cycle_color_frames(
+    img: &DynamicImage,
+    palette: &[Rgb<u8>],
+    n_frames: usize,
+) -> Vec<DynamicImage> {
+    // every pixel takes the index of its nearest palette color, and each frame shifts the indices
+    // along the palette, so that the colors make one full rotation over the animation
+    let img = img.to_rgba8();
+    let indices: Vec<usize> = img
+        .pixels()
+        .map(|pixel| {
+            let color = nearest_color(palette, Rgb([pixel[0], pixel[1], pixel[2]]));
+            palette.iter().position(|&p| p == color).unwrap()
+        })
+        .collect();
+    (0..n_frames)
+        .map(|frame| {
+            let shift = frame * palette.len() / n_frames;
+            let mut out = img.clone();
+            for (pixel, &i) in out.pixels_mut().zip(&indices) {
+                let Rgb([r, g, b]) = palette[(i + shift) % palette.len()];
+                *pixel = Rgba([r, g, b, pixel[3]]);
+            }
+            DynamicImage::ImageRgba8(out)
+        })
+        .collect()
+}
+
+pub fn get_palette(palette: &str) -> Vec<Rgb<u8>> {
+    // named color blind safe presets, or a comma separated list of hex colors
+    match palette {
+        // Okabe and Ito, "Color Universal Design"
+        "cb-safe-8" => {
+            parse_hex_colors("#000000,#e69f00,#56b4e9,#009e73,#f0e442,#0072b2,#d55e00,#cc79a7")
+        }
+        // Paul Tol's bright and high contrast qualitative schemes
+        "tol-bright-7" => {
+            parse_hex_colors("#4477aa,#ee6677,#228833,#ccbb44,#66ccee,#aa3377,#bbbbbb")
+        }
+        "tol-high-contrast-5" => parse_hex_colors("#ffffff,#ddaa33,#bb5566,#004488,#000000"),
+        _ => parse_hex_colors(palette),
+    }
+}
+
+#[derive(Debug)]
+pub enum ColorVision {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+    Achromatopsia,
+}
+
+pub fn get_color_vision(color_vision: &str) -> ColorVision {
+    match color_vision {
+        "protanopia" => ColorVision::Protanopia,
+        "deuteranopia" => ColorVision::Deuteranopia,
+        "tritanopia" => ColorVision::Tritanopia,
+        "achromatopsia" => ColorVision::Achromatopsia,
+        _ => panic!("Unsupported color vision deficiency {}", color_vision),
+    }
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0., 1.);
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    };
+    (c * 255.).round() as u8
+}
+
+pub fn simulate_color_vision_color(color: Rgb<u8>, color_vision: &ColorVision) -> Rgb<u8> {
+    // full severity matrices from Machado, Oliveira, and Fernandes (2009), applied in linear rgb
+    let matrix: [[f32; 3]; 3] = match color_vision {
+        ColorVision::Protanopia => [
+            [0.152286, 1.052583, -0.204868],
+            [0.114503, 0.786281, 0.099216],
+            [-0.003882, -0.048116, 1.051998],
+        ],
+        ColorVision::Deuteranopia => [
+            [0.367322, 0.860646, -0.227968],
+            [0.280085, 0.672501, 0.047413],
+            [-0.011820, 0.042940, 0.968881],
+        ],
+        ColorVision::Tritanopia => [
+            [1.255528, -0.076749, -0.178779],
+            [-0.078411, 0.930809, 0.147602],
+            [0.004733, 0.691367, 0.303900],
+        ],
+        ColorVision::Achromatopsia => [[0.2126, 0.7152, 0.0722]; 3],
+    };
+    let linear = color.0.map(srgb_to_linear);
+    let channel = |i: usize| linear_to_srgb((0..3).map(|j| matrix[i][j] * linear[j]).sum());
+    Rgb([channel(0), channel(1), channel(2)])
+}
+
+pub fn simulate_color_vision(img: &DynamicImage, color_vision: &ColorVision) -> DynamicImage {
+    let mut img = img.to_rgba8();
+    for pixel in img.pixels_mut() {
+        let Rgb([r, g, b]) =
+            simulate_color_vision_color(Rgb([pixel[0], pixel[1], pixel[2]]), color_vision);
+        *pixel = Rgba([r, g, b, pixel[3]]);
+    }
+    DynamicImage::ImageRgba8(img)
+}
+
+pub fn relative_luminance(color: Rgb<u8>) -> f32 {
+    let [r, g, b] = color.0.map(srgb_to_linear);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+pub fn contrast_ratio(a: Rgb<u8>, b: Rgb<u8>) -> f32 {
+    // as defined by wcag, from 1 for the same luminance to 21 for black on white
+    let (a, b) = (relative_luminance(a), relative_luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+// the contrast characters keep against the paper unless --min-contrast is given, the wcag
+// minimum for large text
+pub const PAPER_MIN_CONTRAST: f32 = 3.;
+
+pub fn ink_color(paper: Rgb<u8>) -> Rgb<u8> {
+    // black or white, whichever stands out more from the paper
+    let white = Rgb([255, 255, 255]);
+    let black = Rgb([0, 0, 0]);
+    if contrast_ratio(white, paper) > contrast_ratio(black, paper) {
+        white
+    } else {
+        black
+    }
+}
+
+fn srgb_to_oklab(color: Rgb<u8>) -> [f32; 3] {
+    // from Björn Ottosson's definition of oklab
+    let [r, g, b] = color.0.map(srgb_to_linear);
+    let l = (0.4122215 * r + 0.5363326 * g + 0.051446 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.107397 * b).cbrt();
+    let s = (0.08830246 * r + 0.2817189 * g + 0.6299787 * b).cbrt();
+    [
+        0.2104543 * l + 0.7936178 * m - 0.004072047 * s,
+        1.977998 * l - 2.428592 * m + 0.4505937 * s,
+        0.02590404 * l + 0.7827718 * m - 0.8086758 * s,
+    ]
+}
+
+fn oklab_to_srgb([lightness, a, b]: [f32; 3]) -> Rgb<u8> {
+    let l = (lightness + 0.3963378 * a + 0.2158038 * b).powi(3);
+    let m = (lightness - 0.1055613 * a - 0.06385417 * b).powi(3);
+    let s = (lightness - 0.08948418 * a - 1.291486 * b).powi(3);
+    Rgb([
+        linear_to_srgb(4.076742 * l - 3.307712 * m + 0.2309699 * s),
+        linear_to_srgb(-1.268438 * l + 2.609757 * m - 0.3413194 * s),
+        linear_to_srgb(-0.004196086 * l - 0.7034186 * m + 1.707615 * s),
+    ])
+}
+
+pub fn contrast_color(color: Rgb<u8>, background: Rgb<u8>, min_contrast: f32) -> Rgb<u8> {
+    // colors which don't stand out enough from the background are lightened or darkened in
+    // oklab, only as much as needed, so their hue is kept; their chroma fades on the way, so
+    // black or white can always be reached
+    if contrast_ratio(color, background) >= min_contrast {
+        return color;
+    }
+    let [lightness, a, b] = srgb_to_oklab(color);
+    let target = if ink_color(background) == Rgb([255, 255, 255]) {
+        1.
+    } else {
+        0.
+    };
+    let nudge = |t: f32| {
+        oklab_to_srgb([
+            lightness + t * (target - lightness),
+            a * (1. - t),
+            b * (1. - t),
+        ])
+    };
+    let (mut low, mut high) = (0., 1.);
+    for _ in 0..16 {
+        let t = (low + high) / 2.;
+        if contrast_ratio(nudge(t), background) >= min_contrast {
+            high = t;
+        } else {
+            low = t;
+        }
+    }
+    nudge(high)
+}
+
+pub fn contrast_img(img: &DynamicImage, background: Rgb<u8>, min_contrast: f32) -> DynamicImage {
+    let mut img = img.to_rgba8();
+    for pixel in img.pixels_mut() {
+        let color = Rgb([pixel[0], pixel[1], pixel[2]]);
+        let Rgb([r, g, b]) = contrast_color(color, background, min_contrast);
+        *pixel = Rgba([r, g, b, pixel[3]]);
+    }
+    DynamicImage::ImageRgba8(img)
+}
+
+pub fn anaglyph_img(left: &DynamicImage, right: &DynamicImage) -> DynamicImage {
+    // a gray red/cyan anaglyph: the red channel shows the left view and the green and blue
+    // channels show the right one, so each eye of red/cyan glasses only sees its own view
+    let left = left.to_rgba8();
+    let right = right
+        .resize_exact(left.width(), left.height(), FilterType::Triangle)
+        .to_luma8();
+    let mut img = RgbaImage::new(left.width(), left.height());
+    for ((pixel, l), r) in img.pixels_mut().zip(left.pixels()).zip(right.pixels()) {
+        let luma = (0.299 * l[0] as f32 + 0.587 * l[1] as f32 + 0.114 * l[2] as f32) as u8;
+        *pixel = Rgba([luma, r[0], r[0], l[3]]);
+    }
+    DynamicImage::ImageRgba8(img)
+}
+
+pub fn color_histogram(img: &DynamicImage) -> Vec<f32> {
+    // 4 levels per channel, normalized so that histograms of differently sized images compare
+    let img = img.to_rgb8();
+    let mut histogram = vec![0.; 64];
+    for Rgb([r, g, b]) in img.pixels() {
+        histogram[(r / 64) as usize * 16 + (g / 64) as usize * 4 + (b / 64) as usize] += 1.;
+    }
+    let total = (img.width() * img.height()).max(1) as f32;
+    histogram.iter().map(|count| count / total).collect()
+}
+
+pub fn scene_cuts(frames: &[DynamicImage], threshold: f32) -> Vec<usize> {
+    // the frames which start a new scene, where the histogram difference from the previous frame
+    // (0 for the same colors, 1 for no colors in common) is above the threshold
+    let histograms: Vec<Vec<f32>> = frames.iter().map(color_histogram).collect();
+    histograms
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| {
+            let difference: f32 = pair[0]
+                .iter()
+                .zip(&pair[1])
+                .map(|(a, b)| (a - b).abs())
+                .sum();
+            difference / 2. > threshold
+        })
+        .map(|(i, _)| i + 1)
+        .collect()
+}
@@ -1,56 +1,228 @@
 use colored::Colorize;
+use log::info;
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Write;
 
 use image::imageops::FilterType;
 use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage, Rgba};
 
-use crate::font::Font;
+use crate::color::{heatmap_color, hue_color};
+use crate::font::{Character, Font, WIDE_CONTINUATION};
 use crate::metrics::{
     avg_color_score, dot_score, jaccard_score, movement_toward_clear, occlusion_score, Metric,
 };
 
 use crate::image::{Image, LumaImage};
+use crate::parallel::par_map;
+use crate::random;
+use crate::width::char_width;
 
 pub type Converter = fn(&Font, &[f32]) -> char;
+// rows of characters, as produced by img_to_char_rows
+pub type CharGrid = Vec<Vec<char>>;
 pub enum ConversionAlgorithm {
     Base,
     Edge,
     EdgeAugmented,
     TwoPass,
+    PixelArt,
+    PreserveText,
 }
 
-pub fn score_convert(score_fn: Metric, font: &Font, chunk: &[f32]) -> char {
-    let max_index = font
-        .chars
+pub enum RenderMode {
+    Match,
+    Shade,
+}
+
+// the shade blocks used by shade mode, from empty to full
+pub const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+pub enum EdgeColor {
+    Source,
+    White,
+    GradientAngle,
+    GradientMagnitude,
+}
+
+#[derive(Clone, Copy)]
+pub enum TieBreak {
+    Stable,
+    Random(u64),
+}
+
+pub struct CharSelection {
+    // chooses characters from the scores of every character rather than with a converter, for
+    // selection rules which need more than the single best character
+    pub scorer: Scorer,
+    pub tie_break: TieBreak,
+    pub tie_epsilon: f32,
+    pub repetition_penalty: f32,
+    // pixels of the neighboring cells which are included when scoring each cell
+    pub chunk_overlap: usize,
+    pub grid: Grid,
+}
+
+pub trait Chunker {
+    // the top left pixel of the cell in the given column and row, which may be outside of the
+    // image; pixels of a cell past the edge of the image repeat the edge pixels
+    fn cell_origin(
+        &self,
+        col: usize,
+        row: usize,
+        cell_width: usize,
+        cell_height: usize,
+    ) -> (isize, isize);
+}
+
+pub struct SquareChunker;
+
+impl Chunker for SquareChunker {
+    fn cell_origin(
+        &self,
+        col: usize,
+        row: usize,
+        cell_width: usize,
+        cell_height: usize,
+    ) -> (isize, isize) {
+        ((col * cell_width) as isize, (row * cell_height) as isize)
+    }
+}
+
+pub struct BrickChunker;
+
+impl Chunker for BrickChunker {
+    // every other row is shifted right by half a cell, like a wall of bricks, which breaks up the
+    // columns of the grid
+    fn cell_origin(
+        &self,
+        col: usize,
+        row: usize,
+        cell_width: usize,
+        cell_height: usize,
+    ) -> (isize, isize) {
+        let offset = (row % 2) * (cell_width / 2);
+        (
+            (col * cell_width + offset) as isize,
+            (row * cell_height) as isize,
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Grid {
+    Square,
+    Brick,
+}
+
+impl Grid {
+    pub fn chunker(&self) -> &'static dyn Chunker {
+        match self {
+            Grid::Square => &SquareChunker,
+            Grid::Brick => &BrickChunker,
+        }
+    }
+
+    pub fn row_offset(&self, row: usize, cell_width: usize) -> usize {
+        // how far the cells of a row are drawn to the right of the square grid
+        let (x, _) = self.chunker().cell_origin(0, row, cell_width, 1);
+        x as usize
+    }
+}
+
+pub fn get_grid(grid: &str) -> Grid {
+    match grid {
+        "square" => Grid::Square,
+        "brick" => Grid::Brick,
+        _ => panic!("Unsupported grid {}", grid),
+    }
+}
+
+pub enum RevealOrder {
+    Typewriter,
+    Random,
+    Radial,
+}
+
+pub enum TextDirection {
+    HorizontalTb,
+    VerticalRl,
+}
+
+pub type Scorer = fn(&Font, &[f32]) -> Vec<f32>;
+
+pub fn metric_scores(score_fn: Metric, font: &Font, chunk: &[f32]) -> Vec<f32> {
+    font.chars
+        .iter()
+        .map(|c| score_fn(chunk, &c.bitmap))
+        .collect()
+}
+
+fn best_index(scores: &[f32]) -> usize {
+    scores
         .iter()
-        .map(|c| score_fn(&chunk, &c.bitmap))
         .enumerate()
-        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(Ordering::Equal))
         .unwrap()
-        .0;
-    font.chars[max_index].value
+        .0
+}
+
+pub fn score_convert(score_fn: Metric, font: &Font, chunk: &[f32]) -> char {
+    font.chars[best_index(&metric_scores(score_fn, font, chunk))].value
+}
+
+pub fn dot_scores(font: &Font, chunk: &[f32]) -> Vec<f32> {
+    metric_scores(dot_score, font, chunk)
 }
 
 pub fn dot_convert(font: &Font, chunk: &[f32]) -> char {
     score_convert(dot_score, font, chunk)
 }
 
+pub fn jaccard_scores(font: &Font, chunk: &[f32]) -> Vec<f32> {
+    metric_scores(jaccard_score, font, chunk)
+}
+
 pub fn jaccard_convert(font: &Font, chunk: &[f32]) -> char {
     score_convert(jaccard_score, font, chunk)
 }
 
+pub fn occlusion_scores(font: &Font, chunk: &[f32]) -> Vec<f32> {
+    metric_scores(occlusion_score, font, chunk)
+}
+
 pub fn occlusion_convert(font: &Font, chunk: &[f32]) -> char {
     score_convert(occlusion_score, font, chunk)
 }
 
+pub fn color_scores(font: &Font, chunk: &[f32]) -> Vec<f32> {
+    metric_scores(avg_color_score, font, chunk)
+}
+
 pub fn color_convert(font: &Font, chunk: &[f32]) -> char {
     score_convert(avg_color_score, font, chunk)
 }
 
+pub fn clear_scores(font: &Font, chunk: &[f32]) -> Vec<f32> {
+    metric_scores(movement_toward_clear, font, chunk)
+}
+
 pub fn clear_convert(font: &Font, chunk: &[f32]) -> char {
     score_convert(movement_toward_clear, font, chunk)
 }
 
+pub fn intensity_scores(font: &Font, chunk: &[f32]) -> Vec<f32> {
+    // intensities are stretched so that the brightest character is fully bright, the same way
+    // that the intensity lookup table used by intensity_convert is built
+    let intensity = chunk.iter().sum::<f32>();
+    let max_intensity = font.chars.iter().map(|c| c.intensity).fold(0., f32::max);
+    let scale = (font.width * font.height) as f32 / max_intensity.max(1.);
+    font.chars
+        .iter()
+        .map(|c| -(intensity - c.intensity * scale).abs())
+        .collect()
+}
+
 pub fn intensity_convert(font: &Font, chunk: &[f32]) -> char {
     let intensity = chunk.iter().sum::<f32>();
     let index = intensity as usize;
@@ -75,49 +247,41 @@ fn chunk_direction(chunk: &[f32], width: usize, height: usize) -> (f32, f32) {
     (-y_grad, x_grad)
 }
 
-pub fn direction_and_intensity_convert(font: &Font, chunk: &[f32]) -> char {
+pub fn direction_and_intensity_scores(font: &Font, chunk: &[f32]) -> Vec<f32> {
     let max_direction = (font.width * font.height * 4) as f32; // direction should never be bigger than this
     let (x_dir, y_dir) = chunk_direction(chunk, font.width, font.height);
     let intensity = chunk.iter().sum::<f32>();
 
-    let scores: Vec<f32> = font
-        .chars
+    font.chars
         .iter()
         .map(|c| {
             let grad = -((x_dir - c.direction.0).powi(2) + (y_dir - c.direction.1).powi(2)).sqrt();
             (max_direction - grad) / (1. + (intensity - c.intensity).powf(2.))
         })
-        .collect();
+        .collect()
+}
 
-    font.chars
-        .iter()
-        .zip(scores)
-        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-        .unwrap()
-        .0
-        .value
+pub fn direction_and_intensity_convert(font: &Font, chunk: &[f32]) -> char {
+    font.chars[best_index(&direction_and_intensity_scores(font, chunk))].value
 }
 
-pub fn direction_convert(font: &Font, chunk: &[f32]) -> char {
+pub fn direction_scores(font: &Font, chunk: &[f32]) -> Vec<f32> {
     let (x_dir, y_dir) = chunk_direction(chunk, font.width, font.height);
 
-    let scores: Vec<f32> = font
-        .chars
+    font.chars
         .iter()
         .map(|c| -((x_dir - c.direction.0).powi(2) + (y_dir - c.direction.1).powi(2)).sqrt())
-        .collect();
+        .collect()
+}
 
-    font.chars
-        .iter()
-        .zip(scores)
-        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-        .unwrap()
-        .0
-        .value
+pub fn direction_convert(font: &Font, chunk: &[f32]) -> char {
+    // the lookup table avoids scoring every char, at the cost of quantizing the direction
+    let direction = chunk_direction(chunk, font.width, font.height);
+    font.chars[font.direction_lut.get(direction)].value
 }
 
 pub fn get_converter(metric: &str) -> Converter {
-    match &metric[..] {
+    match metric {
         "dot" => dot_convert,
         "jaccard" => jaccard_convert,
         "occlusion" => occlusion_convert,
@@ -130,17 +294,81 @@ pub fn get_converter(metric: &str) -> Converter {
     }
 }
 
+pub fn get_scorer(metric: &str) -> Scorer {
+    match metric {
+        "dot" => dot_scores,
+        "jaccard" => jaccard_scores,
+        "occlusion" => occlusion_scores,
+        "color" => color_scores,
+        "clear" => clear_scores,
+        "fast" | "intensity" => intensity_scores,
+        "grad" | "direction-and-intensity" => direction_and_intensity_scores,
+        "direction" => direction_scores,
+        _ => panic!("Unsupported metric {}", metric),
+    }
+}
+
 pub fn get_conversion_algorithm(algorithm: &str) -> ConversionAlgorithm {
-    match &algorithm[..] {
+    match algorithm {
         "base" => ConversionAlgorithm::Base,
         "edge" => ConversionAlgorithm::Edge,
         "edge-augmented" => ConversionAlgorithm::EdgeAugmented,
         "two-pass" => ConversionAlgorithm::TwoPass,
+        "pixel-art" => ConversionAlgorithm::PixelArt,
+        "preserve-text" => ConversionAlgorithm::PreserveText,
         _ => panic!("Unsupported conversion algorithm {}", algorithm),
     }
 }
 
-fn pixels_to_chunks(
+pub fn get_render_mode(mode: &str) -> RenderMode {
+    match mode {
+        "match" => RenderMode::Match,
+        "shade" => RenderMode::Shade,
+        _ => panic!("Unsupported mode {}", mode),
+    }
+}
+
+pub fn get_edge_color(edge_color: &str) -> EdgeColor {
+    match edge_color {
+        "source" => EdgeColor::Source,
+        "white" => EdgeColor::White,
+        "gradient-angle" => EdgeColor::GradientAngle,
+        "gradient-magnitude" => EdgeColor::GradientMagnitude,
+        _ => panic!("Unsupported edge color {}", edge_color),
+    }
+}
+
+pub fn get_tie_break(tie_break: &str, seed: u64) -> TieBreak {
+    // plain random uses the seed of the run
+    match tie_break.split_once(':') {
+        None if tie_break == "stable" => TieBreak::Stable,
+        None if tie_break == "random" => TieBreak::Random(seed),
+        Some(("random", seed)) => TieBreak::Random(
+            seed.parse()
+                .unwrap_or_else(|_| panic!("Unsupported tie break seed {}", seed)),
+        ),
+        _ => panic!("Unsupported tie break {}", tie_break),
+    }
+}
+
+pub fn get_reveal_order(order: &str) -> RevealOrder {
+    match order {
+        "typewriter" => RevealOrder::Typewriter,
+        "random" => RevealOrder::Random,
+        "radial" => RevealOrder::Radial,
+        _ => panic!("Unsupported reveal order {}", order),
+    }
+}
+
+pub fn get_text_direction(direction: &str) -> TextDirection {
+    match direction {
+        "horizontal-tb" => TextDirection::HorizontalTb,
+        "vertical-rl" => TextDirection::VerticalRl,
+        _ => panic!("Unsupported text direction {}", direction),
+    }
+}
+
+pub fn pixels_to_chunks(
     pixels: &[f32],
     width: usize,
     height: usize,
@@ -162,11 +390,11 @@ fn pixels_to_chunks(
             .collect();
 
         for _ in 0..chunk_height {
-            for x in 0..horizontal_chunks {
+            for chunk in chunk_row.iter_mut() {
                 let start = y_offset + x_offset;
                 let end = start + chunk_width;
                 let chunk_pixel_row = &pixels[start..end];
-                chunk_row[x].extend(chunk_pixel_row);
+                chunk.extend(chunk_pixel_row);
                 x_offset += chunk_width;
             }
             y_offset += width;
@@ -179,6 +407,100 @@ fn pixels_to_chunks(
     chunks
 }
 
+#[allow(clippy::too_many_arguments)]
+fn pixels_to_windows(
+    pixels: &[f32],
+    width: usize,
+    height: usize,
+    chunk_width: usize,
+    chunk_height: usize,
+    margin: usize,
+    chunker: &dyn Chunker,
+) -> Vec<Vec<f32>> {
+    // like pixels_to_chunks, but for any chunker, and each chunk is extended by a margin on every
+    // side, so windows overlap their neighbors; pixels past the edge of the image repeat the edge
+    // pixels
+    let (window_width, window_height) = (chunk_width + 2 * margin, chunk_height + 2 * margin);
+    let (horizontal_chunks, vertical_chunks) = (width / chunk_width, height / chunk_height);
+    let clamp = |v: isize, max: usize| v.clamp(0, max as isize - 1) as usize;
+    let mut windows = Vec::with_capacity(horizontal_chunks * vertical_chunks);
+    for cy in 0..vertical_chunks {
+        for cx in 0..horizontal_chunks {
+            let (x0, y0) = chunker.cell_origin(cx, cy, chunk_width, chunk_height);
+            let (x0, y0) = (x0 - margin as isize, y0 - margin as isize);
+            let mut window = Vec::with_capacity(window_width * window_height);
+            for wy in 0..window_height {
+                let y = clamp(y0 + wy as isize, height);
+                for wx in 0..window_width {
+                    window.push(pixels[y * width + clamp(x0 + wx as isize, width)]);
+                }
+            }
+            windows.push(window);
+        }
+    }
+    windows
+}
+
+fn window_to_chunk(
+    window: &[f32],
+    chunk_width: usize,
+    chunk_height: usize,
+    margin: usize,
+) -> Vec<f32> {
+    // shrinks a window to the size of a chunk, so it can be scored against the glyphs; pixels
+    // in the margin count for less the further they are from the cell
+    let (window_width, window_height) = (chunk_width + 2 * margin, chunk_height + 2 * margin);
+    let weight = |v: usize, size: usize| {
+        let distance = margin
+            .saturating_sub(v)
+            .max((v + 1).saturating_sub(margin + size));
+        1. - distance as f32 / (margin + 1) as f32
+    };
+    let mut totals = vec![0.; chunk_width * chunk_height];
+    let mut weights = vec![0.; chunk_width * chunk_height];
+    for wy in 0..window_height {
+        let y = wy * chunk_height / window_height;
+        for wx in 0..window_width {
+            let x = wx * chunk_width / window_width;
+            let w = weight(wx, chunk_width) * weight(wy, chunk_height);
+            totals[y * chunk_width + x] += w * window[wy * window_width + wx];
+            weights[y * chunk_width + x] += w;
+        }
+    }
+    totals.iter().zip(weights).map(|(t, w)| t / w).collect()
+}
+
+fn pixels_to_overlapping_chunks(
+    pixels: &[f32],
+    width: usize,
+    height: usize,
+    chunk_width: usize,
+    chunk_height: usize,
+    overlap: usize,
+    grid: Grid,
+) -> Vec<Vec<f32>> {
+    let chunker = grid.chunker();
+    if overlap == 0 {
+        // the square grid has a faster path which doesn't need to check the edges
+        return match grid {
+            Grid::Square => pixels_to_chunks(pixels, width, height, chunk_width, chunk_height),
+            _ => pixels_to_windows(pixels, width, height, chunk_width, chunk_height, 0, chunker),
+        };
+    }
+    pixels_to_windows(
+        pixels,
+        width,
+        height,
+        chunk_width,
+        chunk_height,
+        overlap,
+        chunker,
+    )
+    .iter()
+    .map(|window| window_to_chunk(window, chunk_width, chunk_height, overlap))
+    .collect()
+}
+
 pub fn pixels_to_chars(
     pixels: &[f32],
     width: usize,
@@ -187,185 +509,1398 @@ pub fn pixels_to_chars(
     convert: Converter,
 ) -> Vec<char> {
     let chunks = pixels_to_chunks(pixels, width, height, font.width, font.height);
-    chunks.iter().map(|chunk| convert(&font, chunk)).collect()
+    let mut chars: Vec<char> = chunks.iter().map(|chunk| convert(font, chunk)).collect();
+    if let Some(wide_font) = &font.wide {
+        merge_wide_chars(
+            &mut chars,
+            &chunks,
+            width / font.width,
+            font,
+            wide_font,
+            convert,
+        );
+    }
+    chars
+}
+
+fn select_index(scores: &[f32], cell: usize, selection: &CharSelection) -> usize {
+    let best = best_index(scores);
+    match selection.tie_break {
+        TieBreak::Stable => best,
+        TieBreak::Random(seed) => {
+            // epsilon is relative to the spread of the scores, since metrics score on very
+            // different scales; the choice only depends on the seed and the cell, so output is
+            // reproducible and unchanged cells of animations don't flicker
+            let worst = scores.iter().cloned().fold(f32::INFINITY, f32::min);
+            let threshold = scores[best] - selection.tie_epsilon * (scores[best] - worst);
+            let candidates: Vec<usize> = (0..scores.len())
+                .filter(|&i| scores[i] >= threshold)
+                .collect();
+            candidates[random::hash_below(seed, cell as u64, candidates.len())]
+        }
+    }
+}
+
+pub fn pixels_to_chars_with_selection(
+    pixels: &[f32],
+    width: usize,
+    height: usize,
+    font: &Font,
+    scorer: Scorer,
+    selection: &CharSelection,
+) -> Vec<char> {
+    let chunks = pixels_to_overlapping_chunks(
+        pixels,
+        width,
+        height,
+        font.width,
+        font.height,
+        selection.chunk_overlap,
+        selection.grid,
+    );
+    let n_cols = width / font.width;
+    let mut chars: Vec<char> = Vec::with_capacity(chunks.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut scores = scorer(font, chunk);
+        if selection.repetition_penalty > 0. {
+            // penalties are relative to the spread of the scores, so only characters which score
+            // close to the best are affected
+            let best = scores[best_index(&scores)];
+            let worst = scores.iter().cloned().fold(f32::INFINITY, f32::min);
+            let penalty = selection.repetition_penalty * (best - worst);
+            let left = if i % n_cols > 0 {
+                chars.get(i - 1)
+            } else {
+                None
+            };
+            let top = if i >= n_cols {
+                chars.get(i - n_cols)
+            } else {
+                None
+            };
+            for (score, c) in scores.iter_mut().zip(&font.chars) {
+                for &neighbor in left.iter().chain(top.iter()) {
+                    if *neighbor == c.value {
+                        *score -= penalty;
+                    }
+                }
+            }
+        }
+        chars.push(font.chars[select_index(&scores, i, selection)].value);
+    }
+    if let Some(wide_font) = &font.wide {
+        let convert =
+            |font: &Font, chunk: &[f32]| font.chars[best_index(&scorer(font, chunk))].value;
+        merge_wide_chars(
+            &mut chars,
+            &chunks,
+            width / font.width,
+            font,
+            wide_font,
+            convert,
+        );
+    }
+    chars
+}
+
+fn chunk_error(chunk: &[f32], bitmap: &[f32]) -> f32 {
+    chunk.iter().zip(bitmap).map(|(x, y)| (x - y).abs()).sum()
+}
+
+fn merge_wide_chars<F: Fn(&Font, &[f32]) -> char>(
+    chars: &mut [char],
+    chunks: &[Vec<f32>],
+    n_cols: usize,
+    font: &Font,
+    wide_font: &Font,
+    convert: F,
+) {
+    // replaces pairs of cells with a double width character wherever it reproduces the pair more
+    // closely than the two single width characters do; scores from different metrics aren't
+    // comparable, so the pixel error of each option is used to decide
+    for row_start in (0..chars.len()).step_by(n_cols) {
+        let mut x = 0;
+        while x + 1 < n_cols {
+            let (i, j) = (row_start + x, row_start + x + 1);
+            let pair: Vec<f32> = chunks[i]
+                .chunks(font.width)
+                .zip(chunks[j].chunks(font.width))
+                .flat_map(|(left, right)| left.iter().chain(right))
+                .cloned()
+                .collect();
+            let wide = convert(wide_font, &pair);
+            let wide_error = chunk_error(&pair, &wide_font.char_map[&wide].bitmap);
+            let narrow_error = chunk_error(&chunks[i], &font.char_map[&chars[i]].bitmap)
+                + chunk_error(&chunks[j], &font.char_map[&chars[j]].bitmap);
+            if wide_error < narrow_error {
+                chars[i] = wide;
+                chars[j] = WIDE_CONTINUATION;
+                x += 2;
+            } else {
+                x += 1;
+            }
+        }
+    }
+}
+
+pub fn suggest_width(img: &LumaImage<f32>, font: &Font) -> usize {
+    // the smallest significant features are estimated from the gaps between edges along rows and
+    // columns, ignoring the smallest quarter as noise; the suggested width makes them span at least
+    // two cells, kept between a minimum width and the image width in characters
+    let (width, height) = img.get_dimensions();
+    let native_width = width.div_ceil(font.width);
+    let scale = (512. / width.max(height) as f32).min(1.);
+    let (small_width, small_height) = (
+        ((width as f32 * scale).round() as usize).max(1),
+        ((height as f32 * scale).round() as usize).max(1),
+    );
+    let mut small = img.resize(small_width, small_height);
+    small.blur(1.0, 2);
+    let edges: Vec<bool> = small
+        .detect_edges()
+        .pixels()
+        .iter()
+        .map(|e| e.abs() > 0.1)
+        .collect();
+
+    let mut gaps = Vec::new();
+    let mut add_gaps = |line: &mut dyn Iterator<Item = bool>| {
+        let mut last_edge = None;
+        for (i, edge) in line.enumerate() {
+            if edge {
+                if let Some(last_edge) = last_edge {
+                    // the two sided response of the edge detector is a single edge
+                    if i - last_edge > 3 {
+                        gaps.push(i - last_edge);
+                    }
+                }
+                last_edge = Some(i);
+            }
+        }
+    };
+    for y in 0..small_height {
+        add_gaps(&mut (0..small_width).map(|x| edges[y * small_width + x]));
+    }
+    for x in 0..small_width {
+        add_gaps(&mut (0..small_height).map(|y| edges[y * small_width + x]));
+    }
+    gaps.sort();
+
+    let feature_size = gaps.get(gaps.len() / 4).cloned();
+    let suggested = feature_size.map_or(0, |size| (2 * small_width).div_ceil(size));
+    let width = suggested.clamp(MIN_SUGGESTED_WIDTH, native_width.max(MIN_SUGGESTED_WIDTH));
+    info!(
+        "auto width\t{} (smallest features {:?} of {} pixels, suggested {}, image width {})",
+        width, feature_size, small_width, suggested, native_width
+    );
+    width
 }
 
+const MIN_SUGGESTED_WIDTH: usize = 24;
+
 fn round_up_to_multiple(x: i32, m: i32) -> i32 {
     x + (-x % m)
 }
 
-pub fn img_to_char_rows(
+pub fn output_size(
     font: &Font,
-    img: &LumaImage<f32>,
-    convert: Converter,
+    width: usize,
+    height: usize,
     out_width: Option<usize>,
-    brightness_offset: f32,
-    algorithm: &ConversionAlgorithm,
-) -> Vec<Vec<char>> {
-    let (width, height) = img.get_dimensions();
-
+) -> (usize, usize) {
+    // the number of columns and rows an image converts to; images smaller than a cell, or too
+    // flat for a whole row, still get at least one
+    if width == 0 || height == 0 {
+        panic!("Unsupported image size {}x{}", width, height);
+    }
     let out_width = if let Some(out_width) = out_width {
+        if out_width == 0 {
+            panic!("The output must be at least one column wide");
+        }
         out_width
     } else {
-        round_up_to_multiple(width as i32, font.width as i32) as usize / font.width
+        (round_up_to_multiple(width as i32, font.width as i32) as usize / font.width).max(1)
     };
 
     let out_height = (height as f64
         * (out_width as f64 / width as f64)
         * (font.width as f64 / font.height as f64))
-        .round() as usize;
+        .round()
+        .max(1.) as usize;
+    (out_width, out_height)
+}
 
-    let (out_img_width, out_img_height) = (out_width * font.width, out_height * font.height);
-    let resized_image = img.resize(out_img_width, out_img_height);
+pub fn shade_char_rows(
+    font: &Font,
+    img: &LumaImage<f32>,
+    out_width: Option<usize>,
+    brightness_offset: f32,
+) -> CharGrid {
+    // maps the mean brightness of each cell straight to a shade block, without matching glyphs;
+    // the font only decides the number of rows
+    let (width, height) = img.get_dimensions();
+    let (out_width, out_height) = output_size(font, width, height, out_width);
+    img.resize_area(out_width, out_height)
+        .grid()
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|&v| {
+                    let v = (v - brightness_offset).clamp(0., 1.);
+                    SHADES[(v * (SHADES.len() - 1) as f32).round() as usize]
+                })
+                .collect()
+        })
+        .collect()
+}
 
-    let chars: Vec<char> = match algorithm {
-        ConversionAlgorithm::Base => {
-            let pixels: Vec<f32> = resized_image
-                .pixels()
-                .iter()
-                .map(|y| y - brightness_offset)
-                .collect();
+// how much the pixels of a cell must vary, and how often its rows must cross between dark and
+// light, for the cell to be treated as text
+const TEXT_MIN_DEVIATION: f32 = 0.2;
+const TEXT_MIN_CROSSINGS_PER_ROW: f32 = 1.5;
 
-            pixels_to_chars(&pixels, out_img_width, out_img_height, &font, convert)
-        }
-        ConversionAlgorithm::Edge => {
-            let mut edge_img = img.clone();
-            edge_img.blur(1.0, 2);
-            edge_img = edge_img.detect_edges();
-            edge_img = edge_img.resize(out_img_width, out_img_height);
+fn text_like_cells(pixels: &[f32], width: usize, height: usize, font: &Font) -> Vec<bool> {
+    // text is made of thin, high contrast strokes, so its cells vary a lot and cross their own
+    // mean several times per row; soft texture varies less, and a single large edge crosses once
+    pixels_to_chunks(pixels, width, height, font.width, font.height)
+        .iter()
+        .map(|chunk| {
+            let mean = chunk.iter().sum::<f32>() / chunk.len() as f32;
+            let variance =
+                chunk.iter().map(|p| (p - mean) * (p - mean)).sum::<f32>() / chunk.len() as f32;
+            let crossings = chunk
+                .chunks(font.width)
+                .map(|row| {
+                    row.windows(2)
+                        .filter(|pair| (pair[0] < mean) != (pair[1] < mean))
+                        .count()
+                })
+                .sum::<usize>();
+            variance.sqrt() >= TEXT_MIN_DEVIATION
+                && crossings as f32 / font.height as f32 >= TEXT_MIN_CROSSINGS_PER_ROW
+        })
+        .collect()
+}
 
-            let pixels: Vec<f32> = edge_img
-                .pixels()
-                .iter()
-                .map(|y| y - brightness_offset)
-                .collect();
+struct ConversionLayers {
+    // pixels converted with the chosen metric and edge pixels converted by direction; two-pass
+    // conversion uses both, the other algorithms only one of them
+    pixels: Option<Vec<f32>>,
+    edge_pixels: Option<Vec<f32>>,
+    // the cells which keep their edge characters, when that isn't decided by the edges alone
+    edge_cells: Option<Vec<bool>>,
+    out_width: usize,
+    out_height: usize,
+}
 
-            pixels_to_chars(
-                &pixels,
-                out_img_width,
-                out_img_height,
-                &font,
-                direction_convert,
-            )
+fn conversion_layers(
+    font: &Font,
+    img: &LumaImage<f32>,
+    out_width: Option<usize>,
+    brightness_offset: f32,
+    algorithm: &ConversionAlgorithm,
+) -> ConversionLayers {
+    let (width, height) = img.get_dimensions();
+    let (out_width, out_height) = output_size(font, width, height, out_width);
+
+    let (out_img_width, out_img_height) = (out_width * font.width, out_height * font.height);
+    // pixel art would be blurred by bilinear resizing, and its hard edges don't need detecting
+    let resized_image = match algorithm {
+        ConversionAlgorithm::PixelArt => img.resize_nearest(out_img_width, out_img_height),
+        _ => img.resize(out_img_width, out_img_height),
+    };
+    let edge_image = || {
+        let mut edge_img = img.clone();
+        edge_img.blur(1.0, 2);
+        edge_img = edge_img.detect_edges();
+        edge_img.resize(out_img_width, out_img_height)
+    };
+    let offset = |img: &LumaImage<f32>| -> Vec<f32> {
+        img.pixels().iter().map(|y| y - brightness_offset).collect()
+    };
+
+    let (pixels, edge_pixels) = match algorithm {
+        ConversionAlgorithm::Base | ConversionAlgorithm::PixelArt => {
+            (Some(offset(&resized_image)), None)
         }
+        ConversionAlgorithm::Edge => (None, Some(offset(&edge_image()))),
         ConversionAlgorithm::EdgeAugmented => {
-            let mut edge_img = img.clone();
-            edge_img.blur(1.0, 2);
-            edge_img = edge_img.detect_edges();
-            edge_img = edge_img.resize(out_img_width, out_img_height);
-
             let pixels: Vec<f32> = resized_image
                 .pixels()
                 .iter()
-                .zip(edge_img.pixels())
+                .zip(edge_image().pixels())
                 .map(|(a, b)| a / 4. + b - brightness_offset)
                 .collect();
-
-            pixels_to_chars(&pixels, out_img_width, out_img_height, &font, convert)
+            (Some(pixels), None)
         }
-        ConversionAlgorithm::TwoPass => {
-            let luma_pixels: Vec<f32> = resized_image
-                .pixels()
-                .iter()
-                .map(|y| y - brightness_offset)
-                .collect();
-
-            let mut edge_img = img.clone();
-            edge_img.blur(1.0, 2);
-            edge_img = edge_img.detect_edges();
-            edge_img = edge_img.resize(out_img_width, out_img_height);
-            let edge_pixels: Vec<f32> = edge_img
-                .pixels()
-                .iter()
-                .map(|y| y - brightness_offset)
-                .collect();
-
-            let luma_chars =
-                pixels_to_chars(&luma_pixels, out_img_width, out_img_height, &font, convert);
-
-            let edge_chars = pixels_to_chars(
-                &edge_pixels,
-                out_img_width,
-                out_img_height,
-                &font,
-                direction_convert,
-            );
-
-            luma_chars
-                .iter()
-                .zip(edge_chars)
-                .map(|(&luma, edge)| if edge == ' ' { luma } else { edge })
-                .collect()
+        ConversionAlgorithm::TwoPass | ConversionAlgorithm::PreserveText => {
+            (Some(offset(&resized_image)), Some(offset(&edge_image())))
         }
     };
+    let edge_cells = match algorithm {
+        ConversionAlgorithm::PreserveText => Some(text_like_cells(
+            resized_image.pixels(),
+            out_img_width,
+            out_img_height,
+            font,
+        )),
+        _ => None,
+    };
+
+    ConversionLayers {
+        pixels,
+        edge_pixels,
+        edge_cells,
+        out_width,
+        out_height,
+    }
+}
+
+fn combine_layers<T: Copy>(
+    chars: Option<Vec<T>>,
+    edge_chars: Option<Vec<T>>,
+    use_edge: &[bool],
+) -> Vec<T> {
+    match (chars, edge_chars) {
+        (Some(chars), Some(edge_chars)) => chars
+            .iter()
+            .zip(edge_chars)
+            .zip(use_edge)
+            .map(|((&c, edge), &use_edge)| if use_edge { edge } else { c })
+            .collect(),
+        (Some(chars), None) | (None, Some(chars)) => chars,
+        (None, None) => Vec::new(),
+    }
+}
 
+fn split_rows<T: Clone>(cells: &[T], out_width: usize, out_height: usize) -> Vec<Vec<T>> {
     (0..out_height * out_width)
         .step_by(out_width)
-        .map(|i| chars[i..i + out_width].to_vec())
+        .map(|i| cells[i..i + out_width].to_vec())
         .collect()
 }
 
-pub fn char_rows_to_string(char_rows: &[Vec<char>]) -> String {
-    char_rows
-        .iter()
-        .map(|row| row.iter().collect())
-        .collect::<Vec<String>>()
-        .join("\n")
-}
-
-pub fn char_rows_to_terminal_color_string(char_rows: &[Vec<char>], img: &DynamicImage) -> String {
+fn layers_to_chars(
+    layers: &ConversionLayers,
+    font: &Font,
+    convert: Converter,
+    selection: Option<&CharSelection>,
+) -> (Option<Vec<char>>, Option<Vec<char>>, Vec<bool>) {
+    let (width, height) = (
+        layers.out_width * font.width,
+        layers.out_height * font.height,
+    );
+    let chars = layers.pixels.as_ref().map(|pixels| match selection {
+        Some(selection) => {
+            pixels_to_chars_with_selection(pixels, width, height, font, selection.scorer, selection)
+        }
+        None => pixels_to_chars(pixels, width, height, font, convert),
+    });
+    let edge_chars = layers.edge_pixels.as_ref().map(|pixels| match selection {
+        Some(selection) => {
+            pixels_to_chars_with_selection(pixels, width, height, font, direction_scores, selection)
+        }
+        None => pixels_to_chars(pixels, width, height, font, direction_convert),
+    });
+    // two-pass conversion keeps the edge characters wherever an edge was found, and text
+    // preserving conversion wherever text was found
+    let use_edge = match &layers.edge_cells {
+        Some(edge_cells) => edge_cells.clone(),
+        None => edge_chars.as_ref().map_or_else(Vec::new, |edge_chars| {
+            edge_chars.iter().map(|&c| c != ' ').collect()
+        }),
+    };
+    (chars, edge_chars, use_edge)
+}
+
+pub fn img_to_char_rows(
+    font: &Font,
+    img: &LumaImage<f32>,
+    convert: Converter,
+    out_width: Option<usize>,
+    brightness_offset: f32,
+    algorithm: &ConversionAlgorithm,
+    selection: Option<&CharSelection>,
+) -> Vec<Vec<char>> {
+    let layers = conversion_layers(font, img, out_width, brightness_offset, algorithm);
+    let (chars, edge_chars, use_edge) = layers_to_chars(&layers, font, convert, selection);
+    let chars = combine_layers(chars, edge_chars, &use_edge);
+    split_rows(&chars, layers.out_width, layers.out_height)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CharConfidence {
+    // the score of the chosen character and how far ahead of the runner-up it scored
+    pub score: f32,
+    pub margin: f32,
+}
+
+fn scores_confidence(scores: &[f32], font: &Font, value: char) -> CharConfidence {
+    let chosen = font.chars.iter().position(|c| c.value == value);
+    let score = chosen.map_or_else(|| scores[best_index(scores)], |i| scores[i]);
+    let runner_up = scores
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| Some(i) != chosen)
+        .map(|(_, &score)| score)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let margin = if runner_up.is_finite() && chosen.is_some() {
+        score - runner_up
+    } else {
+        0.
+    };
+    CharConfidence { score, margin }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn chars_to_confidence(
+    chars: &[char],
+    pixels: &[f32],
+    width: usize,
+    height: usize,
+    font: &Font,
+    scorer: Scorer,
+    chunk_overlap: usize,
+    grid: Grid,
+) -> Vec<CharConfidence> {
+    let chunks = pixels_to_overlapping_chunks(
+        pixels,
+        width,
+        height,
+        font.width,
+        font.height,
+        chunk_overlap,
+        grid,
+    );
+    let mut confidence = Vec::with_capacity(chars.len());
+    for (i, &c) in chars.iter().enumerate() {
+        if c == WIDE_CONTINUATION {
+            // the continuation shares the confidence of the double width character before it
+            confidence.push(confidence[i - 1]);
+        } else if chars.get(i + 1) == Some(&WIDE_CONTINUATION) {
+            let wide_font = font.wide.as_ref().unwrap();
+            let pair: Vec<f32> = chunks[i]
+                .chunks(font.width)
+                .zip(chunks[i + 1].chunks(font.width))
+                .flat_map(|(left, right)| left.iter().chain(right))
+                .cloned()
+                .collect();
+            confidence.push(scores_confidence(&scorer(wide_font, &pair), wide_font, c));
+        } else {
+            confidence.push(scores_confidence(&scorer(font, &chunks[i]), font, c));
+        }
+    }
+    confidence
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn img_to_char_rows_with_confidence(
+    font: &Font,
+    img: &LumaImage<f32>,
+    convert: Converter,
+    scorer: Scorer,
+    out_width: Option<usize>,
+    brightness_offset: f32,
+    algorithm: &ConversionAlgorithm,
+    selection: Option<&CharSelection>,
+) -> (Vec<Vec<char>>, Vec<Vec<CharConfidence>>) {
+    // confidence is measured with the scorer matching the converter that chose each character
+    let layers = conversion_layers(font, img, out_width, brightness_offset, algorithm);
+    let (chars, edge_chars, use_edge) = layers_to_chars(&layers, font, convert, selection);
+    let (out_img_width, out_img_height) = (
+        layers.out_width * font.width,
+        layers.out_height * font.height,
+    );
+    let chunk_overlap = selection.map_or(0, |selection| selection.chunk_overlap);
+    let grid = selection.map_or(Grid::Square, |selection| selection.grid);
+    let confidence = layers
+        .pixels
+        .as_ref()
+        .zip(chars.as_ref())
+        .map(|(pixels, chars)| {
+            chars_to_confidence(
+                chars,
+                pixels,
+                out_img_width,
+                out_img_height,
+                font,
+                scorer,
+                chunk_overlap,
+                grid,
+            )
+        });
+    let edge_confidence =
+        layers
+            .edge_pixels
+            .as_ref()
+            .zip(edge_chars.as_ref())
+            .map(|(pixels, chars)| {
+                chars_to_confidence(
+                    chars,
+                    pixels,
+                    out_img_width,
+                    out_img_height,
+                    font,
+                    direction_scores,
+                    chunk_overlap,
+                    grid,
+                )
+            });
+
+    let chars = combine_layers(chars, edge_chars, &use_edge);
+    let confidence = combine_layers(confidence, edge_confidence, &use_edge);
+    (
+        split_rows(&chars, layers.out_width, layers.out_height),
+        split_rows(&confidence, layers.out_width, layers.out_height),
+    )
+}
+
+pub fn img_to_intensity_rows(
+    font: &Font,
+    img: &LumaImage<f32>,
+    out_width: Option<usize>,
+    brightness_offset: f32,
+    algorithm: &ConversionAlgorithm,
+) -> Vec<Vec<f32>> {
+    // the mean brightness of each cell, which intensity_to_char can map to the characters of any
+    // alphabet without converting the image again
+    let layers = conversion_layers(font, img, out_width, brightness_offset, algorithm);
+    let pixels = layers
+        .pixels
+        .as_ref()
+        .or(layers.edge_pixels.as_ref())
+        .unwrap();
+    let (width, height) = (
+        layers.out_width * font.width,
+        layers.out_height * font.height,
+    );
+    let intensities: Vec<f32> = pixels_to_chunks(pixels, width, height, font.width, font.height)
+        .iter()
+        .map(|chunk| (chunk.iter().sum::<f32>() / chunk.len() as f32).clamp(0., 1.))
+        .collect();
+    split_rows(&intensities, layers.out_width, layers.out_height)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn match_histogram(
+    char_rows: &[Vec<char>],
+    font: &Font,
+    img: &LumaImage<f32>,
+    scorer: Scorer,
+    out_width: Option<usize>,
+    brightness_offset: f32,
+    algorithm: &ConversionAlgorithm,
+    tolerance: f32,
+) -> Vec<Vec<char>> {
+    // reassigns characters so that the brightness of the output is distributed like that of the
+    // image: the cells are ranked by the coverage of their glyphs, each rank is given the image
+    // brightness of the same rank as a target, and each cell takes the char closest to its target
+    // among those scoring within the tolerance (a fraction of the cell's range of scores) of
+    // the best; double width characters are left as they are. the candidates are scored against
+    // the same pixels as the conversion, like img_to_intensity_rows
+    let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
+    let layers = conversion_layers(font, img, out_width, brightness_offset, algorithm);
+    if (layers.out_width, layers.out_height) != (n_cols, n_rows) {
+        panic!("The characters don't have the size of the conversion they are matched to");
+    }
+    let (width, height) = (n_cols * font.width, n_rows * font.height);
+    let pixels: Vec<f32> = layers
+        .pixels
+        .as_ref()
+        .or(layers.edge_pixels.as_ref())
+        .unwrap()
+        .iter()
+        .map(|p| p.clamp(0., 1.))
+        .collect();
+    let chunks = pixels_to_chunks(&pixels, width, height, font.width, font.height);
+    let brightness: Vec<f32> = chunks
+        .iter()
+        .map(|chunk| chunk.iter().sum::<f32>() / chunk.len() as f32)
+        .collect();
+
+    // glyph coverage is stretched so the brightest char is 1, as for intensity_chars
+    let coverage = |c: &Character| c.intensity / (c.width * c.height) as f32;
+    let max_coverage = font.chars.iter().map(coverage).fold(f32::EPSILON, f32::max);
+    let char_coverage: HashMap<char, f32> = font
+        .chars
+        .iter()
+        .map(|c| (c.value, coverage(c) / max_coverage))
+        .collect();
+
+    let chars: Vec<char> = char_rows.iter().flatten().cloned().collect();
+    let output: Vec<f32> = chars
+        .iter()
+        .map(|c| char_coverage.get(c).cloned().unwrap_or(0.))
+        .collect();
+    let mut by_output: Vec<usize> = (0..chars.len()).collect();
+    by_output.sort_by(|&a, &b| {
+        output[a]
+            .total_cmp(&output[b])
+            .then(brightness[a].total_cmp(&brightness[b]))
+    });
+    let mut sorted_brightness = brightness.clone();
+    sorted_brightness.sort_by(f32::total_cmp);
+    let mut targets = vec![0.; chars.len()];
+    for (rank, &i) in by_output.iter().enumerate() {
+        targets[i] = sorted_brightness[rank];
+    }
+    let max_brightness = sorted_brightness
+        .last()
+        .cloned()
+        .unwrap_or(0.)
+        .max(f32::EPSILON);
+
+    let matched: Vec<char> = par_map(&chars, |i, &c| {
+        let wide = chars.get(i + 1) == Some(&WIDE_CONTINUATION);
+        if c == WIDE_CONTINUATION || wide || !char_coverage.contains_key(&c) {
+            return c;
+        }
+        let scores = scorer(font, &chunks[i]);
+        let best = scores.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let worst = scores.iter().cloned().fold(f32::INFINITY, f32::min);
+        let threshold = best - tolerance * (best - worst);
+        let target = targets[i] / max_brightness;
+        let distance = |c: &char| (char_coverage[c] - target).abs();
+        font.chars
+            .iter()
+            .zip(&scores)
+            .filter(|&(_, &score)| score >= threshold)
+            .map(|(candidate, _)| candidate.value)
+            .chain(std::iter::once(c))
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .unwrap()
+    });
+    split_rows(&matched, n_cols, n_rows)
+}
+
+pub fn intensity_to_char(font: &Font, intensity: f32) -> char {
+    // the same lookup as intensity_convert, for an intensity between 0 and 1
+    let max_index = font.intensity_chars.len() - 1;
+    let index = ((intensity * max_index as f32) as usize).min(max_index);
+    font.intensity_chars[index].value
+}
+
+fn cell_heatmap(values: &[Vec<f32>], font: &Font) -> DynamicImage {
+    let (n_cols, n_rows) = (values[0].len(), values.len());
+    let mut img = RgbImage::new(n_cols as u32, n_rows as u32);
+    for (y, row) in values.iter().enumerate() {
+        for (x, &t) in row.iter().enumerate() {
+            img.put_pixel(x as u32, y as u32, heatmap_color(t));
+        }
+    }
+    DynamicImage::ImageRgb8(img).resize_exact(
+        (n_cols * font.width) as u32,
+        (n_rows * font.height) as u32,
+        FilterType::Nearest,
+    )
+}
+
+pub fn confidence_heatmap(confidence: &[Vec<CharConfidence>], font: &Font) -> DynamicImage {
+    // margins are relative to the most confident cell, and uncertain cells are drawn hot
+    let max_margin = confidence
+        .iter()
+        .flatten()
+        .map(|c| c.margin)
+        .fold(0., f32::max);
+    let values: Vec<Vec<f32>> = confidence
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|c| {
+                    if max_margin > 0. {
+                        1. - c.margin.max(0.) / max_margin
+                    } else {
+                        0.
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    cell_heatmap(&values, font)
+}
+
+fn sobel_gradients(img: &LumaImage<f32>) -> (LumaImage<f32>, LumaImage<f32>) {
+    let mut blurred = img.clone();
+    blurred.blur(1.0, 2);
+    let gx = blurred.convolve_2d(&[vec![-1., 0., 1.], vec![-2., 0., 2.], vec![-1., 0., 1.]]);
+    let gy = blurred.convolve_2d(&[vec![-1., -2., -1.], vec![0., 0., 0.], vec![1., 2., 1.]]);
+    (gx, gy)
+}
+
+pub fn gradient_angle_img(img: &LumaImage<f32>, n_cols: usize, n_rows: usize) -> DynamicImage {
+    // colors each cell by the orientation of its strokes, from the sobel gradients summed as a
+    // structure tensor so that opposite gradients on both sides of a stroke don't cancel out;
+    // orientations repeat every 180 degrees, so they are doubled to cover the color wheel
+    let (gx, gy) = sobel_gradients(img);
+    let (width, height) = img.get_dimensions();
+    let mut tensors = vec![(0f32, 0f32, 0f32); n_cols * n_rows];
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = (gx.get_pixel(x, y), gy.get_pixel(x, y));
+            let cell = (y * n_rows / height) * n_cols + x * n_cols / width;
+            tensors[cell].0 += dx * dx;
+            tensors[cell].1 += dy * dy;
+            tensors[cell].2 += dx * dy;
+        }
+    }
+    let mut out = RgbImage::new(n_cols as u32, n_rows as u32);
+    for (i, &(xx, yy, xy)) in tensors.iter().enumerate() {
+        // strokes run perpendicular to the dominant gradient
+        let gradient_angle = 0.5 * (2. * xy).atan2(xx - yy);
+        let stroke_angle = gradient_angle + std::f32::consts::FRAC_PI_2;
+        let hue = (2. * stroke_angle).to_degrees();
+        out.put_pixel((i % n_cols) as u32, (i / n_cols) as u32, hue_color(hue));
+    }
+    DynamicImage::ImageRgb8(out)
+}
+
+pub fn gradient_magnitude_img(img: &LumaImage<f32>, n_cols: usize, n_rows: usize) -> DynamicImage {
+    // shades each cell by the strongest sobel gradient under it, so the strokes of an edge are
+    // bright where the edge is sharp and fade out where it is soft; the scale is fixed (a hard
+    // black to white edge is white) rather than normalized, so animated frames don't flicker
+    let (gx, gy) = sobel_gradients(img);
+    let (width, height) = img.get_dimensions();
+    let mut magnitudes = vec![0f32; n_cols * n_rows];
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = (gx.get_pixel(x, y), gy.get_pixel(x, y));
+            let cell = (y * n_rows / height) * n_cols + x * n_cols / width;
+            magnitudes[cell] = magnitudes[cell].max((dx * dx + dy * dy).sqrt());
+        }
+    }
+    let mut out = RgbImage::new(n_cols as u32, n_rows as u32);
+    for (i, magnitude) in magnitudes.iter().enumerate() {
+        let v = ((magnitude / 4.).min(1.) * 255.) as u8;
+        out.put_pixel((i % n_cols) as u32, (i / n_cols) as u32, Rgb([v, v, v]));
+    }
+    DynamicImage::ImageRgb8(out)
+}
+
+pub fn char_rows_error(char_rows: &[Vec<char>], font: &Font, img: &DynamicImage) -> Vec<Vec<f32>> {
+    // the mean absolute difference between the rendered characters and the source brightness in
+    // each cell, from 0 for a perfect match to 1
+    let bitmap = LumaImage::from(&char_rows_to_bitmap(char_rows, font));
+    let (width, height) = bitmap.get_dimensions();
+    let source = LumaImage::from(img).resize(width, height);
+    let (rendered, source) = (bitmap.pixels(), source.pixels());
+
+    char_rows
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            (0..row.len())
+                .map(|x| {
+                    let mut error = 0.;
+                    for i in y * font.height..(y + 1) * font.height {
+                        for j in x * font.width..(x + 1) * font.width {
+                            error += (rendered[i * width + j] - source[i * width + j]).abs();
+                        }
+                    }
+                    error / (font.width * font.height) as f32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn error_heatmap(error: &[Vec<f32>], font: &Font) -> DynamicImage {
+    cell_heatmap(error, font)
+}
+
+pub fn text_to_renderable_char_rows(text: &[Vec<char>], font: &Font) -> Vec<Vec<char>> {
+    // pads ragged lines into a rectangular grid, expands tabs, and blanks out characters that
+    // the font has no glyph for
+    let expanded: Vec<Vec<char>> = text
+        .iter()
+        .map(|row| {
+            row.iter()
+                .flat_map(|&c| if c == '\t' { vec![' '; 4] } else { vec![c] })
+                .map(|c| {
+                    if c == WIDE_CONTINUATION || font.char_map.contains_key(&c) {
+                        c
+                    } else {
+                        ' '
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let n_cols = expanded
+        .iter()
+        .map(|row| row.len())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let mut char_rows: Vec<Vec<char>> = expanded
+        .into_iter()
+        .map(|mut row| {
+            row.resize(n_cols, ' ');
+            row
+        })
+        .collect();
+    if char_rows.is_empty() {
+        char_rows.push(vec![' '; n_cols]);
+    }
+    char_rows
+}
+
+pub type CellBounds = (usize, usize, usize, usize);
+
+pub fn content_bounds(char_rows: &[Vec<char>]) -> Option<CellBounds> {
+    // returns (left, top, right, bottom) of the non-blank cells, with right and bottom exclusive;
+    // a double width glyph covers its continuation cell too, which is never content by itself
+    let mut bounds: Option<CellBounds> = None;
+    for (y, row) in char_rows.iter().enumerate() {
+        for (x, c) in row.iter().enumerate() {
+            if c.is_whitespace() || *c == WIDE_CONTINUATION {
+                continue;
+            }
+            let right = if row.get(x + 1) == Some(&WIDE_CONTINUATION) {
+                x + 2
+            } else {
+                x + 1
+            };
+            bounds = Some(match bounds {
+                Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(right), y1.max(y + 1)),
+                None => (x, y, right, y + 1),
+            });
+        }
+    }
+    bounds
+}
+
+pub fn union_bounds(a: Option<CellBounds>, b: Option<CellBounds>) -> Option<CellBounds> {
+    match (a, b) {
+        (Some((ax0, ay0, ax1, ay1)), Some((bx0, by0, bx1, by1))) => {
+            Some((ax0.min(bx0), ay0.min(by0), ax1.max(bx1), ay1.max(by1)))
+        }
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+pub fn combine_stereo_char_rows(left: &[Vec<char>], right: &[Vec<char>], font: &Font) -> CharGrid {
+    // each cell keeps the heavier character of the two views; double width characters always
+    // come from the left view, so they never lose their second cell
+    let weight = |c: char| font.char_map.get(&c).map_or(0., |c| c.intensity);
+    let is_wide = |c: char| c == WIDE_CONTINUATION || char_width(c) == Some(2);
+    left.iter()
+        .zip(right)
+        .map(|(left_row, right_row)| {
+            left_row
+                .iter()
+                .zip(right_row)
+                .map(|(&l, &r)| {
+                    if is_wide(l) || is_wide(r) || weight(l) >= weight(r) {
+                        l
+                    } else {
+                        r
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn crop_char_rows(char_rows: &[Vec<char>], bounds: CellBounds) -> Vec<Vec<char>> {
+    // a double width glyph which the crop cuts in half is blanked, since half of it can't be drawn
+    let (x0, y0, x1, y1) = bounds;
+    char_rows[y0..y1]
+        .iter()
+        .map(|row| {
+            let mut cropped = row[x0..x1].to_vec();
+            if cropped.first() == Some(&WIDE_CONTINUATION) {
+                cropped[0] = ' ';
+            }
+            if x1 > x0 && row.get(x1) == Some(&WIDE_CONTINUATION) {
+                cropped[x1 - x0 - 1] = ' ';
+            }
+            cropped
+        })
+        .collect()
+}
+
+pub fn crop_cells<T: Clone>(cells: &[Vec<T>], bounds: CellBounds) -> Vec<Vec<T>> {
+    // per-cell values, such as the confidence, cropped along with the char grid
+    let (x0, y0, x1, y1) = bounds;
+    cells[y0..y1]
+        .iter()
+        .map(|row| row[x0..x1].to_vec())
+        .collect()
+}
+
+pub fn crop_img_to_cells(
+    img: &DynamicImage,
+    bounds: CellBounds,
+    n_cols: usize,
+    n_rows: usize,
+) -> DynamicImage {
+    // keeps the color image aligned with a cropped char grid, since colors are sampled by
+    // resizing the whole image to the grid dimensions
+    let (x0, y0, x1, y1) = bounds;
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let left = x0 * width / n_cols;
+    let top = y0 * height / n_rows;
+    let right = (x1 * width / n_cols).max(left + 1);
+    let bottom = (y1 * height / n_rows).max(top + 1);
+    img.crop_imm(
+        left as u32,
+        top as u32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    )
+}
+
+pub fn fill_img_cells(
+    img: &DynamicImage,
+    bounds: CellBounds,
+    n_cols: usize,
+    n_rows: usize,
+    color: Rgb<u8>,
+) -> DynamicImage {
+    // the pixels which are sampled for the colors of the cells within bounds
+    let (x0, y0, x1, y1) = bounds;
+    if x0 >= x1 || y0 >= y1 {
+        return img.clone();
+    }
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut img = img.to_rgba8();
+    let Rgb([r, g, b]) = color;
+    for y in y0 * height / n_rows..(y1 * height / n_rows).max(y0 * height / n_rows + 1) {
+        for x in x0 * width / n_cols..(x1 * width / n_cols).max(x0 * width / n_cols + 1) {
+            img.put_pixel(x as u32, y as u32, Rgba([r, g, b, 255]));
+        }
+    }
+    DynamicImage::ImageRgba8(img)
+}
+
+pub fn sign_char_rows(
+    char_rows: &[Vec<char>],
+    signature: &str,
+    font: &Font,
+) -> (Vec<Vec<char>>, CellBounds) {
+    // writes the signature into the bottom right corner, cut off to fit the grid width; characters
+    // the font can't render become spaces, and double width ones take up two cells
+    let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
+    let mut signature_cells: Vec<char> = Vec::new();
+    for c in signature.chars() {
+        let c = if font.char_map.contains_key(&c) {
+            c
+        } else {
+            ' '
+        };
+        let cells: &[char] = if is_wide(font, c) {
+            &[c, WIDE_CONTINUATION]
+        } else {
+            &[c]
+        };
+        if signature_cells.len() + cells.len() > n_cols {
+            break;
+        }
+        signature_cells.extend(cells);
+    }
+    let signature = signature_cells;
+    let x0 = n_cols - signature.len();
+
+    let mut char_rows = char_rows.to_vec();
+    let row = &mut char_rows[n_rows - 1];
+    row[x0..].copy_from_slice(&signature);
+    // a double width character cut in half by the signature is removed
+    if x0 > 0 && x0 < n_cols && row[x0 - 1] != WIDE_CONTINUATION && is_wide(font, row[x0 - 1]) {
+        row[x0 - 1] = ' ';
+    }
+    (char_rows, (x0, n_rows - 1, n_cols, n_rows))
+}
+
+fn is_wide(font: &Font, c: char) -> bool {
+    font.char_map
+        .get(&c)
+        .is_some_and(|character| character.width > font.width)
+}
+
+pub fn trim_trailing_whitespace(s: &str) -> String {
+    s.lines()
+        .map(str::trim_end)
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+pub enum GridTransform {
+    FlipHorizontal,
+    FlipVertical,
+    Rotate180,
+}
+
+pub fn get_grid_flip(flip: &str) -> GridTransform {
+    match flip {
+        "h" => GridTransform::FlipHorizontal,
+        "v" => GridTransform::FlipVertical,
+        _ => panic!("Unsupported flip {}", flip),
+    }
+}
+
+pub fn get_grid_rotation(degrees: u32) -> GridTransform {
+    match degrees {
+        180 => GridTransform::Rotate180,
+        _ => panic!("Unsupported rotation {}", degrees),
+    }
+}
+
+const HORIZONTAL_MIRRORS: [(char, char); 7] = [
+    ('(', ')'),
+    ('[', ']'),
+    ('{', '}'),
+    ('<', '>'),
+    ('/', '\\'),
+    ('d', 'b'),
+    ('p', 'q'),
+];
+
+const VERTICAL_MIRRORS: [(char, char); 7] = [
+    ('/', '\\'),
+    ('^', 'v'),
+    ('\'', ','),
+    ('M', 'W'),
+    ('n', 'u'),
+    ('p', 'b'),
+    ('q', 'd'),
+];
+
+fn mirror_char(c: char, mirrors: &[(char, char)], font: &Font) -> char {
+    // only substitutes glyphs that the font can actually render
+    mirrors
+        .iter()
+        .find_map(|&(a, b)| {
+            if c == a {
+                Some(b)
+            } else if c == b {
+                Some(a)
+            } else {
+                None
+            }
+        })
+        .filter(|mirrored| font.char_map.contains_key(mirrored))
+        .unwrap_or(c)
+}
+
+pub fn transform_char_rows(
+    char_rows: &[Vec<char>],
+    transform: &GridTransform,
+    mirror_glyphs: bool,
+    font: &Font,
+) -> Vec<Vec<char>> {
+    let flip_rows = matches!(
+        transform,
+        GridTransform::FlipHorizontal | GridTransform::Rotate180
+    );
+    let flip_cols = matches!(
+        transform,
+        GridTransform::FlipVertical | GridTransform::Rotate180
+    );
+
+    let mut result: Vec<Vec<char>> = char_rows.to_vec();
+    if flip_cols {
+        result.reverse();
+    }
+    for row in result.iter_mut() {
+        if flip_rows {
+            row.reverse();
+            // double width characters must stay in front of their continuation cell
+            let mut i = 0;
+            while i + 1 < row.len() {
+                if row[i] == WIDE_CONTINUATION {
+                    row.swap(i, i + 1);
+                    i += 1;
+                }
+                i += 1;
+            }
+        }
+        if mirror_glyphs {
+            for c in row.iter_mut() {
+                if flip_rows {
+                    *c = mirror_char(*c, &HORIZONTAL_MIRRORS, font);
+                }
+                if flip_cols {
+                    *c = mirror_char(*c, &VERTICAL_MIRRORS, font);
+                }
+            }
+        }
+    }
+    result
+}
+
+pub fn transform_img(img: &DynamicImage, transform: &GridTransform) -> DynamicImage {
+    match transform {
+        GridTransform::FlipHorizontal => img.fliph(),
+        GridTransform::FlipVertical => img.flipv(),
+        GridTransform::Rotate180 => img.rotate180(),
+    }
+}
+
+pub fn transform_cells<T: Clone>(cells: &[Vec<T>], transform: &GridTransform) -> Vec<Vec<T>> {
+    let mut result: Vec<Vec<T>> = cells.to_vec();
+    if matches!(
+        transform,
+        GridTransform::FlipVertical | GridTransform::Rotate180
+    ) {
+        result.reverse();
+    }
+    if matches!(
+        transform,
+        GridTransform::FlipHorizontal | GridTransform::Rotate180
+    ) {
+        for row in result.iter_mut() {
+            row.reverse();
+        }
+    }
+    result
+}
+
+pub fn rotate_char_rows_ccw(char_rows: &[Vec<char>]) -> Vec<Vec<char>> {
+    // the columns are read from the right, so the continuation cell of a double width glyph comes
+    // first: the glyph moves into it, and its own cell is left blank, so that no line has a
+    // continuation cell which would shift the rest of the line when it is left out
+    let n_cols = char_rows[0].len();
+    (0..n_cols)
+        .rev()
+        .map(|x| {
+            char_rows
+                .iter()
+                .map(|row| match row[x] {
+                    WIDE_CONTINUATION if x > 0 => row[x - 1],
+                    WIDE_CONTINUATION => ' ',
+                    _ if row.get(x + 1) == Some(&WIDE_CONTINUATION) => ' ',
+                    c => c,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn char_rows_in_reading_order(
+    char_rows: &[Vec<char>],
+    img: &DynamicImage,
+    direction: &TextDirection,
+) -> (Vec<Vec<char>>, DynamicImage) {
+    // text outputs are written in reading order, so vertical-rl output stores each column of the
+    // grid as a line, starting from the rightmost column; the color image is rotated to match
+    match direction {
+        TextDirection::HorizontalTb => (char_rows.to_vec(), img.clone()),
+        TextDirection::VerticalRl => (rotate_char_rows_ccw(char_rows), img.rotate270()),
+    }
+}
+
+pub fn char_rows_to_string(char_rows: &[Vec<char>]) -> String {
+    char_rows
+        .iter()
+        .map(|row| row.iter().filter(|&&c| c != WIDE_CONTINUATION).collect())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn char_rows_to_terminal_color_cells(
+    char_rows: &[Vec<char>],
+    img: &DynamicImage,
+) -> Vec<String> {
+    // one colored string per cell, in row-major order
     let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
     let color_resized_image = img
         .resize_exact(n_cols as u32, n_rows as u32, FilterType::Nearest)
         .to_rgba32f();
 
-    let colored_strings: Vec<String> = char_rows
-        .into_iter()
+    // cells are formatted in parallel, which matters for large grids
+    let cells: Vec<(&char, &Rgba<f32>)> = char_rows
+        .iter()
         .flatten()
         .zip(color_resized_image.pixels())
-        .map(|(c, Rgba([r, g, b, a]))| {
-            let intensity = a * 255.;
-            format!(
-                "{}",
-                c.to_string().truecolor(
-                    (*r * intensity) as u8,
-                    (*g * intensity) as u8,
-                    (*b * intensity) as u8
-                )
+        .collect();
+    par_map(&cells, |_, &(c, Rgba([r, g, b, a]))| {
+        if *c == WIDE_CONTINUATION {
+            return String::new();
+        }
+        let intensity = a * 255.;
+        format!(
+            "{}",
+            c.to_string().truecolor(
+                (*r * intensity) as u8,
+                (*g * intensity) as u8,
+                (*b * intensity) as u8
             )
+        )
+    })
+}
+
+pub fn char_rows_to_cell_strings(char_rows: &[Vec<char>]) -> Vec<String> {
+    char_rows
+        .iter()
+        .flatten()
+        .map(|&c| {
+            if c == WIDE_CONTINUATION {
+                String::new()
+            } else {
+                c.to_string()
+            }
         })
-        .collect();
+        .collect()
+}
 
-    (0..n_rows * n_cols)
-        .step_by(n_cols)
-        .map(|i| colored_strings[i..i + n_cols].join(""))
-        .collect::<Vec<String>>()
-        .join("\n")
+pub fn with_terminal_background(cell_strings: &[String], background: Rgb<u8>) -> Vec<String> {
+    // every cell sets the background itself, since colored cells reset all attributes after them
+    let Rgb([r, g, b]) = background;
+    cell_strings
+        .iter()
+        .map(|s| {
+            if s.is_empty() {
+                String::new()
+            } else {
+                format!("\x1b[48;2;{};{};{}m{}\x1b[0m", r, g, b, s)
+            }
+        })
+        .collect()
 }
 
-pub fn char_rows_to_html_color_string(char_rows: &[Vec<char>], img: &DynamicImage) -> String {
+pub fn join_cell_strings(cell_strings: &[String], n_cols: usize) -> String {
+    // sized up front, since colored frames are many times larger than their characters
+    let len = cell_strings.iter().map(String::len).sum::<usize>() + cell_strings.len() / n_cols;
+    let mut s = String::with_capacity(len);
+    for (i, row) in cell_strings.chunks(n_cols).enumerate() {
+        if i > 0 {
+            s.push('\n');
+        }
+        for cell in row {
+            s.push_str(cell);
+        }
+    }
+    s
+}
+
+pub fn char_rows_to_terminal_color_string(char_rows: &[Vec<char>], img: &DynamicImage) -> String {
+    // the escape sequences are written straight into one buffer, and only when the color changes,
+    // which keeps large frames small and quick to assemble; like the colored cells, the color is
+    // left out when colors are turned off in the environment
+    if !colored::control::SHOULD_COLORIZE.should_colorize() {
+        return char_rows_to_string(char_rows);
+    }
+    let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
+    let color_resized_image = img
+        .resize_exact(n_cols as u32, n_rows as u32, FilterType::Nearest)
+        .to_rgba32f();
+
+    // about a third of the cells of a photo change color
+    let mut s = String::with_capacity(n_rows * (n_cols * 8 + 5));
+    for (y, row) in char_rows.iter().enumerate() {
+        if y > 0 {
+            s.push('\n');
+        }
+        let mut current = None;
+        for (x, &c) in row.iter().enumerate() {
+            if c == WIDE_CONTINUATION {
+                continue;
+            }
+            let Rgba([r, g, b, a]) = *color_resized_image.get_pixel(x as u32, y as u32);
+            let intensity = a * 255.;
+            let color = (
+                (r * intensity) as u8,
+                (g * intensity) as u8,
+                (b * intensity) as u8,
+            );
+            if current != Some(color) {
+                write!(s, "\x1b[38;2;{};{};{}m", color.0, color.1, color.2).unwrap();
+                current = Some(color);
+            }
+            s.push(c);
+        }
+        if current.is_some() {
+            s.push_str("\x1b[0m");
+        }
+    }
+    s
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn char_rows_to_html_color_cells(char_rows: &[Vec<char>], img: &DynamicImage) -> Vec<String> {
+    // one colored span per cell, in row-major order
     let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
     let color_resized_image = img
         .resize_exact(n_cols as u32, n_rows as u32, FilterType::Nearest)
         .to_rgba8();
 
-    let colored_strings: Vec<String> = char_rows
-        .into_iter()
+    let cells: Vec<(&char, &Rgba<u8>)> = char_rows
+        .iter()
         .flatten()
         .zip(color_resized_image.pixels())
-        .map(|(c, Rgba([r, g, b, a]))| {
-            format!(
-                "<span style=\"color: rgba({}, {}, {}, {})\">{}</span>",
-                r, g, b, a, c
-            )
-        })
         .collect();
+    par_map(&cells, |_, &(c, Rgba([r, g, b, a]))| {
+        if *c == WIDE_CONTINUATION {
+            return String::new();
+        }
+        format!(
+            "<span style=\"color: rgba({}, {}, {}, {})\">{}</span>",
+            r,
+            g,
+            b,
+            a,
+            escape_html(&c.to_string())
+        )
+    })
+}
 
-    (0..n_rows * n_cols)
-        .step_by(n_cols)
-        .map(|i| colored_strings[i..i + n_cols].join(""))
-        .collect::<Vec<String>>()
-        .join("\n")
+pub fn char_rows_to_html_color_string(char_rows: &[Vec<char>], img: &DynamicImage) -> String {
+    // like the terminal string, runs of cells with the same color share a single span
+    let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
+    let color_resized_image = img
+        .resize_exact(n_cols as u32, n_rows as u32, FilterType::Nearest)
+        .to_rgba8();
+
+    let mut s = String::with_capacity(n_rows * (n_cols * 12 + 1));
+    for (y, row) in char_rows.iter().enumerate() {
+        if y > 0 {
+            s.push('\n');
+        }
+        let mut current = None;
+        for (x, &c) in row.iter().enumerate() {
+            if c == WIDE_CONTINUATION {
+                continue;
+            }
+            let color = *color_resized_image.get_pixel(x as u32, y as u32);
+            if current != Some(color) {
+                if current.is_some() {
+                    s.push_str("</span>");
+                }
+                let Rgba([r, g, b, a]) = color;
+                write!(
+                    s,
+                    "<span style=\"color: rgba({}, {}, {}, {})\">",
+                    r, g, b, a
+                )
+                .unwrap();
+                current = Some(color);
+            }
+            s.push_str(&escape_html(&c.to_string()));
+        }
+        if current.is_some() {
+            s.push_str("</span>");
+        }
+    }
+    s
+}
+
+pub fn render_char_rows(
+    char_rows: &[Vec<char>],
+    font: &Font,
+    img: &DynamicImage,
+    color: bool,
+) -> DynamicImage {
+    if color {
+        char_rows_to_color_bitmap(char_rows, font, img)
+    } else {
+        char_rows_to_bitmap(char_rows, font)
+    }
 }
 
 pub fn char_rows_to_bitmap(char_rows: &[Vec<char>], font: &Font) -> DynamicImage {
@@ -377,10 +1912,14 @@ pub fn char_rows_to_bitmap(char_rows: &[Vec<char>], font: &Font) -> DynamicImage
         for (i, chr) in row.iter().enumerate() {
             let x_offset = i * font.width;
             let y_offset = j * font.height;
-            let bitmap = &font.char_map.get(&chr).unwrap().bitmap;
+            if *chr == WIDE_CONTINUATION {
+                continue;
+            }
+            let character = font.glyph(*chr);
+            let bitmap = &character.bitmap;
             for y in 0..font.height {
-                for x in 0..font.width {
-                    let pixel = Luma([(255. * bitmap[y * font.width + x]) as u8]);
+                for x in 0..character.width {
+                    let pixel = Luma([(255. * bitmap[y * character.width + x]) as u8]);
                     image.put_pixel((x + x_offset) as u32, (y + y_offset) as u32, pixel);
                 }
             }
@@ -394,6 +1933,15 @@ pub fn char_rows_to_color_bitmap(
     char_rows: &[Vec<char>],
     font: &Font,
     img: &DynamicImage,
+) -> DynamicImage {
+    char_rows_to_color_bitmap_with_background(char_rows, font, img, Rgb([0, 0, 0]))
+}
+
+pub fn char_rows_to_color_bitmap_with_background(
+    char_rows: &[Vec<char>],
+    font: &Font,
+    img: &DynamicImage,
+    background: Rgb<u8>,
 ) -> DynamicImage {
     let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
     let color_resized_image = img
@@ -409,15 +1957,20 @@ pub fn char_rows_to_color_bitmap(
         for (i, chr) in row.iter().enumerate() {
             let x_offset = i * font.width;
             let y_offset = j * font.height;
-            let Rgba([r, g, b, a]) = pixels[j * n_cols as usize + i];
-            let bitmap = &font.char_map.get(&chr).unwrap().bitmap;
+            let Rgba([r, g, b, a]) = pixels[j * n_cols + i];
+            if *chr == WIDE_CONTINUATION {
+                continue;
+            }
+            let character = font.glyph(*chr);
+            let bitmap = &character.bitmap;
             for y in 0..font.height {
-                for x in 0..font.width {
-                    let intensity = bitmap[y * font.width + x] * a * 255.;
+                for x in 0..character.width {
+                    let t = bitmap[y * character.width + x] * a;
+                    let blend = |fg: f32, bg: u8| (bg as f32 + t * (fg * 255. - bg as f32)) as u8;
                     let pixel = Rgb([
-                        (*r * intensity) as u8,
-                        (*g * intensity) as u8,
-                        (*b * intensity) as u8,
+                        blend(*r, background[0]),
+                        blend(*g, background[1]),
+                        blend(*b, background[2]),
                     ]);
                     image.put_pixel((x + x_offset) as u32, (y + y_offset) as u32, pixel);
                 }
@@ -427,3 +1980,85 @@ pub fn char_rows_to_color_bitmap(
 
     DynamicImage::ImageRgb8(image)
 }
+
+pub fn offset_cell_rows(
+    img: &DynamicImage,
+    cell_width: usize,
+    cell_height: usize,
+    grid: Grid,
+    background: Rgb<u8>,
+) -> DynamicImage {
+    // draws the rows of cells of a rendered grid at their offsets, widening the image by the
+    // largest offset, for grids whose rows don't line up
+    let n_rows = img.height() as usize / cell_height;
+    let max_offset = (0..n_rows.min(2))
+        .map(|row| grid.row_offset(row, cell_width))
+        .max()
+        .unwrap_or(0);
+    if max_offset == 0 {
+        return img.clone();
+    }
+    let img = img.to_rgb8();
+    let mut out = RgbImage::from_pixel(img.width() + max_offset as u32, img.height(), background);
+    for (x, y, &pixel) in img.enumerate_pixels() {
+        let offset = grid.row_offset(y as usize / cell_height, cell_width);
+        out.put_pixel(x + offset as u32, y, pixel);
+    }
+    DynamicImage::ImageRgb8(out)
+}
+
+pub fn reveal_char_rows(
+    char_rows: &[Vec<char>],
+    order: &RevealOrder,
+    n_frames: usize,
+    cell_aspect: f32,
+    seed: u64,
+) -> Vec<Vec<Vec<char>>> {
+    // each cell gets a reveal time in [0, 1), and each frame shows the cells revealed by then;
+    // cell_aspect is the height of a cell over its width, so radial reveals grow as circles
+    let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
+    let n_cells = n_cols * n_rows;
+    let mut times: Vec<f32> = match order {
+        RevealOrder::Typewriter => (0..n_cells).map(|i| i as f32 / n_cells as f32).collect(),
+        RevealOrder::Random => {
+            let mut cells: Vec<usize> = (0..n_cells).collect();
+            cells.sort_by_key(|&i| random::hash(seed, i as u64));
+            let mut times = vec![0.; n_cells];
+            for (rank, i) in cells.into_iter().enumerate() {
+                times[i] = rank as f32 / n_cells as f32;
+            }
+            times
+        }
+        RevealOrder::Radial => {
+            let (cx, cy) = ((n_cols as f32 - 1.) / 2., (n_rows as f32 - 1.) / 2.);
+            let distance = |i: usize| {
+                let (x, y) = ((i % n_cols) as f32, (i / n_cols) as f32);
+                ((x - cx).powi(2) + ((y - cy) * cell_aspect).powi(2)).sqrt()
+            };
+            let max_distance = (0..n_cells).map(distance).fold(0., f32::max) + 1.;
+            (0..n_cells).map(|i| distance(i) / max_distance).collect()
+        }
+    };
+    // double width characters appear at once with their continuation
+    for i in 1..n_cells {
+        if char_rows[i / n_cols][i % n_cols] == WIDE_CONTINUATION {
+            times[i] = times[i - 1];
+        }
+    }
+
+    (1..=n_frames)
+        .map(|frame| {
+            let t = frame as f32 / n_frames as f32;
+            char_rows
+                .iter()
+                .enumerate()
+                .map(|(y, row)| {
+                    row.iter()
+                        .enumerate()
+                        .map(|(x, &c)| if times[y * n_cols + x] < t { c } else { ' ' })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect()
+}
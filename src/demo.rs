@@ -0,0 +1,153 @@
+use std::f32::consts::PI;
+
+use image::{DynamicImage, Rgb, RgbImage};
+
+use crate::color::{gradient_color, hue_color};
+use crate::random;
+
+pub enum Demo {
+    Plasma,
+    Tunnel,
+    Fire,
+}
+
+pub fn get_demo(demo: &str) -> Demo {
+    match demo {
+        "plasma" => Demo::Plasma,
+        "tunnel" => Demo::Tunnel,
+        "fire" => Demo::Fire,
+        _ => panic!("Unsupported demo {}", demo),
+    }
+}
+
+fn plasma_frame(width: u32, height: u32, t: f32) -> RgbImage {
+    // a sum of sine waves, with the hue following the value
+    RgbImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as f32 / width as f32, y as f32 / height as f32);
+        let (cx, cy) = (x - 0.5 + (t / 3.).sin() / 2., y - 0.5 + (t / 2.).cos() / 2.);
+        let v = (x * 10. + t).sin()
+            + ((y * 10. + t) / 2.).sin()
+            + ((x * 10. + y * 10. + t) / 2.).sin()
+            + ((cx * cx + cy * cy).sqrt() * 20. + t).sin();
+        let v = (v / 4. + 1.) / 2.;
+        let Rgb([r, g, b]) = hue_color(v * 360.);
+        let shade = |c: u8| (c as f32 * (0.3 + 0.7 * v)) as u8;
+        Rgb([shade(r), shade(g), shade(b)])
+    })
+}
+
+fn tunnel_frame(width: u32, height: u32, t: f32) -> RgbImage {
+    // a checkerboard mapped to polar coordinates, moving towards the viewer
+    RgbImage::from_fn(width, height, |x, y| {
+        let dx = x as f32 - width as f32 / 2.;
+        let dy = (y as f32 - height as f32 / 2.) * 2.;
+        let distance = (dx * dx + dy * dy).sqrt().max(1.);
+        let depth = 32. * height as f32 / distance + t * 8.;
+        let angle = dy.atan2(dx) / PI * 8. + t;
+        let checker = (depth as i32 + angle.floor() as i32) % 2 == 0;
+        // fade into the distance at the center
+        let fog = (distance / (width.max(height) as f32 / 2.)).min(1.);
+        let v = if checker { 255. * fog } else { 64. * fog };
+        Rgb([(v * 0.6) as u8, (v * 0.9) as u8, v as u8])
+    })
+}
+
+struct FireState {
+    // the heat of every pixel from 0 to 1, seeded at the bottom row and cooling as it rises
+    width: usize,
+    height: usize,
+    heat: Vec<f32>,
+    step: u64,
+    seed: u64,
+}
+
+impl FireState {
+    fn new(width: usize, height: usize, seed: u64) -> FireState {
+        FireState {
+            width,
+            height,
+            heat: vec![0.; width * height],
+            step: 0,
+            seed,
+        }
+    }
+
+    fn advance(&mut self) {
+        let (width, height) = (self.width, self.height);
+        for x in 0..width {
+            let value = (self.step * width as u64 + x as u64) * 2;
+            self.heat[(height - 1) * width + x] = if random::hash_below(self.seed, value, 3) == 0 {
+                0.
+            } else {
+                1.
+            };
+        }
+        for y in 0..height - 1 {
+            for x in 0..width {
+                // each pixel takes the heat of a pixel below it, drifting sideways at random
+                let value =
+                    ((self.step * height as u64 + y as u64) * width as u64 + x as u64) * 2 + 1;
+                let drift = random::hash_below(self.seed, value, 3) as isize - 1;
+                let src_x = (x as isize + drift).clamp(0, width as isize - 1) as usize;
+                let below = self.heat[(y + 1) * width + src_x];
+                let cooling = 1.5 / height as f32 * random::hash_below(self.seed, value, 4) as f32;
+                self.heat[y * width + x] = (below - cooling).max(0.);
+            }
+        }
+        self.step += 1;
+    }
+
+    fn frame(&self) -> RgbImage {
+        let colors = [
+            Rgb([0, 0, 0]),
+            Rgb([128, 0, 0]),
+            Rgb([255, 96, 0]),
+            Rgb([255, 220, 64]),
+            Rgb([255, 255, 255]),
+        ];
+        RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            gradient_color(&colors, self.heat[y as usize * self.width + x as usize])
+        })
+    }
+}
+
+pub fn demo_frames(
+    demo: &Demo,
+    width: u32,
+    height: u32,
+    n_frames: usize,
+    fps: f64,
+    seed: u64,
+) -> Vec<DynamicImage> {
+    // every frame only depends on its time (and the ones before it and the seed, for fire), so
+    // runs with the same settings always give the same frames
+    match demo {
+        Demo::Plasma | Demo::Tunnel => (0..n_frames)
+            .map(|i| {
+                let t = (i as f64 / fps) as f32;
+                let frame = match demo {
+                    Demo::Plasma => plasma_frame(width, height, t),
+                    _ => tunnel_frame(width, height, t),
+                };
+                DynamicImage::ImageRgb8(frame)
+            })
+            .collect(),
+        Demo::Fire => {
+            let mut fire = FireState::new(width as usize, height as usize, seed);
+            // the flames rise one pixel per step, so taller frames take more steps per frame, and
+            // they are given time to reach their full height before the first frame
+            let steps_per_frame = (height as usize / 40).max(1);
+            for _ in 0..height {
+                fire.advance();
+            }
+            (0..n_frames)
+                .map(|_| {
+                    for _ in 0..steps_per_frame {
+                        fire.advance();
+                    }
+                    DynamicImage::ImageRgb8(fire.frame())
+                })
+                .collect()
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use colored::Colorize;
+
+pub struct CharRowsDiff {
+    pub char_rows: Vec<Vec<char>>,
+    pub different: Vec<Vec<bool>>,
+    pub n_different: usize,
+    pub n_cells: usize,
+}
+
+impl CharRowsDiff {
+    pub fn similarity(&self) -> f64 {
+        if self.n_cells == 0 {
+            1.
+        } else {
+            1. - self.n_different as f64 / self.n_cells as f64
+        }
+    }
+}
+
+pub fn text_to_char_rows(text: &str) -> Vec<Vec<char>> {
+    text.lines().map(|line| line.chars().collect()).collect()
+}
+
+pub fn diff_char_rows(a: &[Vec<char>], b: &[Vec<char>]) -> CharRowsDiff {
+    // grids of different sizes are compared as if the smaller one were padded with spaces
+    let n_rows = a.len().max(b.len());
+    let n_cols = a.iter().chain(b).map(|row| row.len()).max().unwrap_or(0);
+    let get = |rows: &[Vec<char>], x: usize, y: usize| {
+        rows.get(y)
+            .and_then(|row| row.get(x))
+            .cloned()
+            .unwrap_or(' ')
+    };
+
+    let mut char_rows = Vec::with_capacity(n_rows);
+    let mut different = Vec::with_capacity(n_rows);
+    let mut n_different = 0;
+    for y in 0..n_rows {
+        let mut row = Vec::with_capacity(n_cols);
+        let mut different_row = Vec::with_capacity(n_cols);
+        for x in 0..n_cols {
+            let (a, b) = (get(a, x, y), get(b, x, y));
+            row.push(b);
+            different_row.push(a != b);
+            if a != b {
+                n_different += 1;
+            }
+        }
+        char_rows.push(row);
+        different.push(different_row);
+    }
+
+    CharRowsDiff {
+        char_rows,
+        different,
+        n_different,
+        n_cells: n_rows * n_cols,
+    }
+}
+
+pub fn diff_to_terminal_string(diff: &CharRowsDiff) -> String {
+    // shows the second grid, with cells that differ from the first highlighted
+    diff.char_rows
+        .iter()
+        .zip(&diff.different)
+        .map(|(row, different)| {
+            row.iter()
+                .zip(different)
+                .map(|(c, &different)| {
+                    if different {
+                        format!("{}", c.to_string().black().on_red())
+                    } else {
+                        format!("{}", c.to_string().dimmed())
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
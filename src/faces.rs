@@ -0,0 +1,122 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, Rgba};
+
+// faces are found in a copy of the image at most this wide, which is plenty for framing
+const DETECT_WIDTH: u32 = 160;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Face {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+fn is_skin(pixel: Rgba<u8>) -> bool {
+    // the usual skin range in YCbCr, which holds up across skin tones and most lighting because it
+    // ignores brightness; transparent and very dark pixels never count
+    let [r, g, b, a] = pixel.0.map(|c| c as f32);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128. - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 128. + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    a >= 128. && y > 40. && (77. ..=127.).contains(&cb) && (133. ..=173.).contains(&cr)
+}
+
+fn skin_regions(mask: &[bool], width: usize, height: usize) -> Vec<(Face, usize)> {
+    // the bounding box and pixel count of every 4-connected region of skin
+    let mut seen = vec![false; mask.len()];
+    let mut regions = Vec::new();
+    for start in 0..mask.len() {
+        if !mask[start] || seen[start] {
+            continue;
+        }
+        seen[start] = true;
+        let mut stack = vec![start];
+        let (mut x0, mut y0, mut x1, mut y1, mut n) = (width, height, 0, 0, 0);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            (x0, y0, x1, y1, n) = (x0.min(x), y0.min(y), x1.max(x + 1), y1.max(y + 1), n + 1);
+            let neighbors = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            for j in neighbors.into_iter().flatten() {
+                if mask[j] && !seen[j] {
+                    seen[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+        let face = Face {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        };
+        regions.push((face, n));
+    }
+    regions
+}
+
+pub fn detect_faces(img: &DynamicImage) -> Vec<Face> {
+    // a lightweight detector: faces are taken to be large regions of skin which are a little taller
+    // than wide and fill most of their bounding box, with some darker features (eyes, mouth,
+    // eyebrows) inside; returns the faces from largest to smallest, in pixels of the image
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    let scale = (width as f32 / DETECT_WIDTH as f32).max(1.);
+    let small = img.resize_exact(
+        (width as f32 / scale).round().max(1.) as u32,
+        (height as f32 / scale).round().max(1.) as u32,
+        FilterType::Triangle,
+    );
+    let (small_width, small_height) = (small.width() as usize, small.height() as usize);
+    let mask: Vec<bool> = small.pixels().map(|(_, _, pixel)| is_skin(pixel)).collect();
+
+    let min_height = (small_height / 16).max(4);
+    let mut faces: Vec<Face> = skin_regions(&mask, small_width, small_height)
+        .into_iter()
+        .filter(|(face, n)| {
+            let aspect = face.height as f32 / face.width as f32;
+            let fill = *n as f32 / (face.width * face.height) as f32;
+            face.height >= min_height
+                && (0.8..=2.2).contains(&aspect)
+                && (0.4..0.97).contains(&fill)
+        })
+        .map(|(face, _)| Face {
+            x: (face.x as f32 * scale) as usize,
+            y: (face.y as f32 * scale) as usize,
+            width: ((face.width as f32 * scale) as usize).min(width as usize),
+            height: ((face.height as f32 * scale) as usize).min(height as usize),
+        })
+        .collect();
+    faces.sort_by_key(|face| std::cmp::Reverse(face.width * face.height));
+    faces
+}
+
+pub fn face_bounds(
+    faces: &[Face],
+    width: usize,
+    height: usize,
+) -> Option<(usize, usize, usize, usize)> {
+    // a portrait framing of all the faces, as (left, top, right, bottom) like content_bounds: half
+    // a face of room on either side and above, and a face below for the neck and shoulders
+    faces
+        .iter()
+        .map(|face| {
+            let (margin_x, margin_y) = (face.width / 2, face.height / 2);
+            (
+                face.x.saturating_sub(margin_x),
+                face.y.saturating_sub(margin_y),
+                (face.x + face.width + margin_x).min(width),
+                (face.y + face.height * 2).min(height),
+            )
+        })
+        .reduce(|(ax0, ay0, ax1, ay1), (bx0, by0, bx1, by1)| {
+            (ax0.min(bx0), ay0.min(by0), ax1.max(bx1), ay1.max(by1))
+        })
+}
@@ -3,6 +3,10 @@ use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::Path;
 
+use log::info;
+
+use crate::width::is_grid_safe;
+
 #[derive(Clone)]
 pub struct Character {
     pub value: char,
@@ -46,7 +50,7 @@ impl Character {
             grid.push(row);
         }
 
-        let total_kernel = [[0.0, 0.0, 0.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0]]
+        let total_kernel: Vec<Vec<f32>> = [[0.0, 0.0, 0.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0]]
             .into_iter()
             .map(|row| row.into_iter().collect())
             .collect();
@@ -55,11 +59,11 @@ impl Character {
             return (0.0, 0.0);
         }
 
-        let x_kernel = [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [-1.0, 0.0, 1.0]]
+        let x_kernel: Vec<Vec<f32>> = [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [-1.0, 0.0, 1.0]]
             .into_iter()
             .map(|row| row.into_iter().collect())
             .collect();
-        let y_kernel = [[0.0, 0.0, 0.0], [0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]
+        let y_kernel: Vec<Vec<f32>> = [[0.0, 0.0, 0.0], [0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]
             .into_iter()
             .map(|row| row.into_iter().collect())
             .collect();
@@ -74,9 +78,124 @@ impl Character {
     pub fn get(&self, x: usize, y: usize) -> f32 {
         self.bitmap[y * self.width + x]
     }
+
+    pub fn resized(&self, width: usize, height: usize) -> Character {
+        // each new pixel is the average of the old pixels it covers, weighted by how much of
+        // them it covers
+        let (sx, sy) = (
+            self.width as f32 / width as f32,
+            self.height as f32 / height as f32,
+        );
+        let overlap =
+            |a0: f32, a1: f32, b: usize| (a1.min(b as f32 + 1.) - a0.max(b as f32)).max(0.);
+        let mut bitmap = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let (y0, y1) = (y as f32 * sy, (y + 1) as f32 * sy);
+            for x in 0..width {
+                let (x0, x1) = (x as f32 * sx, (x + 1) as f32 * sx);
+                let mut total = 0.;
+                for by in (y0 as usize)..(y1.ceil() as usize).min(self.height) {
+                    for bx in (x0 as usize)..(x1.ceil() as usize).min(self.width) {
+                        total += self.get(bx, by) * overlap(x0, x1, bx) * overlap(y0, y1, by);
+                    }
+                }
+                bitmap.push(total / (sx * sy));
+            }
+        }
+        Character::new(self.value, bitmap, width, height)
+    }
+
+    fn dilated(&self, offsets: &[(isize, isize)]) -> Vec<f32> {
+        // each pixel takes the brightest of the pixels at the offsets from it
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                offsets
+                    .iter()
+                    .filter_map(|&(dx, dy)| {
+                        let (x, y) = (x as isize - dx, y as isize - dy);
+                        let inside = (0..self.width as isize).contains(&x)
+                            && (0..self.height as isize).contains(&y);
+                        inside.then(|| self.get(x as usize, y as usize))
+                    })
+                    .fold(0., f32::max)
+            })
+            .collect()
+    }
+
+    pub fn styled(&self, style: &GlyphStyle) -> Character {
+        let bitmap = match style {
+            GlyphStyle::Filled => return self.clone(),
+            // strokes grow one pixel to the right, like the bold of a terminal
+            GlyphStyle::Bold => self.dilated(&[(0, 0), (1, 0)]),
+            // the ring of pixels just outside of the strokes, which leaves them hollow
+            GlyphStyle::Outline => {
+                let offsets: Vec<(isize, isize)> = (-1..=1)
+                    .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                    .collect();
+                let dilated = self.dilated(&offsets);
+                dilated
+                    .iter()
+                    .zip(&self.bitmap)
+                    .map(|(outer, inner)| (outer - inner).max(0.))
+                    .collect()
+            }
+        };
+        Character::new(self.value, bitmap, self.width, self.height)
+    }
+}
+
+pub enum GlyphStyle {
+    Filled,
+    Outline,
+    Bold,
 }
 
-fn masked_discrete_convolution_2d(grid: &Vec<Vec<f32>>, kernel: &Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+pub fn get_glyph_style(style: &str) -> GlyphStyle {
+    match style {
+        "filled" => GlyphStyle::Filled,
+        "outline" => GlyphStyle::Outline,
+        "bold" => GlyphStyle::Bold,
+        _ => panic!("Unsupported glyph style {}", style),
+    }
+}
+
+pub fn resize_chars(chars: &[Character], cell_width: usize, cell_height: usize) -> Vec<Character> {
+    // double width glyphs become two cells wide
+    let Some(base_width) = chars.iter().map(|c| c.width).min() else {
+        return Vec::new();
+    };
+    chars
+        .iter()
+        .map(|c| {
+            let cells = (c.width as f32 / base_width as f32).round().max(1.) as usize;
+            c.resized(cells * cell_width, cell_height)
+        })
+        .collect()
+}
+
+pub fn add_blank_chars(chars: &mut Vec<Character>, values: &[char]) {
+    // synthesizes empty glyphs for the values the font doesn't have, at the size of its narrowest
+    // glyph, e.g. for fonts without a space
+    let Some(base) = chars.iter().min_by_key(|c| c.width) else {
+        return;
+    };
+    let (width, height) = (base.width, base.height);
+    let blanks: Vec<Character> = values
+        .iter()
+        .filter(|&&value| !chars.iter().any(|c| c.value == value))
+        .map(|&value| Character::new(value, vec![0.; width * height], width, height))
+        .collect();
+    if !blanks.is_empty() {
+        info!(
+            "synthesized blank glyphs\t{:?}",
+            blanks.iter().map(|c| c.value).collect::<String>()
+        );
+    }
+    chars.extend(blanks);
+}
+
+fn masked_discrete_convolution_2d(grid: &[Vec<f32>], kernel: &[Vec<f32>]) -> Vec<Vec<f32>> {
     // offset kernel to keep output size the same
     let kernel_row_offset = ((kernel.len() - 1) / 2) as i64;
     let kernel_col_offset = ((kernel[0].len() - 1) / 2) as i64;
@@ -112,10 +231,20 @@ fn masked_discrete_convolution_2d(grid: &Vec<Vec<f32>>, kernel: &Vec<Vec<f32>>)
     result
 }
 
-fn sum_2d(grid: &Vec<Vec<f32>>) -> f32 {
+fn sum_2d(grid: &[Vec<f32>]) -> f32 {
     grid.iter().map(|row| row.iter().sum::<f32>()).sum()
 }
 
+#[derive(Clone, Default)]
+pub struct FontOptions {
+    // glyphs whose bitmaps differ by at most this much in total are treated as the same glyph
+    pub glyph_tolerance: f32,
+    // when merging glyphs, keep an ascii code point as the representative if there is one
+    pub prefer_ascii: bool,
+    // drop characters which don't take up exactly one cell in a monospace grid
+    pub safe_chars: bool,
+}
+
 #[derive(Clone)]
 pub struct Font {
     pub width: usize,
@@ -123,16 +252,177 @@ pub struct Font {
     pub chars: Vec<Character>,
     pub char_map: HashMap<char, Character>,
     pub intensity_chars: Vec<Character>,
+    pub direction_lut: DirectionLut,
+    pub duplicates: HashMap<char, char>,
+    pub wide: Option<Box<Font>>,
+}
+
+const DIRECTION_ANGLES: usize = 256;
+const DIRECTION_MAGNITUDES: usize = 32;
+
+#[derive(Clone)]
+pub struct DirectionLut {
+    // the index of the char with the closest direction, for directions quantized by angle and
+    // magnitude; directions longer than max_magnitude use the longest magnitude bucket
+    indices: Vec<usize>,
+    max_magnitude: f32,
+}
+
+impl DirectionLut {
+    fn new(chars: &[Character]) -> DirectionLut {
+        let max_magnitude = 2.
+            * chars
+                .iter()
+                .map(|c| c.direction.0.hypot(c.direction.1))
+                .fold(0., f32::max)
+                .max(1.);
+        let mut indices = Vec::with_capacity(DIRECTION_ANGLES * DIRECTION_MAGNITUDES);
+        for a in 0..DIRECTION_ANGLES {
+            let angle = a as f32 / DIRECTION_ANGLES as f32 * std::f32::consts::TAU;
+            for m in 0..DIRECTION_MAGNITUDES {
+                let magnitude = m as f32 / (DIRECTION_MAGNITUDES - 1) as f32 * max_magnitude;
+                let (x, y) = (magnitude * angle.cos(), magnitude * angle.sin());
+                let distance = |c: &Character| (x - c.direction.0).hypot(y - c.direction.1);
+                let best = (0..chars.len())
+                    .min_by(|&i, &j| distance(&chars[i]).total_cmp(&distance(&chars[j])))
+                    .unwrap();
+                indices.push(best);
+            }
+        }
+        DirectionLut {
+            indices,
+            max_magnitude,
+        }
+    }
+
+    pub fn get(&self, direction: (f32, f32)) -> usize {
+        let (x, y) = direction;
+        let angle = y.atan2(x).rem_euclid(std::f32::consts::TAU);
+        let a = (angle / std::f32::consts::TAU * DIRECTION_ANGLES as f32).round() as usize
+            % DIRECTION_ANGLES;
+        let m =
+            (x.hypot(y) / self.max_magnitude * (DIRECTION_MAGNITUDES - 1) as f32).round() as usize;
+        self.indices[a * DIRECTION_MAGNITUDES + m.min(DIRECTION_MAGNITUDES - 1)]
+    }
+}
+
+// placeholder for the second cell covered by a double width character
+pub const WIDE_CONTINUATION: char = '\0';
+
+fn bitmap_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum()
+}
+
+fn deduplicate_chars(
+    chars: &[Character],
+    alphabet: &[char],
+    options: &FontOptions,
+) -> (Vec<Character>, HashMap<char, char>) {
+    // groups visually identical glyphs so that only one representative per group is scored;
+    // groups are built in alphabet order so the first alphabet entry wins by default
+    let alphabet_order: HashMap<char, usize> = alphabet
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, &c)| (c, i))
+        .collect();
+    let mut ordered: Vec<&Character> = chars.iter().collect();
+    ordered.sort_by_key(|c| alphabet_order.get(&c.value).cloned().unwrap_or(usize::MAX));
+
+    let mut groups: Vec<Vec<&Character>> = Vec::new();
+    for c in ordered {
+        let group = groups.iter_mut().find(|group| {
+            group[0].width == c.width
+                && group[0].height == c.height
+                && bitmap_distance(&group[0].bitmap, &c.bitmap) <= options.glyph_tolerance
+        });
+        match group {
+            Some(group) => group.push(c),
+            None => groups.push(vec![c]),
+        }
+    }
+
+    let mut representatives: HashSet<char> = HashSet::new();
+    let mut duplicates: HashMap<char, char> = HashMap::new();
+    for group in groups {
+        let representative = group
+            .iter()
+            .find(|c| options.prefer_ascii && c.value.is_ascii())
+            .unwrap_or(&group[0])
+            .value;
+        representatives.insert(representative);
+        for c in group {
+            if c.value != representative {
+                duplicates.insert(c.value, representative);
+            }
+        }
+    }
+
+    // keep the original font order for the scored glyphs so ties resolve the same way as before
+    let chars = chars
+        .iter()
+        .filter(|c| representatives.contains(&c.value))
+        .cloned()
+        .collect();
+    (chars, duplicates)
+}
+
+pub fn missing_chars(chars: &[Character], alphabet: &[char]) -> Vec<char> {
+    // alphabet chars without a glyph in the font, in alphabet order; control chars, like the
+    // newline at the end of an alphabet file, are never drawn so they aren't reported
+    let glyphs: HashSet<char> = chars.iter().map(|c| c.value).collect();
+    let mut seen = HashSet::new();
+    alphabet
+        .iter()
+        .cloned()
+        .filter(|&c| !c.is_control() && !glyphs.contains(&c) && seen.insert(c))
+        .collect()
 }
 
 impl Font {
     pub fn new(chars: &[Character], alphabet: &[char]) -> Font {
-        let char_set: HashSet<char> = alphabet.iter().cloned().collect();
-        let chars: Vec<Character> = chars
+        Font::with_options(chars, alphabet, &FontOptions::default())
+    }
+
+    pub fn with_options(chars: &[Character], alphabet: &[char], options: &FontOptions) -> Font {
+        let char_set: HashSet<char> = alphabet
+            .iter()
+            .cloned()
+            .filter(|&c| !options.safe_chars || is_grid_safe(c))
+            .collect();
+        if options.safe_chars {
+            let unsafe_chars: String = alphabet.iter().filter(|&&c| !is_grid_safe(c)).collect();
+            if !unsafe_chars.is_empty() {
+                info!("dropped unsafe chars\t{:?}", unsafe_chars);
+            }
+        }
+        let missing = missing_chars(chars, alphabet);
+        if !missing.is_empty() {
+            info!("missing chars\t{:?}", missing.iter().collect::<String>());
+        }
+        let all_chars: Vec<Character> = chars
             .iter()
             .filter(|c| char_set.contains(&c.value))
             .cloned()
             .collect();
+        if all_chars.is_empty() {
+            panic!(
+                "Unsupported alphabet {:?}; the font has no glyphs for any of its chars",
+                alphabet.iter().collect::<String>()
+            );
+        }
+        let (chars, duplicates) = deduplicate_chars(&all_chars, alphabet, options);
+
+        // glyphs which are exactly two cells wide (e.g. east asian wide characters) are kept in a
+        // separate font, since they are matched against pairs of cells
+        let min_width = chars.iter().map(|c| c.width).min().unwrap();
+        let (wide_chars, chars): (Vec<Character>, Vec<Character>) =
+            chars.into_iter().partition(|c| c.width == 2 * min_width);
+        let wide = if wide_chars.is_empty() {
+            None
+        } else {
+            Some(Box::new(Font::with_options(&wide_chars, alphabet, options)))
+        };
 
         let min_height = chars.iter().map(|c| c.height).min().unwrap();
         let max_height = chars.iter().map(|c| c.height).max().unwrap();
@@ -143,7 +433,6 @@ impl Font {
             )
         }
 
-        let min_width = chars.iter().map(|c| c.width).min().unwrap();
         let max_width = chars.iter().map(|c| c.width).max().unwrap();
         if max_width != min_width {
             panic!(
@@ -154,11 +443,17 @@ impl Font {
 
         let (width, height) = (min_width, min_height);
 
-        let max_intensity = chars.iter().map(|c| c.intensity as i32).max().unwrap_or(0);
+        // at least 1, for alphabets which the font only has blank glyphs for
+        let max_intensity = chars
+            .iter()
+            .map(|c| c.intensity as i32)
+            .max()
+            .unwrap_or(0)
+            .max(1);
         let max_possible_intensity = (width * height) as i32;
         let normalized_intensities: Vec<i32> = chars
             .iter()
-            .map(|c| (c.intensity as i32 * max_possible_intensity / max_intensity) as i32)
+            .map(|c| c.intensity as i32 * max_possible_intensity / max_intensity)
             .collect();
 
         let mut char_intensities: Vec<(i32, Character)> = normalized_intensities
@@ -171,13 +466,25 @@ impl Font {
             Vec::with_capacity(max_possible_intensity as usize + 1);
         let mut index = 0;
         for i in 0..=max_possible_intensity {
-            while i > char_intensities[index].0 {
+            while i > char_intensities[index].0 && index + 1 < char_intensities.len() {
                 index += 1;
             }
             intensity_chars.push(char_intensities[index].1.clone());
         }
 
-        let char_map = chars.iter().map(|c| (c.value, c.clone())).collect();
+        let direction_lut = DirectionLut::new(&chars);
+
+        // merged glyphs are still renderable, they just aren't scored
+        let mut char_map: HashMap<char, Character> =
+            all_chars.iter().map(|c| (c.value, c.clone())).collect();
+        // the space is always renderable, so writers have something to draw chars without a
+        // glyph as
+        if let Some(space) = chars
+            .iter()
+            .find(|c| c.value == ' ' && c.width == width && c.height == height)
+        {
+            char_map.entry(' ').or_insert_with(|| space.clone());
+        }
 
         Font {
             width,
@@ -185,17 +492,59 @@ impl Font {
             chars,
             char_map,
             intensity_chars,
+            direction_lut,
+            duplicates,
+            wide,
         }
     }
 
-    pub fn from_bdf_stream<R: Read>(stream: R, alphabet: &[char]) -> Font {
+    pub fn glyph(&self, c: char) -> &Character {
+        // chars the font can't render, e.g. from text input, are drawn as spaces
+        self.char_map
+            .get(&c)
+            .or_else(|| self.char_map.get(&' '))
+            .unwrap_or_else(|| panic!("Unsupported char {:?}; the font has no glyph for it", c))
+    }
+
+    pub fn intensity_ranking(&self) -> Vec<(char, f32)> {
+        // every renderable char with the fraction of its cell it covers, stretched so that the
+        // brightest char is 1 the same way intensity_chars is built, from darkest to brightest
+        let coverage = |c: &Character| c.intensity / (c.width * c.height) as f32;
+        let mut chars: Vec<&Character> = self.char_map.values().collect();
+        chars.sort_by(|a, b| {
+            coverage(a)
+                .total_cmp(&coverage(b))
+                .then(a.value.cmp(&b.value))
+        });
+        let max_coverage = chars.last().map_or(0., |&c| coverage(c));
+        chars
+            .iter()
+            .map(|&c| (c.value, coverage(c) / max_coverage.max(f32::EPSILON)))
+            .collect()
+    }
+
+    pub fn add_renderable_chars(&mut self, chars: &[Character], values: &[char]) {
+        // makes glyphs outside of the alphabet renderable without ever matching them to the input,
+        // e.g. for text drawn on top of the output
+        for c in chars {
+            if values.contains(&c.value)
+                && c.width == self.width
+                && c.height == self.height
+                && !self.char_map.contains_key(&c.value)
+            {
+                self.char_map.insert(c.value, c.clone());
+            }
+        }
+    }
+
+    pub fn read_bdf_chars<R: Read>(stream: R) -> Vec<Character> {
         let buf_reader = BufReader::new(stream);
         let font: bdf_reader::Font = bdf_reader::Font::read(buf_reader).unwrap();
         let mut chars: Vec<Character> = font
             .glyphs()
             .into_iter()
-            .map(|glyph| {
-                let value: char = glyph.encoding() as u8 as char;
+            .filter_map(|glyph| {
+                let value: char = char::from_u32(glyph.encoding())?;
                 let bbox = glyph.bounding_box();
                 let width = bbox.width as usize;
                 let height = bbox.height as usize;
@@ -210,18 +559,45 @@ impl Font {
                         });
                     }
                 }
-                Character::new(value, bitmap, width, height)
+                Some(Character::new(value, bitmap, width, height))
             })
             .collect();
-        chars.sort_by_key(|c| c.value as u8);
+        // conversion relies on there being a blank glyph, e.g. to tell where two-pass
+        // conversion found edges
+        add_blank_chars(&mut chars, &[' ']);
+        chars.sort_by_key(|c| c.value);
+        chars
+    }
 
-        Font::new(&chars, alphabet)
+    pub fn from_bdf_stream<R: Read>(stream: R, alphabet: &[char]) -> Font {
+        Font::new(&Font::read_bdf_chars(stream), alphabet)
     }
 
     pub fn from_bdf(path: &Path, alphabet: &[char]) -> Font {
         Font::from_bdf_stream(File::open(path).unwrap(), alphabet)
     }
 
+    pub fn read_bdf_file_chars(path: &Path) -> Vec<Character> {
+        Font::read_bdf_chars(File::open(path).unwrap())
+    }
+
+    pub fn with_glyph_style(&self, style: &GlyphStyle) -> Font {
+        // restyles the glyphs the bitmap writers draw, once for the whole output; the font used
+        // for conversion keeps its original glyphs, so the chosen characters stay the same
+        let mut font = self.clone();
+        font.chars = self.chars.iter().map(|c| c.styled(style)).collect();
+        font.char_map = self
+            .char_map
+            .iter()
+            .map(|(&value, c)| (value, c.styled(style)))
+            .collect();
+        font.wide = self
+            .wide
+            .as_ref()
+            .map(|wide| Box::new(wide.with_glyph_style(style)));
+        font
+    }
+
     pub fn _print(&self) {
         for c in &self.chars {
             println!("{}", c.value);
@@ -233,7 +609,7 @@ impl Font {
                         print!("  ");
                     }
                 }
-                print!("\n");
+                println!();
             }
         }
     }
@@ -1,21 +1,305 @@
-use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
-use image::{AnimationDecoder, Delay, DynamicImage, Frame};
+use color_quant::NeuQuant;
+use image::codecs::gif::GifDecoder;
+use image::imageops::{overlay, FilterType};
+use image::{AnimationDecoder, DynamicImage, Frames, ImageDecoder, Rgb};
 use indicatif::ProgressIterator;
-use log::info;
+use log::{info, warn};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{Cursor, Read, Write};
 use std::path::Path;
 
+use crate::color::quantize_img;
+use crate::convert::{
+    direction_and_intensity_convert, img_to_char_rows, render_char_rows, CharGrid, CharSelection,
+    ConversionAlgorithm, Converter,
+};
+use crate::font::Font;
+use crate::image::{check_pixel_limit, LumaImage};
+use crate::interrupt::interrupted;
+use crate::meta::{gif_comment, with_output_size};
 use crate::progress::default_progress_bar;
+use crate::rules::{apply_rules, RuleConversion};
+
+pub struct TranscodeOptions<'a> {
+    pub font: &'a Font,
+    pub convert: Converter,
+    pub out_width: Option<usize>,
+    pub brightness_offset: f32,
+    pub algorithm: ConversionAlgorithm,
+    pub color: bool,
+    pub fps: f64,
+    pub max_frames: Option<usize>,
+    pub max_pixels: Option<u64>,
+    pub strict: bool,
+    pub selection: Option<CharSelection>,
+    pub rules: Vec<RuleConversion>,
+    // colored output is quantized to the palette one cell at a time
+    pub palette: Option<Vec<Rgb<u8>>>,
+    pub global_palette: bool,
+    // embedded as a comment, with the size of the output filled in once it's known
+    pub meta: Option<Value>,
+}
+
+impl<'a> TranscodeOptions<'a> {
+    pub fn new(font: &'a Font) -> TranscodeOptions<'a> {
+        // the same defaults as the command line
+        TranscodeOptions {
+            font,
+            convert: direction_and_intensity_convert,
+            out_width: None,
+            brightness_offset: 0.,
+            algorithm: ConversionAlgorithm::EdgeAugmented,
+            color: true,
+            fps: 30.,
+            max_frames: None,
+            max_pixels: None,
+            strict: false,
+            selection: None,
+            rules: Vec::new(),
+            palette: None,
+            global_palette: false,
+            meta: None,
+        }
+    }
+}
+
+pub fn convert_frame(img: &DynamicImage, options: &TranscodeOptions) -> CharGrid {
+    // the conversion of a single frame, with the rules applied over it
+    let luma = LumaImage::from(img);
+    let char_rows = img_to_char_rows(
+        options.font,
+        &luma,
+        options.convert,
+        options.out_width,
+        options.brightness_offset,
+        &options.algorithm,
+        options.selection.as_ref(),
+    );
+    apply_rules(
+        char_rows,
+        &luma,
+        &options.rules,
+        options.font,
+        options.out_width,
+        options.brightness_offset,
+    )
+}
+
+pub fn render_frame(
+    char_rows: &CharGrid,
+    img: &DynamicImage,
+    options: &TranscodeOptions,
+) -> DynamicImage {
+    // colors are only sampled once per cell, so that's what the palette is applied to
+    let cells = match &options.palette {
+        Some(palette) if options.color => {
+            let (n_cols, n_rows) = (char_rows[0].len() as u32, char_rows.len() as u32);
+            let cells = img.resize_exact(n_cols, n_rows, FilterType::Nearest);
+            if palette.is_empty() {
+                cells
+            } else {
+                quantize_img(&cells, palette)
+            }
+        }
+        _ => img.clone(),
+    };
+    render_char_rows(char_rows, options.font, &cells, options.color)
+}
+
+fn gif_decoder<R: Read>(stream: R) -> GifDecoder<R> {
+    // the decoder reads up to the first frame, so this also fails for gifs without frames
+    GifDecoder::new(stream).unwrap_or_else(|err| {
+        panic!(
+            "Unsupported animation: it has no frames, or its first frame can't be read ({})",
+            err
+        )
+    })
+}
 
 pub fn read_gif_from_stream<R: Read>(stream: R) -> Vec<DynamicImage> {
-    let decoder = GifDecoder::new(stream).unwrap();
-    let frames = decoder.into_frames();
-    let frames = frames.collect_frames().expect("error decoding gif");
-    frames
-        .iter()
-        .map(|frame| DynamicImage::ImageRgba8(frame.buffer().clone()))
-        .collect()
+    read_gif_from_stream_with_limits(stream, None, None, false)
+}
+
+pub fn read_gif_from_stream_with_limits<R: Read>(
+    mut stream: R,
+    max_frames: Option<usize>,
+    max_pixels: Option<u64>,
+    strict: bool,
+) -> Vec<DynamicImage> {
+    // frames are decoded one at a time, so oversized or overly long gifs are rejected or cut off
+    // before they are ever fully held in memory; only the compressed gif is read in up front, so
+    // that decoding can start over after a corrupt frame
+    let mut gif = Vec::new();
+    stream.read_to_end(&mut gif).unwrap();
+    let decoder = gif_decoder(&gif[..]);
+    let (width, height) = decoder.dimensions();
+    check_pixel_limit(width, height, max_pixels);
+
+    take_frames(
+        recover_frames(decoder.into_frames(), resync_gif(&gif), strict),
+        max_frames,
+    )
+}
+
+fn skip_sub_blocks(gif: &[u8], mut i: usize) -> usize {
+    // data in a gif is split into blocks of up to 255 bytes, each prefixed by its length
+    while let Some(&len) = gif.get(i) {
+        i += 1 + len as usize;
+        if len == 0 {
+            break;
+        }
+    }
+    i
+}
+
+fn color_table_len(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        0
+    } else {
+        3 << ((packed & 7) + 1)
+    }
+}
+
+fn frame_offsets(gif: &[u8]) -> (usize, Vec<usize>) {
+    // the length of the header, logical screen descriptor, and global color table, and where the
+    // blocks of each frame start, extensions included; corrupt image data doesn't move any of
+    // these, since the lengths of the sub-blocks around it are still intact
+    let header_len = 13 + gif.get(10).map_or(0, |&packed| color_table_len(packed));
+    let mut offsets = Vec::new();
+    let (mut i, mut frame_start) = (header_len, header_len);
+    loop {
+        match gif.get(i) {
+            Some(0x21) => i = skip_sub_blocks(gif, i + 2),
+            Some(0x2c) => {
+                let packed = gif.get(i + 9).copied().unwrap_or(0);
+                i = skip_sub_blocks(gif, i + 11 + color_table_len(packed));
+                offsets.push(frame_start);
+                frame_start = i;
+            }
+            _ => break,
+        }
+    }
+    (header_len, offsets)
+}
+
+fn resync_gif<'a>(gif: &'a [u8]) -> impl FnMut(usize) -> Option<Frames<'a>> + 'a {
+    // decodes the gif again from the start of a later frame, once the decoder has lost its place
+    let (header_len, offsets) = frame_offsets(gif);
+    move |index| {
+        let offset = *offsets.get(index)?;
+        let stream = Cursor::new([&gif[..header_len], &gif[offset..]].concat());
+        GifDecoder::new(stream)
+            .ok()
+            .map(|decoder| decoder.into_frames())
+    }
+}
+
+// a decoder which fails this many times in a row has most likely lost its place in the stream
+const MAX_CONSECUTIVE_ERRORS: usize = 8;
+
+pub fn recover_frames<'a, F>(
+    mut frames: Frames<'a>,
+    mut resync: F,
+    strict: bool,
+) -> impl Iterator<Item = DynamicImage> + 'a
+where
+    F: FnMut(usize) -> Option<Frames<'a>> + 'a,
+{
+    // corrupt frames between two good ones are replaced by the last good frame, so one bad frame
+    // doesn't abort a long conversion; corrupt frames at the start or end are dropped, and strict
+    // fails on the first bad frame instead. a decoder can't find the next frame by itself after a
+    // corrupt one, so resync gives frames decoded again from a given index; without it (None),
+    // every frame after a corrupt one is lost. frames decoded again are drawn over the last good
+    // frame, since the canvas they were drawn onto has been lost along with the decoder
+    let mut queue = VecDeque::new();
+    let mut previous: Option<DynamicImage> = None;
+    let mut index = 0;
+    let mut n_errors = 0;
+    let mut resynced = false;
+    std::iter::from_fn(move || loop {
+        if let Some(img) = queue.pop_front() {
+            return Some(img);
+        }
+        let frame = match frames.next() {
+            Some(frame) => frame,
+            None => {
+                if n_errors > 0 {
+                    warn!(
+                        "dropping the last {} frames, which couldn't be decoded",
+                        n_errors
+                    );
+                }
+                return None;
+            }
+        };
+        index += 1;
+        match frame {
+            Ok(frame) => {
+                let mut img = frame.into_buffer();
+                if let Some(previous) = &previous {
+                    for _ in 0..n_errors {
+                        queue.push_back(previous.clone());
+                    }
+                    if resynced {
+                        let mut base = previous.to_rgba8();
+                        overlay(&mut base, &img, 0, 0);
+                        img = base;
+                    }
+                }
+                let img = DynamicImage::ImageRgba8(img);
+                n_errors = 0;
+                previous = Some(img.clone());
+                queue.push_back(img);
+            }
+            Err(err) if strict => panic!("Error decoding frame {}: {}", index - 1, err),
+            Err(err) => {
+                warn!("frame {} couldn't be decoded: {}", index - 1, err);
+                n_errors += 1;
+                if n_errors == MAX_CONSECUTIVE_ERRORS {
+                    warn!(
+                        "stopping after {} corrupt frames in a row",
+                        MAX_CONSECUTIVE_ERRORS
+                    );
+                    return None;
+                }
+                if let Some(next) = resync(index) {
+                    frames = next;
+                    resynced = true;
+                }
+            }
+        }
+    })
+}
+
+fn take_frames(
+    frames: impl Iterator<Item = DynamicImage>,
+    max_frames: Option<usize>,
+) -> Vec<DynamicImage> {
+    let max_frames = max_frames.unwrap_or(usize::MAX);
+    let mut collected = Vec::new();
+    for frame in frames {
+        if collected.len() == max_frames {
+            info!("stopping after the first {} frames", max_frames);
+            break;
+        }
+        collected.push(frame);
+    }
+    if collected.is_empty() && max_frames > 0 {
+        panic!("Unsupported animation: none of its frames could be decoded");
+    }
+    collected
+}
+
+pub fn collect_frames(
+    frames: Frames,
+    max_frames: Option<usize>,
+    strict: bool,
+) -> Vec<DynamicImage> {
+    // for the animations of other formats, whose decoders can't start over at a later frame
+    take_frames(recover_frames(frames, |_| None, strict), max_frames)
 }
 
 pub fn read_gif(path: &Path) -> Vec<DynamicImage> {
@@ -23,27 +307,281 @@ pub fn read_gif(path: &Path) -> Vec<DynamicImage> {
     read_gif_from_stream(fp)
 }
 
-pub fn write_gif_to_stream<W: Write>(stream: W, frames: &[DynamicImage], fps: f64) {
-    let mut encoder = GifEncoder::new(stream);
-    encoder.set_repeat(Repeat::Infinite).unwrap();
-    let delay = Delay::from_numer_denom_ms(1000, fps as u32);
+pub fn read_gif_with_limits(
+    path: &Path,
+    max_frames: Option<usize>,
+    max_pixels: Option<u64>,
+    strict: bool,
+) -> Vec<DynamicImage> {
+    let fp = File::open(path).unwrap();
+    read_gif_from_stream_with_limits(fp, max_frames, max_pixels, strict)
+}
+
+fn gif_encoder<W: Write>(
+    stream: W,
+    width: u16,
+    height: u16,
+    palette: &[u8],
+    comment: Option<&str>,
+) -> gif::Encoder<W> {
+    // the comment goes right after the header, before the first frame is encoded
+    let mut encoder = gif::Encoder::new(stream, width, height, palette).unwrap();
+    encoder.set_repeat(gif::Repeat::Infinite).unwrap();
+    if let Some(comment) = comment {
+        encoder
+            .write_raw_extension(gif::Extension::Comment.into(), &[comment.as_bytes()])
+            .unwrap();
+    }
+    encoder
+}
+
+fn gif_delay(fps: f64) -> u16 {
+    // in hundredths of a second, the same for every way of writing a gif
+    if fps < 1. {
+        panic!("Unsupported fps {}; it must be at least 1", fps);
+    }
+    (1000 / fps as u32 / 10) as u16
+}
+
+fn rgba_gif_frame(img: &DynamicImage, delay: u16) -> gif::Frame<'static> {
+    // each frame gets its own palette
+    let (width, height) = (img.width() as u16, img.height() as u16);
+    let mut rgba = img.to_rgba8().into_raw();
+    let mut frame = gif::Frame::from_rgba_speed(width, height, &mut rgba, 1);
+    frame.delay = delay;
+    frame.dispose = gif::DisposalMethod::Background;
+    frame
+}
+
+pub fn write_gif_to_stream<W: Write>(stream: W, frames: &[DynamicImage], fps: f64) -> usize {
+    write_gif_to_stream_with_comment(stream, frames, fps, None)
+}
+
+pub fn write_gif_to_stream_with_comment<W: Write>(
+    stream: W,
+    frames: &[DynamicImage],
+    fps: f64,
+    comment: Option<&str>,
+) -> usize {
+    // an interruption stops encoding early, and the gif is still finished properly when the
+    // encoder is dropped; returns the number of frames written
+    if frames.is_empty() {
+        return 0;
+    }
+    let (width, height) = (frames[0].width() as u16, frames[0].height() as u16);
+    let mut encoder = gif_encoder(stream, width, height, &[], comment);
+    let delay = gif_delay(fps);
 
     info!("converting bitmaps to gif frames...");
     let progress = default_progress_bar("Frames", frames.len());
-    let frames: Vec<Frame> = frames
+    let frames: Vec<gif::Frame> = frames
         .iter()
         .progress_with(progress)
-        .map(|f| Frame::from_parts(f.to_rgba8(), 0, 0, delay))
+        .take_while(|_| !interrupted())
+        .map(|f| rgba_gif_frame(f, delay))
         .collect();
 
     info!("encoding gif frames...");
     let progress = default_progress_bar("Frames", frames.len());
-    encoder
-        .encode_frames(frames.into_iter().progress_with(progress))
-        .unwrap();
+    let mut n_frames = 0;
+    for frame in frames.iter().progress_with(progress) {
+        if interrupted() {
+            break;
+        }
+        encoder.write_frame(frame).unwrap();
+        n_frames += 1;
+    }
+    n_frames
+}
+
+pub fn write_gif(path: &Path, frames: &[DynamicImage], fps: f64) -> usize {
+    write_gif_with_comment(path, frames, fps, None)
 }
 
-pub fn write_gif(path: &Path, frames: &[DynamicImage], fps: f64) {
+pub fn write_gif_with_comment(
+    path: &Path,
+    frames: &[DynamicImage],
+    fps: f64,
+    comment: Option<&str>,
+) -> usize {
     let fp = File::create(path).unwrap();
-    write_gif_to_stream(fp, frames, fps)
+    write_gif_to_stream_with_comment(fp, frames, fps, comment)
+}
+
+type PaletteIndex = Box<dyn Fn(&[u8]) -> u8>;
+
+fn global_palette(frames: &[DynamicImage]) -> (Vec<u8>, PaletteIndex) {
+    // ascii output usually has few enough colors to be indexed exactly; otherwise, a palette is
+    // trained on pixels sampled from up to 16 frames spread over the animation
+    let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+    'frames: for frame in frames {
+        for pixel in frame.to_rgb8().pixels() {
+            if !colors.contains_key(&pixel.0) {
+                if colors.len() == 256 {
+                    colors.clear();
+                    break 'frames;
+                }
+                colors.insert(pixel.0, colors.len() as u8);
+            }
+        }
+    }
+
+    if !colors.is_empty() {
+        let mut palette = vec![0; colors.len() * 3];
+        for (color, &i) in &colors {
+            palette[i as usize * 3..i as usize * 3 + 3].copy_from_slice(color);
+        }
+        return (palette, Box::new(move |p| colors[&[p[0], p[1], p[2]]]));
+    }
+
+    let step = (frames.len() / 16).max(1);
+    let samples: Vec<u8> = frames
+        .iter()
+        .step_by(step)
+        .flat_map(|frame| frame.to_rgba8().into_raw())
+        .collect();
+    let quantizer = NeuQuant::new(10, 256, &samples);
+    (
+        quantizer.color_map_rgb(),
+        Box::new(move |p| quantizer.index_of(p) as u8),
+    )
+}
+
+pub fn write_gif_to_stream_with_global_palette<W: Write>(
+    stream: W,
+    frames: &[DynamicImage],
+    fps: f64,
+    comment: Option<&str>,
+) -> usize {
+    // one palette shared by every frame avoids colors flickering between frames and keeps the
+    // file smaller than storing a local palette per frame
+    let (width, height) = (frames[0].width() as u16, frames[0].height() as u16);
+    info!("computing global gif palette...");
+    let (palette, index_of) = global_palette(frames);
+    let mut encoder = gif_encoder(stream, width, height, &palette, comment);
+    let delay = gif_delay(fps);
+
+    info!("encoding gif frames...");
+    let progress = default_progress_bar("Frames", frames.len());
+    let mut n_frames = 0;
+    for f in frames.iter().progress_with(progress) {
+        if interrupted() {
+            break;
+        }
+        let buffer: Vec<u8> = f.to_rgba8().chunks(4).map(&index_of).collect();
+        let frame = gif::Frame {
+            width,
+            height,
+            delay,
+            buffer: Cow::Owned(buffer),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).unwrap();
+        n_frames += 1;
+    }
+    n_frames
+}
+
+pub fn write_gif_with_global_palette(
+    path: &Path,
+    frames: &[DynamicImage],
+    fps: f64,
+    comment: Option<&str>,
+) -> usize {
+    let fp = File::create(path).unwrap();
+    write_gif_to_stream_with_global_palette(fp, frames, fps, comment)
+}
+
+pub fn transcode_ascii_stream<R: Read, W: Write>(
+    stream_in: R,
+    stream_out: W,
+    options: &TranscodeOptions,
+) -> usize {
+    transcode_ascii_stream_with_hook(stream_in, stream_out, options, |_, _| {})
+}
+
+pub fn transcode_ascii_stream_with_hook<R: Read, W: Write, F: FnMut(&mut CharGrid, usize)>(
+    mut stream_in: R,
+    stream_out: W,
+    options: &TranscodeOptions,
+    mut on_frame: F,
+) -> usize {
+    // each frame is decoded, converted, and encoded before the next one is decoded, so memory use
+    // only grows with the size of the compressed gif, unless the frames share a global palette,
+    // which needs all of them; on_frame can change each grid before it is rendered; returns the
+    // number of frames written
+    let mut gif = Vec::new();
+    stream_in.read_to_end(&mut gif).unwrap();
+    let decoder = gif_decoder(&gif[..]);
+    let (width, height) = decoder.dimensions();
+    check_pixel_limit(width, height, options.max_pixels);
+
+    // the encoder is only made once the size of the rendered frames is known
+    let mut stream_out = Some(stream_out);
+    let mut encoder = None;
+    let mut bitmaps = Vec::new();
+    let delay = gif_delay(options.fps);
+
+    let max_frames = options.max_frames.unwrap_or(usize::MAX);
+    let mut n_frames = 0;
+    let mut size = (0, 0);
+    let frames = recover_frames(decoder.into_frames(), resync_gif(&gif), options.strict);
+    for img in frames.take(max_frames) {
+        if interrupted() {
+            break;
+        }
+        let mut char_rows = convert_frame(&img, options);
+        on_frame(&mut char_rows, n_frames);
+        size = (char_rows[0].len(), char_rows.len());
+        let bitmap = render_frame(&char_rows, &img, options);
+        if options.global_palette {
+            bitmaps.push(bitmap);
+        } else {
+            let (width, height) = (bitmap.width() as u16, bitmap.height() as u16);
+            encoder
+                .get_or_insert_with(|| {
+                    gif_encoder(stream_out.take().unwrap(), width, height, &[], None)
+                })
+                .write_frame(&rgba_gif_frame(&bitmap, delay))
+                .unwrap();
+        }
+        n_frames += 1;
+    }
+
+    // the number of frames is only known at the end, so the comment comes after the last one
+    let comment = options
+        .meta
+        .as_ref()
+        .map(|meta| gif_comment(&with_output_size(meta, size.0, size.1, n_frames)));
+    if let Some(mut encoder) = encoder {
+        if let Some(comment) = comment {
+            encoder
+                .write_raw_extension(gif::Extension::Comment.into(), &[comment.as_bytes()])
+                .unwrap();
+        }
+    } else if !bitmaps.is_empty() {
+        // an interruption while converting stops this from writing any of them
+        n_frames = write_gif_to_stream_with_global_palette(
+            stream_out.take().unwrap(),
+            &bitmaps,
+            options.fps,
+            comment.as_deref(),
+        );
+    }
+    n_frames
+}
+
+pub fn transcode_ascii(path_in: &Path, path_out: &Path, options: &TranscodeOptions) -> usize {
+    transcode_ascii_with_hook(path_in, path_out, options, |_, _| {})
+}
+
+pub fn transcode_ascii_with_hook<F: FnMut(&mut CharGrid, usize)>(
+    path_in: &Path,
+    path_out: &Path,
+    options: &TranscodeOptions,
+    on_frame: F,
+) -> usize {
+    let fp_in = File::open(path_in).unwrap();
+    let fp_out = File::create(path_out).unwrap();
+    transcode_ascii_stream_with_hook(fp_in, fp_out, options, on_frame)
 }
@@ -0,0 +1,100 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use image::Rgb;
+
+use crate::color::ink_color;
+
+// plays the frames pushed so far; until the done marker has been written, the page reloads
+// itself every second to pick up new frames, keeping its place in the animation
+const HTML_HEADER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<script>
+let frames = [];
+let done = false;
+let push = frame => frames.push(frame);
+window.onload = function () {
+    let display = document.getElementById("display");
+    let currentFrame = Number(sessionStorage.getItem("frame") || 0);
+    let render = function () {
+        if (frames.length > 0) {
+            currentFrame = (currentFrame + 1) % frames.length;
+            display.innerHTML = frames[currentFrame];
+        }
+    };
+    render();
+    setInterval(() => requestAnimationFrame(render), 1000.0 / FPS);
+    if (!done) {
+        document.getElementById("status").textContent = `converting... ${frames.length} frames so far`;
+        setTimeout(() => {
+            sessionStorage.setItem("frame", currentFrame);
+            location.reload();
+        }, 1000);
+    }
+};
+</script>
+</head>
+<body style="background-color: BACKGROUND; color: FOREGROUND; font: bold 15px 'Courier New'">
+<p id="status"></p>
+<pre id="display" style="WRITING_MODE"></pre>
+"#;
+
+pub struct HtmlWriter {
+    file: File,
+}
+
+impl HtmlWriter {
+    pub fn create(path: &Path, fps: f64, vertical: bool, paper: Option<Rgb<u8>>) -> HtmlWriter {
+        let writing_mode = if vertical {
+            "writing-mode: vertical-rl; text-orientation: upright"
+        } else {
+            "writing-mode: horizontal-tb"
+        };
+        // uncolored text is white on black, unless it's written on paper
+        let background = paper.unwrap_or(Rgb([0, 0, 0]));
+        let foreground = ink_color(background);
+        let hex = |Rgb([r, g, b]): Rgb<u8>| format!("#{:02X}{:02X}{:02X}", r, g, b);
+        let header = HTML_HEADER
+            .replace("FPS", &fps.to_string())
+            .replace("WRITING_MODE", writing_mode)
+            .replace("BACKGROUND", &hex(background))
+            .replace("FOREGROUND", &hex(foreground));
+        let mut file = File::create(path).unwrap();
+        file.write_all(header.as_bytes()).unwrap();
+        file.flush().unwrap();
+        HtmlWriter { file }
+    }
+
+    pub fn push_frame(&mut self, frame_html: &str) {
+        // each frame is written in one go, so a reload mid conversion sees whole frames
+        let frame = serde_json::to_string(frame_html)
+            .unwrap()
+            .replace("</", "<\\/");
+        let script = format!("<script>push({});</script>\n", frame);
+        self.file.write_all(script.as_bytes()).unwrap();
+        self.file.flush().unwrap();
+    }
+
+    pub fn finish(mut self) {
+        self.file
+            .write_all(b"<script>done = true;</script>\n</body>\n</html>\n")
+            .unwrap();
+    }
+}
+
+pub fn write_html(
+    path: &Path,
+    frames_html: &[String],
+    fps: f64,
+    vertical: bool,
+    paper: Option<Rgb<u8>>,
+) {
+    let mut writer = HtmlWriter::create(path, fps, vertical, paper);
+    for frame_html in frames_html {
+        writer.push_frame(frame_html);
+    }
+    writer.finish();
+}
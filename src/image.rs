@@ -39,6 +39,19 @@ impl<T: Copy> Image<T> for LumaImage<T> {
     }
 }
 
+pub enum ResizeFilter {
+    Bilinear,
+    DetailPreserve,
+}
+
+pub fn get_resize_filter(filter: &str) -> ResizeFilter {
+    match filter {
+        "bilinear" => ResizeFilter::Bilinear,
+        "detail-preserve" => ResizeFilter::DetailPreserve,
+        _ => panic!("Unsupported resize filter {}", filter),
+    }
+}
+
 impl From<&DynamicImage> for LumaImage<f32> {
     fn from(value: &DynamicImage) -> Self {
         let (width, height) = value.dimensions();
@@ -66,7 +79,7 @@ impl From<LumaImage<f32>> for DynamicImage {
         for y in 0..value.height {
             for x in 0..value.width {
                 let v = (value.get_pixel(x, y) * 255.) as u8;
-                result.put_pixel(x as u32, y as u32, Rgba { 0: [v, v, v, 255] });
+                result.put_pixel(x as u32, y as u32, Rgba([v, v, v, 255]));
             }
         }
         result
@@ -200,6 +213,105 @@ impl LumaImage<f32> {
         result
     }
 
+    pub fn resize_detail_preserving(&self, width: usize, height: usize) -> LumaImage<f32> {
+        // every output pixel is a mean of the input pixels it covers, weighted by how far each
+        // one is from their plain mean, so thin lines which differ from their surroundings keep
+        // about half of their contrast instead of being averaged away; enlarging has no detail
+        // to lose, so it is left to resize
+        if width >= self.width && height >= self.height {
+            return self.resize(width, height);
+        }
+        let span = |i: usize, out: usize, len: usize| {
+            let start = i * len / out;
+            (start, ((i + 1) * len / out).max(start + 1))
+        };
+        let mut result = LumaImage {
+            width,
+            height,
+            pixels: vec![0.; width * height],
+        };
+        for y in 0..height {
+            let (y0, y1) = span(y, height, self.height);
+            for x in 0..width {
+                let (x0, x1) = span(x, width, self.width);
+                let footprint: Vec<f32> = (y0..y1)
+                    .flat_map(|in_y| (x0..x1).map(move |in_x| (in_x, in_y)))
+                    .map(|(in_x, in_y)| self.get_pixel(in_x, in_y))
+                    .collect();
+                let mean = footprint.iter().sum::<f32>() / footprint.len() as f32;
+                // the small constant keeps flat regions at their plain mean
+                let (sum, weights) = footprint.iter().fold((0., 0.), |(sum, weights), &p| {
+                    let weight = (p - mean).abs() + 1e-4;
+                    (sum + p * weight, weights + weight)
+                });
+                result.set_pixel(x, y, sum / weights);
+            }
+        }
+        result
+    }
+
+    pub fn resize_with(
+        &self,
+        width: usize,
+        height: usize,
+        filter: &ResizeFilter,
+    ) -> LumaImage<f32> {
+        match filter {
+            ResizeFilter::Bilinear => self.resize(width, height),
+            ResizeFilter::DetailPreserve => self.resize_detail_preserving(width, height),
+        }
+    }
+
+    pub fn resize_nearest(&self, width: usize, height: usize) -> LumaImage<f32> {
+        // integer coordinates, so integer scale factors repeat each pixel exactly
+        let mut result = LumaImage {
+            width,
+            height,
+            pixels: vec![0.; width * height],
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let in_x = x * self.width / width;
+                let in_y = y * self.height / height;
+                result.set_pixel(x, y, self.get_pixel(in_x, in_y));
+            }
+        }
+
+        result
+    }
+
+    pub fn resize_area(&self, width: usize, height: usize) -> LumaImage<f32> {
+        // every output pixel is the mean of the input pixels it covers, in a single pass over
+        // the input; when enlarging, pixels which cover no input pixel take the nearest one
+        let mut sums = vec![0.; width * height];
+        let mut counts = vec![0; width * height];
+        for y in 0..self.height {
+            let out_y = y * height / self.height;
+            for x in 0..self.width {
+                let i = out_y * width + x * width / self.width;
+                sums[i] += self.get_pixel(x, y);
+                counts[i] += 1;
+            }
+        }
+        let mut result = LumaImage {
+            width,
+            height,
+            pixels: sums,
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * width + x;
+                result.pixels[i] = if counts[i] > 0 {
+                    result.pixels[i] / counts[i] as f32
+                } else {
+                    self.get_pixel(x * self.width / width, y * self.height / height)
+                };
+            }
+        }
+        result
+    }
+
     pub fn grid(&self) -> Vec<Vec<f32>> {
         let mut i = 0;
         (0..self.height)
@@ -218,6 +330,15 @@ impl LumaImage<f32> {
     pub fn pixels(&self) -> &Vec<f32> {
         &self.pixels
     }
+
+    pub fn apply_depth(&mut self, depth: &LumaImage<f32>, strength: f32) {
+        // brightens near pixels and darkens far ones, where brighter depth pixels are nearer, so
+        // that nearer regions get heavier characters
+        let depth = depth.resize(self.width, self.height);
+        for (pixel, d) in self.pixels.iter_mut().zip(depth.pixels()) {
+            *pixel = (*pixel + strength * (d - 0.5)).clamp(0., 1.);
+        }
+    }
 }
 
 pub fn get_gaussian_kernel(sigma: f32, size: isize) -> Vec<f32> {
@@ -231,9 +352,159 @@ pub fn get_gaussian_kernel(sigma: f32, size: isize) -> Vec<f32> {
 
 pub fn normalize_f32(v: &[f32]) -> Vec<f32> {
     let inv_total = 1. / v.iter().sum::<f32>();
-    v.iter().cloned().map(|x| (x * inv_total)).collect()
+    v.iter().cloned().map(|x| x * inv_total).collect()
 }
 
 pub fn lerp_f32(a: f32, t: f32, b: f32) -> f32 {
     a + t * (b - a)
 }
+
+pub fn content_bounds(img: &DynamicImage, threshold: f32) -> Option<(usize, usize, usize, usize)> {
+    // returns (left, top, right, bottom) of the pixels that differ from the background, with right
+    // and bottom exclusive; transparent images use alpha as the background indicator, and opaque
+    // images compare luma against the top left pixel
+    let luma =
+        |pixel: Rgba<u8>| (pixel[0] as f32 + pixel[1] as f32 + pixel[2] as f32) / (3. * 255.);
+    let background = img.get_pixel(0, 0);
+    let transparent = img.pixels().any(|(_, _, pixel)| pixel[3] < 255);
+
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (x, y, pixel) in img.pixels() {
+        let is_content = if transparent {
+            pixel[3] as f32 / 255. > threshold
+        } else {
+            (luma(pixel) - luma(background)).abs() > threshold
+        };
+        if !is_content {
+            continue;
+        }
+        let (x, y) = (x as usize, y as usize);
+        bounds = Some(match bounds {
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x + 1), y1.max(y + 1)),
+            None => (x, y, x + 1, y + 1),
+        });
+    }
+    bounds
+}
+
+pub fn check_pixel_limit(width: u32, height: u32, max_pixels: Option<u64>) {
+    if let Some(max_pixels) = max_pixels {
+        let pixels = width as u64 * height as u64;
+        if pixels > max_pixels {
+            panic!(
+                "Input is {}x{} ({} pixels), which is more than the maximum of {} pixels",
+                width, height, pixels, max_pixels
+            );
+        }
+    }
+}
+
+pub fn pad_to_cells(
+    img: &DynamicImage,
+    cell_width: usize,
+    cell_height: usize,
+    out_width: Option<usize>,
+) -> DynamicImage {
+    // centers the image on a transparent canvas whose size converts to a whole number of cells
+    // at the output width, so it never has to be stretched to round the number of rows; at the
+    // native width, every cell covers exactly cell_width by cell_height pixels of the image
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let (padded_width, padded_height) = match out_width {
+        None => (
+            width.div_ceil(cell_width) * cell_width,
+            height.div_ceil(cell_height) * cell_height,
+        ),
+        Some(out_width) => {
+            let scale = (out_width * cell_width) as f64 / width as f64;
+            let out_height = (height as f64 * scale / cell_height as f64).ceil();
+            let padded_height = (out_height * cell_height as f64 / scale).round() as usize;
+            (width, padded_height.max(height))
+        }
+    };
+    let mut padded = DynamicImage::new_rgba8(padded_width as u32, padded_height as u32);
+    let (x, y) = ((padded_width - width) / 2, (padded_height - height) / 2);
+    padded.copy_from(img, x as u32, y as u32).unwrap();
+    padded
+}
+
+pub fn split_side_by_side(img: &DynamicImage) -> (DynamicImage, DynamicImage) {
+    // the left and right views of a side-by-side stereo image; an odd middle column is dropped
+    let (width, height) = img.dimensions();
+    let half = width / 2;
+    (
+        img.crop_imm(0, 0, half, height),
+        img.crop_imm(width - half, 0, half, height),
+    )
+}
+
+pub struct Layer {
+    pub path: String,
+    // the position of the layer's top left corner on the input, which can be negative
+    pub x: i64,
+    pub y: i64,
+    pub alpha: f32,
+}
+
+pub fn parse_layer(layer: &str) -> Layer {
+    // e.g. logo.png@10,20 or logo.png@10,20,0.5; the last @ separates the path from the position,
+    // so paths can contain @
+    let unsupported = || -> ! { panic!("Unsupported layer {}", layer) };
+    let (path, position) = layer.rsplit_once('@').unwrap_or_else(|| unsupported());
+    let fields: Vec<&str> = position.split(',').collect();
+    if path.is_empty() || !(2..=3).contains(&fields.len()) {
+        unsupported();
+    }
+    let alpha = match fields.get(2) {
+        Some(alpha) => alpha
+            .parse::<f32>()
+            .ok()
+            .filter(|alpha| (0. ..=1.).contains(alpha))
+            .unwrap_or_else(|| unsupported()),
+        None => 1.,
+    };
+    Layer {
+        path: path.to_string(),
+        x: fields[0].parse().unwrap_or_else(|_| unsupported()),
+        y: fields[1].parse().unwrap_or_else(|_| unsupported()),
+        alpha,
+    }
+}
+
+pub fn composite_layer(
+    img: &DynamicImage,
+    layer: &DynamicImage,
+    x: i64,
+    y: i64,
+    alpha: f32,
+) -> DynamicImage {
+    // the layer is drawn over the image with the usual "over" operator, its own alpha scaled by
+    // alpha; parts of the layer outside the image are cut off
+    let mut out = img.to_rgba8();
+    let layer = layer.to_rgba8();
+    for (lx, ly, pixel) in layer.enumerate_pixels() {
+        let (ox, oy) = (x + lx as i64, y + ly as i64);
+        if ox < 0 || oy < 0 || ox >= out.width() as i64 || oy >= out.height() as i64 {
+            continue;
+        }
+        let below = out.get_pixel_mut(ox as u32, oy as u32);
+        let top_alpha = pixel[3] as f32 / 255. * alpha;
+        let below_alpha = below[3] as f32 / 255.;
+        let out_alpha = top_alpha + below_alpha * (1. - top_alpha);
+        if out_alpha == 0. {
+            *below = Rgba([0, 0, 0, 0]);
+            continue;
+        }
+        let channel = |i: usize| {
+            let top = pixel[i] as f32 * top_alpha;
+            let bottom = below[i] as f32 * below_alpha * (1. - top_alpha);
+            ((top + bottom) / out_alpha).round() as u8
+        };
+        *below = Rgba([
+            channel(0),
+            channel(1),
+            channel(2),
+            (out_alpha * 255.).round() as u8,
+        ]);
+    }
+    DynamicImage::ImageRgba8(out)
+}
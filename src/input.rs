@@ -0,0 +1,120 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::io::Reader;
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat};
+use serde_json::Value;
+
+use crate::gif::{collect_frames, read_gif_with_limits};
+use crate::image::check_pixel_limit;
+
+pub enum InputFormat {
+    Animation(ImageFormat),
+    Still(ImageFormat),
+    Cells(Value),
+    Text(String),
+}
+
+impl InputFormat {
+    pub fn is_animation(&self) -> bool {
+        matches!(self, InputFormat::Animation(_) | InputFormat::Cells(_))
+    }
+}
+
+fn is_animated(path: &Path, format: ImageFormat) -> bool {
+    let reader = || BufReader::new(File::open(path).unwrap());
+    match format {
+        ImageFormat::Gif => true,
+        ImageFormat::Png => PngDecoder::new(reader()).is_ok_and(|decoder| decoder.is_apng()),
+        ImageFormat::WebP => {
+            WebPDecoder::new(reader()).is_ok_and(|decoder| decoder.has_animation())
+        }
+        _ => false,
+    }
+}
+
+pub fn sniff_input_format(path: &Path) -> InputFormat {
+    // formats are detected from the file contents rather than the file name, so that e.g. a png
+    // saved with a .gif extension is still decoded correctly
+    let mut header = Vec::with_capacity(512);
+    File::open(path)
+        .unwrap_or_else(|err| panic!("Could not open input {:?}: {}", path, err))
+        .take(512)
+        .read_to_end(&mut header)
+        .unwrap();
+
+    if let Ok(format) = image::guess_format(&header) {
+        return if is_animated(path, format) {
+            InputFormat::Animation(format)
+        } else {
+            InputFormat::Still(format)
+        };
+    }
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) if !text.contains('\0') => text,
+        _ => panic!("Unsupported input format for {:?}", path),
+    };
+    let trimmed = text.trim_start();
+    if trimmed.starts_with("<svg") || trimmed.starts_with("<?xml") {
+        panic!(
+            "Unsupported input format for {:?}: svg input is not supported",
+            path
+        );
+    }
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        if let Ok(json) = serde_json::from_str::<Value>(&text) {
+            if json.get("frames").is_some() {
+                return InputFormat::Cells(json);
+            }
+            panic!(
+                "Unsupported input format for {:?}: only json written with --json-format cells can be used as input",
+                path
+            );
+        }
+    }
+    InputFormat::Text(text)
+}
+
+pub fn read_frames(
+    path: &Path,
+    format: &InputFormat,
+    max_frames: Option<usize>,
+    max_pixels: Option<u64>,
+    strict: bool,
+) -> Vec<DynamicImage> {
+    let reader = || BufReader::new(File::open(path).unwrap());
+    match format {
+        InputFormat::Animation(ImageFormat::Gif) => {
+            read_gif_with_limits(path, max_frames, max_pixels, strict)
+        }
+        InputFormat::Animation(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(reader()).unwrap();
+            let (width, height) = decoder.dimensions();
+            check_pixel_limit(width, height, max_pixels);
+            collect_frames(decoder.apng().into_frames(), max_frames, strict)
+        }
+        InputFormat::Animation(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(reader()).unwrap();
+            let (width, height) = decoder.dimensions();
+            check_pixel_limit(width, height, max_pixels);
+            collect_frames(decoder.into_frames(), max_frames, strict)
+        }
+        InputFormat::Animation(format) | InputFormat::Still(format) => {
+            let (width, height) = Reader::with_format(reader(), *format)
+                .into_dimensions()
+                .unwrap();
+            check_pixel_limit(width, height, max_pixels);
+            let img = Reader::with_format(reader(), *format)
+                .decode()
+                .unwrap_or_else(|err| panic!("Error decoding {:?}: {}", path, err));
+            vec![img]
+        }
+        InputFormat::Cells(_) | InputFormat::Text(_) => {
+            panic!("{:?} is not an image", path)
+        }
+    }
+}
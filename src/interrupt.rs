@@ -0,0 +1,31 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// ctrl-c during a long stage of a conversion stops that stage early, so that the frames finished
+// so far can still be written out instead of leaving a missing or corrupt output; a second ctrl-c
+// before the first one has been handled exits right away
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // only async-signal-safe calls can be made here
+        unsafe { libc::_exit(130) };
+    }
+}
+
+pub fn catch_interrupts() {
+    #[cfg(unix)]
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_interrupt;
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn take_interrupt() -> bool {
+    // handles the interruption, so that the next stage runs to completion
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
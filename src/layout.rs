@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::Path;
+
+pub struct Band {
+    // the number of rows of the band, or None for the rows left over by the other bands
+    pub rows: Option<usize>,
+    pub font: String,
+    // an integer factor the glyphs of the font are enlarged by
+    pub scale: u32,
+}
+
+pub fn parse_layout(layout: &str) -> Vec<Band> {
+    // one band per line from the top of the output down, as "<rows> <font> [scale=<factor>]", with
+    // * as the rows of at most one band which fills the rest of the output; blank lines and lines
+    // starting with # are skipped
+    let bands: Vec<Band> = layout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let unsupported = || -> ! { panic!("Unsupported layout band {:?}", line) };
+            let rows = match fields[0] {
+                "*" => None,
+                rows => Some(
+                    rows.parse::<usize>()
+                        .ok()
+                        .filter(|&rows| rows > 0)
+                        .unwrap_or_else(|| unsupported()),
+                ),
+            };
+            let font = fields.get(1).unwrap_or_else(|| unsupported()).to_string();
+            let mut band = Band {
+                rows,
+                font,
+                scale: 1,
+            };
+            for option in &fields[2..] {
+                let (key, value) = option.split_once('=').unwrap_or_else(|| unsupported());
+                match key {
+                    "scale" => {
+                        band.scale = value
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|&scale| scale > 0)
+                            .unwrap_or_else(|| unsupported())
+                    }
+                    _ => panic!("Unsupported layout option {:?}", key),
+                }
+            }
+            band
+        })
+        .collect();
+    if bands.is_empty() {
+        panic!("The layout has no bands");
+    }
+    if bands.iter().filter(|band| band.rows.is_none()).count() > 1 {
+        panic!("Only one band of the layout can fill the rest of the output");
+    }
+    bands
+}
+
+pub fn read_layout(path: &Path) -> Vec<Band> {
+    parse_layout(&fs::read_to_string(path).unwrap())
+}
+
+pub fn band_heights(cell_heights: &[usize], rows: &[Option<usize>], height: usize) -> Vec<usize> {
+    // the rows of every band, for an output of the given height in pixels: bands with a fixed
+    // number of rows keep it while it fits, and the filling band gets the rest
+    let fixed: usize = cell_heights
+        .iter()
+        .zip(rows)
+        .map(|(cell_height, rows)| cell_height * rows.unwrap_or(0))
+        .sum();
+    let mut left = height;
+    cell_heights
+        .iter()
+        .zip(rows)
+        .map(|(&cell_height, rows)| {
+            let rows = match rows {
+                Some(rows) => (*rows).min(left / cell_height),
+                None => height.saturating_sub(fixed) / cell_height,
+            };
+            left = left.saturating_sub(rows * cell_height);
+            rows
+        })
+        .collect()
+}
@@ -1,6 +1,31 @@
+pub mod archive;
+pub mod base64;
+pub mod binary;
+pub mod canvas;
+pub mod cells;
+pub mod color;
 pub mod convert;
+pub mod demo;
+pub mod diff;
+#[cfg(feature = "focus-faces")]
+pub mod faces;
 pub mod font;
 pub mod gif;
+pub mod html;
+pub mod image;
+pub mod input;
+pub mod interrupt;
+pub mod layout;
+pub mod links;
+pub mod logging;
+pub mod meta;
 pub mod metrics;
+pub mod parallel;
+pub mod png;
 pub mod progress;
-pub mod image;
\ No newline at end of file
+pub mod random;
+pub mod registry;
+pub mod rules;
+pub mod selftest;
+pub mod terminal;
+pub mod width;
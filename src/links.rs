@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::Path;
+
+use crate::convert::escape_html;
+
+pub struct Link {
+    pub col: usize,
+    pub row: usize,
+    pub width: usize,
+    pub height: usize,
+    pub url: String,
+}
+
+pub enum LinkFormat {
+    Terminal,
+    Html,
+}
+
+pub fn parse_link_map(map: &str) -> Vec<Link> {
+    // one region per line, as "<col> <row> <width> <height> <url>" in cells of the output; blank
+    // lines and lines starting with # are skipped
+    map.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.splitn(5, char::is_whitespace).collect();
+            let number = |i: usize| {
+                fields
+                    .get(i)
+                    .and_then(|field| field.parse::<usize>().ok())
+                    .unwrap_or_else(|| panic!("Unsupported link map line {:?}", line))
+            };
+            let url = fields
+                .get(4)
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .unwrap_or_else(|| panic!("Unsupported link map line {:?}", line));
+            Link {
+                col: number(0),
+                row: number(1),
+                width: number(2),
+                height: number(3),
+                url,
+            }
+        })
+        .collect()
+}
+
+pub fn read_link_map(path: &Path) -> Vec<Link> {
+    parse_link_map(&fs::read_to_string(path).unwrap())
+}
+
+pub fn link_grid(links: &[Link], n_cols: usize, n_rows: usize) -> Vec<Vec<Option<&str>>> {
+    // the url of each cell; regions later in the map are drawn over earlier ones
+    let mut grid = vec![vec![None; n_cols]; n_rows];
+    for link in links {
+        for row in grid.iter_mut().skip(link.row).take(link.height) {
+            for cell in row.iter_mut().skip(link.col).take(link.width) {
+                *cell = Some(link.url.as_str());
+            }
+        }
+    }
+    grid
+}
+
+fn open_link(url: &str, format: &LinkFormat) -> String {
+    match format {
+        LinkFormat::Terminal => format!("\x1b]8;;{}\x1b\\", url),
+        LinkFormat::Html => format!("<a href=\"{}\">", escape_html(url).replace('"', "&quot;")),
+    }
+}
+
+fn close_link(format: &LinkFormat) -> &'static str {
+    match format {
+        LinkFormat::Terminal => "\x1b]8;;\x1b\\",
+        LinkFormat::Html => "</a>",
+    }
+}
+
+pub fn join_linked_cell_strings(
+    cell_strings: &[String],
+    n_cols: usize,
+    links: &[Link],
+    format: &LinkFormat,
+) -> String {
+    // like join_cell_strings, but runs of cells in the same region are wrapped in a link, which
+    // is closed at the end of each line
+    let grid = link_grid(links, n_cols, cell_strings.len() / n_cols);
+    cell_strings
+        .chunks(n_cols)
+        .zip(grid)
+        .map(|(row, row_links)| {
+            let mut line = String::new();
+            let mut current = None;
+            for (s, url) in row.iter().zip(row_links) {
+                if url != current {
+                    if current.is_some() {
+                        line += close_link(format);
+                    }
+                    if let Some(url) = url {
+                        line += &open_link(url, format);
+                    }
+                    current = url;
+                }
+                line += s;
+            }
+            if current.is_some() {
+                line += close_link(format);
+            }
+            line
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
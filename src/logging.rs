@@ -0,0 +1,72 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde_json::json;
+
+// context which is added to every log line: the stage of the conversion and, while frames are
+// being processed one at a time, the index of the current frame
+static STAGE: Mutex<&str> = Mutex::new("setup");
+static FRAME: AtomicUsize = AtomicUsize::new(NO_FRAME);
+const NO_FRAME: usize = usize::MAX;
+
+pub fn set_stage(stage: &'static str) {
+    *STAGE.lock().unwrap() = stage;
+}
+
+pub fn set_frame(frame: Option<usize>) {
+    FRAME.store(frame.unwrap_or(NO_FRAME), Ordering::Relaxed);
+}
+
+fn frame() -> Option<usize> {
+    match FRAME.load(Ordering::Relaxed) {
+        NO_FRAME => None,
+        frame => Some(frame),
+    }
+}
+
+pub fn init(json: bool) {
+    // RUST_LOG still picks what is logged, and warnings are shown when it isn't set; --log-json
+    // only changes the format to one json object per line, with "name\tvalue" messages split
+    // into fields
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    if json {
+        builder.format(|buf, record| {
+            let message = record.args().to_string();
+            let mut line = json!({
+                "time": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "stage": *STAGE.lock().unwrap(),
+                "message": message,
+            });
+            if let Some(frame) = frame() {
+                line["frame"] = json!(frame);
+            }
+            if let Some((name, value)) = message.split_once('\t') {
+                line["name"] = json!(name);
+                line["value"] = json!(value);
+            }
+            writeln!(buf, "{}", line)
+        });
+    } else {
+        builder.format(|buf, record| {
+            let stage = *STAGE.lock().unwrap();
+            let context = match frame() {
+                Some(frame) => format!("{} frame {}", stage, frame),
+                None => stage.to_string(),
+            };
+            writeln!(
+                buf,
+                "[{} {:<5} {} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                context,
+                record.args()
+            )
+        });
+    }
+    builder.init();
+}
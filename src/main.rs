@@ -1,212 +1,2760 @@
-use crate::convert::get_converter;
-use crate::convert::{
-    char_rows_to_bitmap, char_rows_to_color_bitmap, char_rows_to_html_color_string,
-    char_rows_to_string, char_rows_to_terminal_color_string,
+use img_to_ascii::archive::{archive_entry, extract_archive, read_archive, write_archive};
+use img_to_ascii::base64;
+use img_to_ascii::binary::{self, get_binary_format, get_compression};
+use img_to_ascii::canvas::{parse_canvas_size, place_on_canvas};
+use img_to_ascii::convert::get_converter;
+use img_to_ascii::convert::{
+    char_rows_to_cell_strings, char_rows_to_color_bitmap_with_background,
+    char_rows_to_html_color_cells, char_rows_to_html_color_string, char_rows_to_string,
+    char_rows_to_terminal_color_cells, char_rows_to_terminal_color_string,
+    combine_stereo_char_rows, content_bounds, crop_cells, crop_char_rows, crop_img_to_cells,
+    escape_html, get_edge_color, get_grid, get_render_mode, gradient_angle_img,
+    gradient_magnitude_img, join_cell_strings, offset_cell_rows, shade_char_rows,
+    trim_trailing_whitespace, union_bounds, with_terminal_background, EdgeColor, Grid, RenderMode,
+    SHADES,
 };
-use crate::font::Font;
-use crate::gif::write_gif;
-use crate::progress::default_progress_bar;
-
-use ::image::DynamicImage;
-use clap::Parser;
-use convert::get_conversion_algorithm;
-use image::LumaImage;
+use img_to_ascii::demo::{demo_frames, get_demo, Demo};
+use img_to_ascii::diff::{diff_char_rows, diff_to_terminal_string, text_to_char_rows};
+#[cfg(feature = "focus-faces")]
+use img_to_ascii::faces;
+use img_to_ascii::font::{
+    get_glyph_style, missing_chars, resize_chars, Character, Font, FontOptions,
+};
+use img_to_ascii::gif::{
+    read_gif_from_stream, transcode_ascii, write_gif, write_gif_to_stream, write_gif_with_comment,
+    write_gif_with_global_palette, TranscodeOptions,
+};
+use img_to_ascii::input::{read_frames, sniff_input_format, InputFormat};
+use img_to_ascii::interrupt;
+use img_to_ascii::layout::{band_heights, read_layout, Band};
+use img_to_ascii::links::{join_linked_cell_strings, read_link_map, LinkFormat};
+use img_to_ascii::logging;
+use img_to_ascii::meta::{
+    file_crc32, gif_comment, read_embedded_meta, read_sidecar, source_status, with_output_size,
+    write_sidecar,
+};
+use img_to_ascii::parallel::{par_map, set_threads};
+use img_to_ascii::png::{parse_poster_pages, write_char_rows_png, write_png, write_poster};
+use img_to_ascii::progress::default_progress_bar;
+use img_to_ascii::random;
+use img_to_ascii::rules::{apply_rules, read_rules, RuleConversion};
+use img_to_ascii::selftest::{
+    check_cells_json, check_converters, check_font, widest_alphabet, Check,
+};
+
+use ::image::imageops::FilterType;
+use ::image::{DynamicImage, ImageFormat, ImageOutputFormat, Rgb, RgbImage};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueSource};
+use img_to_ascii::cells::{
+    add_confidence, add_intensity, cell_size_from_json, cells_from_json, cells_to_char_rows,
+    cells_to_color_img, cells_to_json, cells_to_planes_json, char_rows_to_cells, fix_wide_cells,
+    get_resample, resample_cells, Cell, CellRows, Resample,
+};
+use img_to_ascii::color::{
+    anaglyph_img, contrast_img, cycle_color_frames, get_color_vision, get_palette,
+    horizontal_gradient_img, ink_color, kmeans_palette, parse_hex_color, parse_hex_colors,
+    quantize_img, scene_cuts, simulate_color_vision, PAPER_MIN_CONTRAST,
+};
+use img_to_ascii::convert::{
+    self, char_rows_in_reading_order, get_conversion_algorithm, get_grid_flip, get_grid_rotation,
+    get_text_direction, rotate_char_rows_ccw, text_to_renderable_char_rows, transform_cells,
+    transform_char_rows, transform_img, TextDirection,
+};
+use img_to_ascii::convert::{
+    char_rows_error, confidence_heatmap, error_heatmap, fill_img_cells, get_reveal_order,
+    get_scorer, get_tie_break, intensity_to_char, render_char_rows, reveal_char_rows,
+    sign_char_rows, suggest_width, CharConfidence, CharSelection, TieBreak,
+};
+use img_to_ascii::html::{write_html, HtmlWriter};
+use img_to_ascii::image::{
+    self, composite_layer, get_resize_filter, parse_layer, Image, Layer, LumaImage, ResizeFilter,
+};
+use img_to_ascii::registry;
+use img_to_ascii::terminal::locale_supports_utf8;
 use indicatif::ProgressIterator;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::env::temp_dir;
 use std::fs;
+use std::io::{self, Cursor, Read};
+use std::panic;
 use std::path::Path;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
-use log::info;
+use log::info;
+
+#[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(required_unless_present = "stdio-json")]
+    image_path: Option<String>,
+    #[clap(short, long, default_value_t = String::from("bitocra-13"))]
+    font: String,
+    #[clap(short, long, default_value_t = String::from("alphabet"))]
+    alphabet: String,
+    #[clap(short, long)]
+    width: Option<usize>,
+    #[clap(short, long, default_value_t = String::from("direction-and-intensity"))]
+    metric: String,
+    #[clap(long)]
+    no_color: bool,
+    #[clap(short, long, default_value_t = 0.0)]
+    brightness_offset: f32,
+    #[clap(short, long)]
+    out_path: Option<String>,
+    #[clap(long, default_value_t = 30.0)]
+    fps: f64,
+    #[clap(short, long, default_value_t = String::from("edge-augmented"))]
+    conversion_algorithm: String,
+    #[clap(long)]
+    trim: bool,
+    #[clap(long, conflicts_with = "width")]
+    auto_width: bool,
+    #[clap(
+        long,
+        require_equals = true,
+        min_values = 0,
+        default_missing_value = "0.05"
+    )]
+    autocrop: Option<f32>,
+    #[clap(
+        long,
+        require_equals = true,
+        min_values = 0,
+        default_missing_value = "0.1"
+    )]
+    match_histogram: Option<f32>,
+    #[clap(long)]
+    focus_faces: bool,
+    #[clap(long, default_value_t = 0.0)]
+    glyph_tolerance: f32,
+    #[clap(long)]
+    prefer_ascii: bool,
+    #[clap(long)]
+    safe_chars: bool,
+    #[clap(long)]
+    require_full_alphabet: bool,
+    #[clap(long, default_value_t = String::from("filled"))]
+    glyph_style: String,
+    #[clap(long, default_value_t = String::from("horizontal-tb"))]
+    direction: String,
+    #[clap(long)]
+    flip_output: Option<String>,
+    #[clap(long)]
+    rotate_output: Option<u32>,
+    #[clap(long)]
+    mirror_glyphs: bool,
+    #[clap(long)]
+    gradient: Option<String>,
+    #[clap(long, default_value_t = String::from("html"))]
+    json_format: String,
+    #[clap(long)]
+    compress: Option<String>,
+    #[clap(long)]
+    archive_bitmaps: bool,
+    #[clap(long)]
+    auto_palette: Option<usize>,
+    #[clap(long)]
+    palette: Option<String>,
+    #[clap(long)]
+    simulate: Option<String>,
+    #[clap(long)]
+    gif_global_palette: bool,
+    #[clap(long)]
+    max_frames: Option<usize>,
+    #[clap(long)]
+    max_pixels: Option<u64>,
+    #[clap(long)]
+    strict: bool,
+    #[clap(long)]
+    confidence: bool,
+    #[clap(long)]
+    confidence_map: Option<String>,
+    #[clap(long)]
+    error_map: Option<String>,
+    #[clap(long, default_value_t = String::from("stable"))]
+    tie_break: String,
+    #[clap(long)]
+    seed: Option<u64>,
+    #[clap(long, default_value_t = 0.001)]
+    tie_epsilon: f32,
+    #[clap(long, default_value_t = 0.)]
+    repetition_penalty: f32,
+    #[clap(long)]
+    reveal: Option<String>,
+    #[clap(long, default_value_t = String::from("3s"))]
+    reveal_duration: String,
+    #[clap(long, conflicts_with = "reveal")]
+    color_cycle: Option<String>,
+    #[clap(long, default_value_t = String::from("3s"))]
+    cycle_duration: String,
+    #[clap(long)]
+    signature: Option<String>,
+    #[clap(long, default_value_t = String::from("#ffffff"))]
+    signature_color: String,
+    #[clap(long)]
+    inline_playback: bool,
+    #[clap(long)]
+    poster: Option<String>,
+    #[clap(long)]
+    pixel_art: bool,
+    #[clap(
+        long,
+        require_equals = true,
+        min_values = 0,
+        default_missing_value = "0.3"
+    )]
+    scene_cuts: Option<f32>,
+    #[clap(long)]
+    stdio_json: bool,
+    #[clap(long, default_value_t = String::from("source"))]
+    edge_color: String,
+    #[clap(long, conflicts_with = "edge-color")]
+    edge_shading: bool,
+    #[clap(long, default_value_t = 0)]
+    chunk_overlap: usize,
+    #[clap(long, default_value_t = String::from("square"))]
+    grid: String,
+    #[clap(long)]
+    layout: Option<String>,
+    #[clap(long)]
+    canvas: Option<String>,
+    #[clap(long)]
+    overlay_safe_area: bool,
+    #[clap(long)]
+    safe_area_guides: bool,
+    #[clap(long)]
+    cell_size: Option<String>,
+    #[clap(long, default_value_t = String::from("resample"))]
+    fit: String,
+    #[clap(long)]
+    log_json: bool,
+    #[clap(long, default_value_t = 0)]
+    threads: usize,
+    #[clap(long)]
+    checkpoint: Option<String>,
+    #[clap(long, requires = "checkpoint")]
+    resume: bool,
+    #[clap(long)]
+    preview_every: Option<usize>,
+    #[clap(long, requires = "preview-every")]
+    preview_path: Option<String>,
+    #[clap(long)]
+    links: Option<String>,
+    #[clap(long)]
+    anaglyph: bool,
+    #[clap(long, requires = "anaglyph")]
+    right_image: Option<String>,
+    #[clap(long)]
+    layer: Vec<String>,
+    #[clap(long)]
+    depth: Option<String>,
+    #[clap(long, default_value_t = 0.5)]
+    depth_strength: f32,
+    #[clap(long, default_value_t = String::from("match"))]
+    mode: String,
+    #[clap(long)]
+    shade_fg: Option<String>,
+    #[clap(long)]
+    shade_bg: Option<String>,
+    #[clap(long, default_value_t = String::from("bilinear"))]
+    resize: String,
+    #[clap(long, conflicts_with_all = &["pixel-art", "metric", "conversion-algorithm"])]
+    preserve_text: bool,
+    #[clap(long, conflicts_with_all = &["confidence", "confidence-map"])]
+    rules: Option<String>,
+    #[clap(long, conflicts_with = "shade-bg")]
+    paper: Option<String>,
+    #[clap(long)]
+    min_contrast: Option<f32>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage fonts from the font registry
+    Font {
+        #[clap(subcommand)]
+        command: FontCommand,
+    },
+    /// Inspect alphabets
+    Alphabet {
+        #[clap(subcommand)]
+        command: AlphabetCommand,
+    },
+    /// Compare two ascii art text files cell by cell and report their similarity. Images are
+    /// converted with the default settings before comparing.
+    Diff {
+        a: String,
+        b: String,
+        #[clap(short, long)]
+        width: Option<usize>,
+        /// Exit with an error if the similarity percentage is below this value
+        #[clap(long)]
+        min_similarity: Option<f64>,
+    },
+    /// Re-render the cells json output of a previous conversion (--json-format cells) with a
+    /// different font, scale, or colors, without needing the original image
+    ReRender {
+        cells_path: String,
+        #[clap(short, long)]
+        out_path: String,
+        #[clap(short, long, default_value_t = String::from("bitocra-13"))]
+        font: String,
+        /// Integer factor to enlarge the rendered bitmap by
+        #[clap(long, default_value_t = 1)]
+        scale: u32,
+        /// Hex color to use for every character instead of the stored colors
+        #[clap(long)]
+        color: Option<String>,
+        /// Hex background color
+        #[clap(long)]
+        background: Option<String>,
+        #[clap(long, default_value_t = 30.0)]
+        fps: f64,
+        /// How to resample the grid when the font's cell aspect ratio differs from the original
+        /// font's: intensity, nearest, or none
+        #[clap(long, default_value_t = String::from("intensity"))]
+        resample: String,
+        /// Alphabet to swap in, for cells json converted with --metric intensity, which stores
+        /// the brightness of every cell
+        #[clap(short, long)]
+        alphabet: Option<String>,
+    },
+    /// Show the settings an output was made with: from the .meta.json sidecar written next to
+    /// .html and .json outputs, or from the metadata inside .ascz, .gif, and .png outputs
+    Info { path: String },
+    /// Unpack the frames and metadata of an .ascz archive into a directory
+    Extract {
+        archive_path: String,
+        #[clap(short, long)]
+        out_path: String,
+    },
+    /// Play the frames of an .ascz archive in the console
+    Play {
+        archive_path: String,
+        /// Frame rate to play at, instead of the one the archive was made with
+        #[clap(long)]
+        fps: Option<f64>,
+    },
+    /// Generate a procedural animation (plasma, tunnel, or fire) and convert it, as a showcase
+    /// and a reproducible stress test of the animation pipeline
+    Demo {
+        name: String,
+        #[clap(long, default_value_t = String::from("5s"))]
+        duration: String,
+        /// Path to write the output to (.gif); otherwise it is played once in the console
+        #[clap(short, long)]
+        out_path: Option<String>,
+        #[clap(short, long, default_value_t = 80)]
+        width: usize,
+        #[clap(long, default_value_t = 30.0)]
+        fps: f64,
+        #[clap(short, long, default_value_t = String::from("bitocra-13"))]
+        font: String,
+        /// Seed for the random flicker of the fire demo
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Cross-check the converters against their scores, load every embedded font with every
+    /// embedded alphabet, and round trip cells json, reporting any mismatch on this machine
+    SelfTest {
+        /// Number of random chunks to convert per metric and font
+        #[clap(long, default_value_t = 1000)]
+        chunks: usize,
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum FontCommand {
+    /// Download a registry font so it can be used by name with --font
+    Fetch { name: String },
+    /// List the fonts available in the registry
+    List,
+}
+
+#[derive(Subcommand)]
+enum AlphabetCommand {
+    /// Print an alphabet's characters from darkest to brightest as drawn by a font, with their
+    /// intensities normalized so that the brightest one is 1
+    Rank {
+        font: String,
+        alphabet: String,
+        /// Path to write the sorted characters to, for use as an --alphabet file
+        #[clap(short, long)]
+        out_path: Option<String>,
+    },
+}
+
+fn run_command(command: Command) {
+    match command {
+        Command::Font {
+            command: FontCommand::Fetch { name },
+        } => {
+            let path = registry::fetch_font(&name);
+            println!("fetched {} to {}", name, path.to_str().unwrap());
+        }
+        Command::Font {
+            command: FontCommand::List,
+        } => {
+            for font in &registry::REGISTRY {
+                let status = if registry::cached_font_path(font.name).is_some() {
+                    "fetched"
+                } else {
+                    "available"
+                };
+                println!("{}\t{}\t{}\t{}", font.name, font.license, status, font.url);
+            }
+        }
+        Command::Alphabet {
+            command:
+                AlphabetCommand::Rank {
+                    font,
+                    alphabet,
+                    out_path,
+                },
+        } => {
+            let font = load_font(&font, &load_alphabet(&alphabet), &FontOptions::default());
+            let ranking = font.intensity_ranking();
+            for (c, intensity) in &ranking {
+                let bar = "█".repeat((intensity * 40.).round() as usize);
+                println!("{:?}\t{:.3}\t{}", c, intensity, bar);
+            }
+            if let Some(out_path) = out_path {
+                let ramp: String = ranking.iter().map(|(c, _)| c).collect();
+                fs::write(out_path, ramp).unwrap();
+            }
+        }
+        Command::Diff {
+            a,
+            b,
+            width,
+            min_similarity,
+        } => {
+            let a = read_char_rows(Path::new(&a), width);
+            let b = read_char_rows(Path::new(&b), width);
+            let diff = diff_char_rows(&a, &b);
+            println!("{}", diff_to_terminal_string(&diff));
+            let similarity = diff.similarity() * 100.;
+            println!(
+                "similarity: {:.2}% ({} of {} cells differ)",
+                similarity, diff.n_different, diff.n_cells
+            );
+            if let Some(min_similarity) = min_similarity {
+                if similarity < min_similarity {
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::ReRender {
+            cells_path,
+            out_path,
+            font,
+            scale,
+            color,
+            background,
+            fps,
+            resample,
+            alphabet,
+        } => re_render(
+            Path::new(&cells_path),
+            Path::new(&out_path),
+            &font,
+            scale,
+            color.as_ref().map(|color| parse_hex_color(color)),
+            background
+                .as_ref()
+                .map_or(Rgb([0, 0, 0]), |color| parse_hex_color(color)),
+            fps,
+            get_resample(&resample),
+            alphabet.as_deref(),
+        ),
+        Command::Info { path } => {
+            let path = Path::new(&path);
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            let meta: Value = match extension {
+                Some("ascz") => {
+                    serde_json::from_slice(archive_entry(&read_archive(path), "meta.json")).unwrap()
+                }
+                Some("gif") | Some("png") => read_embedded_meta(path)
+                    .unwrap_or_else(|| panic!("{:?} has no embedded metadata", path)),
+                _ => read_sidecar(path),
+            };
+            println!(
+                "source\t{} ({})",
+                meta["source"].as_str().unwrap_or("unknown"),
+                source_status(&meta)
+            );
+            println!("font\t{}", meta["font"].as_str().unwrap_or("unknown"));
+            println!(
+                "alphabet\t{}",
+                meta["alphabet"].as_str().unwrap_or("unknown")
+            );
+            println!(
+                "alphabet chars\t[{}]",
+                meta["alphabet_chars"].as_str().unwrap_or("")
+            );
+            println!(
+                "size\t{}x{} cells, {} frames",
+                meta["columns"], meta["rows"], meta["frames"]
+            );
+            println!("metric\t{}", meta["metric"].as_str().unwrap_or("unknown"));
+            if let Some(seed) = meta["seed"].as_u64() {
+                println!("seed\t{}", seed);
+            }
+            println!("version\t{}", meta["version"].as_str().unwrap_or("unknown"));
+            let command: Vec<String> = meta["command"]
+                .as_array()
+                .map(|args| {
+                    args.iter()
+                        .filter_map(|arg| arg.as_str())
+                        .map(|arg| {
+                            if arg.contains(char::is_whitespace) {
+                                format!("{:?}", arg)
+                            } else {
+                                arg.to_string()
+                            }
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            println!("command\timage-to-ascii {}", command.join(" "));
+        }
+        Command::Demo {
+            name,
+            duration,
+            out_path,
+            width,
+            fps,
+            font,
+            seed,
+        } => demo(
+            &get_demo(&name),
+            parse_duration(&duration),
+            out_path.as_ref().map(Path::new),
+            width,
+            fps,
+            &font,
+            seed,
+        ),
+        Command::SelfTest { chunks, seed } => self_test(chunks, seed),
+        Command::Extract {
+            archive_path,
+            out_path,
+        } => {
+            let n_files = extract_archive(Path::new(&archive_path), Path::new(&out_path));
+            println!("extracted {} files to {}", n_files, out_path);
+        }
+        Command::Play { archive_path, fps } => play(Path::new(&archive_path), fps),
+    }
+}
+
+fn crop_frames(frames: &[DynamicImage], bounds: (usize, usize, usize, usize)) -> Vec<DynamicImage> {
+    let (x0, y0, x1, y1) = bounds;
+    frames
+        .iter()
+        .map(|frame| frame.crop_imm(x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32))
+        .collect()
+}
+
+#[cfg(feature = "focus-faces")]
+fn face_bounds(frames: &[DynamicImage]) -> Option<(usize, usize, usize, usize)> {
+    // frame all the faces found in any frame, so animated output keeps a constant size
+    let faces: Vec<faces::Face> = frames.iter().flat_map(faces::detect_faces).collect();
+    info!("faces\t{:?}", faces);
+    let (width, height) = (frames[0].width() as usize, frames[0].height() as usize);
+    let bounds = faces::face_bounds(&faces, width, height);
+    if bounds.is_none() {
+        eprintln!("warning: no faces were found, so the whole image is converted");
+    }
+    info!("face bounds\t{:?}", bounds);
+    bounds
+}
+
+#[cfg(not(feature = "focus-faces"))]
+fn face_bounds(_frames: &[DynamicImage]) -> Option<(usize, usize, usize, usize)> {
+    unreachable!()
+}
+
+fn play(archive_path: &Path, fps: Option<f64>) {
+    let entries = read_archive(archive_path);
+    let meta: Value = serde_json::from_slice(archive_entry(&entries, "meta.json")).unwrap();
+    let fps = fps.or(meta["fps"].as_f64()).unwrap_or(30.);
+    let cells: Value = serde_json::from_slice(archive_entry(&entries, "cells.json")).unwrap();
+    let out_frames: Vec<String> = cells_from_json(&cells)
+        .iter()
+        .map(|cells| {
+            char_rows_to_terminal_color_string(
+                &cells_to_char_rows(cells),
+                &cells_to_color_img(cells),
+            )
+        })
+        .collect();
+    if out_frames.len() == 1 {
+        println!("{}", out_frames[0]);
+        return;
+    }
+    loop {
+        for frame in &out_frames {
+            let t0 = Instant::now();
+            println!("{}[2J{}", 27 as char, frame);
+            let delay = (1.0 / fps) - t0.elapsed().as_secs_f64();
+            if delay > 0.0 {
+                sleep(Duration::from_secs_f64(delay));
+            }
+        }
+    }
+}
+
+fn self_test(n_chunks: usize, seed: u64) {
+    let mut checks: Vec<Check> = fonts()
+        .iter()
+        .map(|(name, data)| check_font(name, data, &alphabets()))
+        .collect();
+    for (name, data) in fonts() {
+        let chars = Font::read_bdf_chars(data.as_bytes());
+        let font = Font::new(&chars, &widest_alphabet(&chars, &alphabets()));
+        for mut check in check_converters(&font, n_chunks, seed) {
+            check.name = format!("{} ({})", check.name, name);
+            checks.push(check);
+        }
+    }
+    let font = load_font(
+        "bitocra-13",
+        &load_alphabet("alphabet"),
+        &FontOptions::default(),
+    );
+    checks.push(check_cells_json(&font, 3, seed));
+
+    for check in &checks {
+        let status = if !check.passed() {
+            "FAIL"
+        } else if check.exact {
+            "ok"
+        } else {
+            "approx"
+        };
+        println!(
+            "{}\t{}\t{} of {} mismatched",
+            status, check.name, check.mismatches, check.total
+        );
+    }
+    let n_failed = checks.iter().filter(|check| !check.passed()).count();
+    println!("{} of {} checks failed", n_failed, checks.len());
+    if n_failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn demo(
+    demo: &Demo,
+    duration: f64,
+    out_path: Option<&Path>,
+    width: usize,
+    fps: f64,
+    font_str: &str,
+    seed: u64,
+) {
+    let font = load_font(
+        font_str,
+        &load_alphabet("alphabet"),
+        &FontOptions::default(),
+    );
+    // one pixel per glyph pixel, in a 4:3 frame
+    let (img_width, img_height) = (width * font.width, width * font.width * 3 / 4);
+    let n_frames = ((duration * fps).round() as usize).max(1);
+
+    let t0 = Instant::now();
+    let frames = demo_frames(
+        demo,
+        img_width as u32,
+        img_height as u32,
+        n_frames,
+        fps,
+        seed,
+    );
+    let generate_time = t0.elapsed().as_secs_f64();
+
+    let t0 = Instant::now();
+    let progress = default_progress_bar("Frames", frames.len());
+    let frame_char_rows: Vec<Vec<Vec<char>>> = frames
+        .iter()
+        .progress_with(progress)
+        .map(|frame| {
+            convert::img_to_char_rows(
+                &font,
+                &LumaImage::from(frame),
+                get_converter("direction-and-intensity"),
+                Some(width),
+                0.,
+                &get_conversion_algorithm("edge-augmented"),
+                None,
+            )
+        })
+        .collect();
+    let convert_time = t0.elapsed().as_secs_f64();
+    eprintln!(
+        "generated {} {}x{} frames in {:.2}s, converted them in {:.2}s ({:.1} frames per second)",
+        n_frames,
+        img_width,
+        img_height,
+        generate_time,
+        convert_time,
+        n_frames as f64 / convert_time
+    );
+
+    if let Some(path) = out_path {
+        let out_frames: Vec<DynamicImage> = frame_char_rows
+            .iter()
+            .zip(&frames)
+            .map(|(char_rows, frame)| render_char_rows(char_rows, &font, frame, true))
+            .collect();
+        write_gif(path, &out_frames, fps);
+    } else {
+        for (char_rows, frame) in frame_char_rows.iter().zip(&frames) {
+            let t0 = Instant::now();
+            println!(
+                "{}[2J{}",
+                27 as char,
+                char_rows_to_terminal_color_string(char_rows, frame)
+            );
+            let delay = (1.0 / fps) - t0.elapsed().as_secs_f64();
+            if delay > 0.0 {
+                sleep(Duration::from_secs_f64(delay));
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn re_render(
+    cells_path: &Path,
+    out_path: &Path,
+    font_str: &str,
+    scale: u32,
+    color: Option<Rgb<u8>>,
+    background: Rgb<u8>,
+    fps: f64,
+    resample: Option<Resample>,
+    alphabet: Option<&str>,
+) {
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(cells_path).unwrap()).unwrap();
+    let mut frames = cells_from_json(&json);
+    if let Some(alphabet) = alphabet {
+        // only the cheap intensity lookup is repeated, so no image or conversion is needed
+        let lookup_font = load_font(font_str, &load_alphabet(alphabet), &FontOptions::default());
+        let mut n_swapped = 0;
+        for cell in frames.iter_mut().flatten().flatten() {
+            if let Some(intensity) = cell.intensity {
+                cell.value = intensity_to_char(&lookup_font, intensity);
+                n_swapped += 1;
+            }
+        }
+        if n_swapped == 0 {
+            panic!(
+                "{:?} has no stored intensities; convert with --metric intensity and \
+                 --json-format cells to swap its alphabet",
+                cells_path
+            );
+        }
+        info!("swapped cells\t{}", n_swapped);
+        for cells in frames.iter_mut() {
+            fix_wide_cells(cells);
+        }
+    }
+    if let Some(Rgb([r, g, b])) = color {
+        for cell in frames.iter_mut().flatten().flatten() {
+            cell.color = [r, g, b, cell.color[3]];
+        }
+    }
+
+    let mut alphabet: Vec<char> = frames.iter().flatten().flatten().map(|c| c.value).collect();
+    alphabet.push(' ');
+    alphabet.sort();
+    alphabet.dedup();
+    let font = load_font(font_str, &alphabet, &FontOptions::default());
+
+    // keep the proportions of the original by changing the number of rows for fonts with a
+    // different cell aspect ratio
+    if let (Some((cell_width, cell_height)), Some(resample)) =
+        (cell_size_from_json(&json), resample)
+    {
+        let (n_cols, n_rows) = (frames[0][0].len(), frames[0].len());
+        let aspect = cell_height as f32 / cell_width as f32;
+        let font_aspect = font.height as f32 / font.width as f32;
+        let out_rows = ((n_rows as f32 * aspect / font_aspect).round() as usize).max(1);
+        if out_rows != n_rows {
+            info!("resampled rows\t{} -> {}", n_rows, out_rows);
+            let intensity = |c: char| font.char_map.get(&c).map_or(0., |c| c.intensity);
+            frames = frames
+                .iter()
+                .map(|cells| resample_cells(cells, n_cols, out_rows, &resample, intensity))
+                .collect();
+        }
+    }
+
+    if out_path.extension().unwrap() == "json" {
+        fs::write(
+            out_path,
+            serde_json::to_string(&cells_to_json(&frames, Some((font.width, font.height))))
+                .unwrap(),
+        )
+        .unwrap();
+        return;
+    }
+    if out_path.extension().unwrap() == "png" {
+        let cells = &frames[0];
+        let char_rows = text_to_renderable_char_rows(&cells_to_char_rows(cells), &font);
+        let img = cells_to_color_img(cells);
+        write_char_rows_png(
+            out_path, &char_rows, &font, &img, true, background, scale, None,
+        );
+        return;
+    }
+
+    let out_frames: Vec<DynamicImage> = frames
+        .iter()
+        .map(|cells| {
+            let char_rows = text_to_renderable_char_rows(&cells_to_char_rows(cells), &font);
+            let img = char_rows_to_color_bitmap_with_background(
+                &char_rows,
+                &font,
+                &cells_to_color_img(cells),
+                background,
+            );
+            if scale > 1 {
+                img.resize_exact(
+                    img.width() * scale,
+                    img.height() * scale,
+                    FilterType::Nearest,
+                )
+            } else {
+                img
+            }
+        })
+        .collect();
+
+    if out_path.extension().unwrap() == "gif" {
+        write_gif(out_path, &out_frames, fps);
+    } else {
+        out_frames[0].save(out_path).unwrap();
+    }
+}
+
+fn write_layout(args: &Cli, image_path: &Path, input_format: &InputFormat, bands: &[Band]) {
+    // every band of rows is converted from its own slice of the image with its own font, at the
+    // width in pixels of the output with the main font, and the bands are stacked into one output
+    if !matches!(input_format, InputFormat::Still(_)) {
+        panic!("--layout needs a still image as input");
+    }
+    let out_path = args
+        .out_path
+        .as_ref()
+        .map(Path::new)
+        .unwrap_or_else(|| panic!("--layout needs an .html or image output"));
+    let img = ::image::open(image_path).unwrap();
+    let alphabet = load_alphabet(&args.alphabet);
+    let base_font = load_font(&args.font, &alphabet, &FontOptions::default());
+    let (n_cols, n_rows) = convert::output_size(
+        &base_font,
+        img.width() as usize,
+        img.height() as usize,
+        args.width,
+    );
+    let (width, height) = (n_cols * base_font.width, n_rows * base_font.height);
+    info!("layout size\t{}x{}", width, height);
+
+    let fonts: Vec<Font> = bands
+        .iter()
+        .map(|band| load_font(&band.font, &alphabet, &FontOptions::default()))
+        .collect();
+    let cell_heights: Vec<usize> = bands
+        .iter()
+        .zip(&fonts)
+        .map(|(band, font)| font.height * band.scale as usize)
+        .collect();
+    let rows: Vec<Option<usize>> = bands.iter().map(|band| band.rows).collect();
+    let band_rows = band_heights(&cell_heights, &rows, height);
+    info!("layout rows\t{:?}", band_rows);
+
+    let convert = get_converter(&args.metric);
+    let algorithm = get_conversion_algorithm(&args.conversion_algorithm);
+    let color = !args.no_color;
+    let mut y = 0;
+    // the characters of every band, with the slice of the image they were converted from
+    let mut out_bands: Vec<(Vec<Vec<char>>, DynamicImage, usize)> = Vec::new();
+    for (i, (band, font)) in bands.iter().zip(&fonts).enumerate() {
+        let (rows, cell_height) = (band_rows[i], cell_heights[i]);
+        if rows == 0 {
+            continue;
+        }
+        let band_height = rows * cell_height;
+        let cell_width = font.width * band.scale as usize;
+        let cols = (width / cell_width).max(1);
+        // the slice of the image under the band, stretched so the band has exactly its rows
+        let (y0, y1) = (
+            y * img.height() as usize / height,
+            ((y + band_height) * img.height() as usize / height).min(img.height() as usize),
+        );
+        y += band_height;
+        let slice = img.crop_imm(0, y0 as u32, img.width(), (y1 - y0).max(1) as u32);
+        let slice = slice.resize_exact(
+            (cols * font.width) as u32,
+            (rows * font.height) as u32,
+            FilterType::Triangle,
+        );
+        let char_rows = convert::img_to_char_rows(
+            font,
+            &LumaImage::from(&slice),
+            convert,
+            Some(cols),
+            args.brightness_offset / 255.,
+            &algorithm,
+            None,
+        );
+        out_bands.push((char_rows, slice, i));
+    }
+
+    if out_path.extension().unwrap() == "html" {
+        // each band gets a font size in proportion to the height of its cells
+        let html: String = out_bands
+            .iter()
+            .map(|(char_rows, slice, i)| {
+                let size = 15 * cell_heights[*i] / base_font.height;
+                let text = if color {
+                    char_rows_to_html_color_string(char_rows, slice)
+                } else {
+                    escape_html(&char_rows_to_string(char_rows))
+                };
+                format!("<div style=\"font-size: {}px\">{}</div>", size, text)
+            })
+            .collect();
+        write_html(out_path, &[html], args.fps, false, None);
+    } else {
+        let out_height: usize = out_bands
+            .iter()
+            .map(|(char_rows, _, i)| char_rows.len() * cell_heights[*i])
+            .sum();
+        let mut out = RgbImage::new(width as u32, out_height as u32);
+        let mut y = 0;
+        for (char_rows, slice, i) in &out_bands {
+            let bitmap = render_char_rows(char_rows, &fonts[*i], slice, color);
+            let scale = bands[*i].scale;
+            let bitmap = bitmap.resize_exact(
+                bitmap.width() * scale,
+                bitmap.height() * scale,
+                FilterType::Nearest,
+            );
+            ::image::imageops::replace(&mut out, &bitmap.to_rgb8(), 0, y);
+            y += bitmap.height() as i64;
+        }
+        out.save(out_path).unwrap();
+    }
+}
+
+fn parse_cell_size(cell_size: &str) -> (usize, usize) {
+    // <width>x<height>, in pixels
+    let parse = |value: &str| match value.parse::<usize>() {
+        Ok(n) if n > 0 => n,
+        _ => panic!("Unsupported cell size {}", cell_size),
+    };
+    match cell_size.split_once('x') {
+        Some((width, height)) => (parse(width), parse(height)),
+        None => panic!("Unsupported cell size {}", cell_size),
+    }
+}
+
+fn parse_duration(duration: &str) -> f64 {
+    // in seconds, with an optional s or ms suffix
+    let parse = |value: &str| {
+        value
+            .parse::<f64>()
+            .unwrap_or_else(|_| panic!("Unsupported duration {}", duration))
+    };
+    if let Some(ms) = duration.strip_suffix("ms") {
+        parse(ms) / 1000.
+    } else if let Some(s) = duration.strip_suffix('s') {
+        parse(s)
+    } else {
+        parse(duration)
+    }
+}
+
+fn read_char_rows(path: &Path, width: Option<usize>) -> Vec<Vec<char>> {
+    match sniff_input_format(path) {
+        InputFormat::Text(text) => text_to_char_rows(&text),
+        InputFormat::Cells(json) => cells_to_char_rows(&cells_from_json(&json)[0]),
+        format => {
+            let alphabet = load_alphabet("alphabet");
+            let font = load_font("bitocra-13", &alphabet, &FontOptions::default());
+            let img = &read_frames(path, &format, Some(1), None, false)[0];
+            convert::img_to_char_rows(
+                &font,
+                &LumaImage::from(img),
+                get_converter("direction-and-intensity"),
+                width,
+                0.,
+                &get_conversion_algorithm("edge-augmented"),
+                None,
+            )
+        }
+    }
+}
+
+fn alphabets() -> Vec<(&'static str, &'static str)> {
+    #[allow(unused_mut)]
+    let mut alphabets = vec![
+        ("alphabet", include_str!("../alphabets/alphabet.txt")),
+        ("letters", include_str!("../alphabets/letters.txt")),
+        ("lowercase", include_str!("../alphabets/lowercase.txt")),
+        ("minimal", include_str!("../alphabets/minimal.txt")),
+        ("symbols", include_str!("../alphabets/symbols.txt")),
+        ("uppercase", include_str!("../alphabets/uppercase.txt")),
+    ];
+    #[cfg(feature = "alphabet-blocks")]
+    alphabets.push(("blocks", include_str!("../alphabets/blocks.txt")));
+    #[cfg(feature = "alphabet-braille")]
+    alphabets.push(("braille", include_str!("../alphabets/braille.txt")));
+    #[cfg(feature = "alphabet-box-drawing")]
+    alphabets.push(("box-drawing", include_str!("../alphabets/box-drawing.txt")));
+    alphabets
+}
+
+fn fonts() -> Vec<(&'static str, &'static str)> {
+    #[allow(unused_mut)]
+    let mut fonts = vec![
+        ("courier", include_str!("../fonts/courier.bdf")),
+        ("bitocra-13", include_str!("../fonts/bitocra-13.bdf")),
+    ];
+    #[cfg(feature = "font-blocks")]
+    fonts.push(("blocks", include_str!("../fonts/blocks.bdf")));
+    fonts
+}
+
+fn load_alphabet(alphabet_str: &str) -> Vec<char> {
+    let alphabet_map: HashMap<&str, &str> = alphabets().into_iter().collect();
+    if let Some(alphabet) = alphabet_map.get(alphabet_str) {
+        info!("alphabet name\t{:?}", alphabet_str);
+        alphabet.chars().collect()
+    } else {
+        let alphabet_path = Path::new(alphabet_str);
+        info!("alphabet path\t{:?}", alphabet_path);
+        let bytes = fs::read(alphabet_path).unwrap();
+        // fall back to one char per byte for alphabet files which are not valid utf-8
+        match String::from_utf8(bytes) {
+            Ok(text) => text.chars().collect(),
+            Err(err) => err.into_bytes().iter().map(|&b| b as char).collect(),
+        }
+    }
+}
+
+fn load_font(font_str: &str, alphabet: &[char], font_options: &FontOptions) -> Font {
+    Font::with_options(&read_font_chars(font_str), alphabet, font_options)
+}
+
+fn read_font_chars(font_str: &str) -> Vec<Character> {
+    let font_map: HashMap<&str, &str> = fonts().into_iter().collect();
+    if let Some(font_data) = font_map.get(font_str) {
+        info!("font name\t{:?}", font_str);
+        Font::read_bdf_chars(font_data.as_bytes())
+    } else if let Some(font_path) = registry::cached_font_path(font_str) {
+        info!("registry font path\t{:?}", font_path);
+        Font::read_bdf_file_chars(&font_path)
+    } else {
+        let font_path = Path::new(font_str);
+        info!("font path\t{:?}", font_path);
+        Font::read_bdf_file_chars(font_path)
+    }
+}
+
+// how often converted frames are saved to the checkpoint
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+fn recorded_args() -> Vec<String> {
+    // the arguments an output was made with; --threads is left out, since it doesn't change the
+    // output
+    let mut args = std::env::args().skip(1);
+    let mut recorded = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--threads" {
+            args.next();
+        } else if !arg.starts_with("--threads=") {
+            recorded.push(arg);
+        }
+    }
+    recorded
+}
+
+fn checkpoint_command() -> String {
+    // the arguments the checkpoint was made with, so it isn't resumed with different settings
+    recorded_args()
+        .into_iter()
+        .filter(|arg| arg != "--resume")
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn write_checkpoint(path: &Path, cells: &[CellRows]) {
+    // written to a temporary file first, so an interruption mid-write can't corrupt it
+    let mut value = cells_to_json(cells, None);
+    value["command"] = json!(checkpoint_command());
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, value.to_string()).unwrap();
+    fs::rename(&tmp_path, path).unwrap();
+    info!("checkpoint\t{} frames", cells.len());
+}
+
+fn read_checkpoint(path: &Path) -> Vec<CellRows> {
+    let value: Value = serde_json::from_str(&fs::read_to_string(path).unwrap())
+        .unwrap_or_else(|err| panic!("Unsupported checkpoint {:?}: {}", path, err));
+    if value["command"].as_str() != Some(&checkpoint_command()) {
+        panic!(
+            "The checkpoint {:?} was made with different arguments: {}",
+            path, value["command"]
+        );
+    }
+    cells_from_json(&value)
+}
+
+fn cells_to_confidence(cells: &[Vec<Cell>]) -> Vec<Vec<CharConfidence>> {
+    cells
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|cell| {
+                    cell.confidence
+                        .expect("the checkpoint is missing confidence")
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn stdio_json_response(request: &Value) -> Value {
+    // a single conversion, described by a json request instead of arguments
+    let option = |key: &str, default: &'static str| -> String {
+        request[key].as_str().unwrap_or(default).to_string()
+    };
+    let image_data = request["image"]
+        .as_str()
+        .expect("request is missing a base64 image");
+    // data urls are accepted as well as plain base64
+    let image_data = image_data
+        .split_once(";base64,")
+        .map_or(image_data, |(_, data)| data);
+    let bytes = base64::decode(image_data);
+    let frames = if ::image::guess_format(&bytes).ok() == Some(ImageFormat::Gif) {
+        read_gif_from_stream(&bytes[..])
+    } else {
+        vec![::image::load_from_memory(&bytes)
+            .unwrap_or_else(|err| panic!("Unsupported image: {}", err))]
+    };
+
+    let alphabet = load_alphabet(&option("alphabet", "alphabet"));
+    let font = load_font(
+        &option("font", "bitocra-13"),
+        &alphabet,
+        &FontOptions::default(),
+    );
+    let width = request["width"].as_u64().map(|width| width as usize);
+    let convert = get_converter(&option("metric", "direction-and-intensity"));
+    let algorithm = get_conversion_algorithm(&option("conversion_algorithm", "edge-augmented"));
+    let brightness_offset = request["brightness_offset"].as_f64().unwrap_or(0.) as f32;
+    let color = request["color"].as_bool().unwrap_or(true);
+    let format = option("format", "text");
+    let fps = request["fps"].as_f64().unwrap_or(30.);
+
+    let frame_char_rows: Vec<Vec<Vec<char>>> = frames
+        .iter()
+        .map(|frame| {
+            convert::img_to_char_rows(
+                &font,
+                &LumaImage::from(frame),
+                convert,
+                width,
+                brightness_offset / 255.,
+                &algorithm,
+                None,
+            )
+        })
+        .collect();
+    let (n_cols, n_rows) = (frame_char_rows[0][0].len(), frame_char_rows[0].len());
+    let strings = |to_string: fn(&[Vec<char>], &DynamicImage) -> String| -> Value {
+        frame_char_rows
+            .iter()
+            .zip(&frames)
+            .map(|(char_rows, frame)| to_string(char_rows, frame))
+            .collect()
+    };
+    let bitmaps = || -> Vec<DynamicImage> {
+        frame_char_rows
+            .iter()
+            .zip(&frames)
+            .map(|(char_rows, frame)| render_char_rows(char_rows, &font, frame, color))
+            .collect()
+    };
+
+    let output = match format.as_str() {
+        "text" => strings(|char_rows, _| char_rows_to_string(char_rows)),
+        "ansi" if color => strings(char_rows_to_terminal_color_string),
+        "html" if color => strings(char_rows_to_html_color_string),
+        "ansi" | "html" => strings(|char_rows, _| char_rows_to_string(char_rows)),
+        "cells" | "planes" => {
+            let white = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([255, 255, 255])));
+            let cells: Vec<CellRows> = frame_char_rows
+                .iter()
+                .zip(&frames)
+                .map(|(char_rows, frame)| {
+                    char_rows_to_cells(char_rows, if color { frame } else { &white })
+                })
+                .collect();
+            let cell_size = Some((font.width, font.height));
+            match format.as_str() {
+                "cells" => cells_to_json(&cells, cell_size),
+                _ => cells_to_planes_json(&cells, cell_size, true),
+            }
+        }
+        "png" => {
+            let mut png = Cursor::new(Vec::new());
+            bitmaps()[0]
+                .write_to(&mut png, ImageOutputFormat::Png)
+                .unwrap();
+            json!(base64::encode(png.get_ref()))
+        }
+        "gif" => {
+            let mut gif = Vec::new();
+            write_gif_to_stream(&mut gif, &bitmaps(), fps);
+            json!(base64::encode(&gif))
+        }
+        _ => panic!("Unsupported format {}", format),
+    };
+    json!({
+        "format": format,
+        "columns": n_cols,
+        "rows": n_rows,
+        "frames": frames.len(),
+        "output": output,
+    })
+}
+
+fn stdio_json() {
+    // the response is always json, so failures are reported as an error field as well as with
+    // the exit code
+    let response = panic::catch_unwind(|| {
+        let mut request = String::new();
+        io::stdin().read_to_string(&mut request).unwrap();
+        let request: Value = serde_json::from_str(&request)
+            .unwrap_or_else(|err| panic!("Unsupported request json: {}", err));
+        stdio_json_response(&request)
+    });
+    match response {
+        Ok(response) => println!("{}", response),
+        Err(err) => {
+            let message = err
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| err.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| String::from("conversion failed"));
+            println!("{}", json!({ "error": message }));
+            std::process::exit(1);
+        }
+    }
+}
+
+// the options which the library's gif transcode handles the same way as the full conversion
+const TRANSCODE_ARGS: &[&str] = &[
+    "image-path",
+    "out-path",
+    "font",
+    "alphabet",
+    "cell-size",
+    "glyph-tolerance",
+    "prefer-ascii",
+    "safe-chars",
+    "require-full-alphabet",
+    "width",
+    "metric",
+    "no-color",
+    "brightness-offset",
+    "fps",
+    "conversion-algorithm",
+    "max-frames",
+    "max-pixels",
+    "strict",
+    "tie-break",
+    "seed",
+    "tie-epsilon",
+    "repetition-penalty",
+    "chunk-overlap",
+    "palette",
+    "gif-global-palette",
+    "rules",
+    "threads",
+    "log-json",
+];
+
+fn only_transcode_args(matches: &ArgMatches) -> bool {
+    // clap's own --help and --version never get this far
+    Cli::command()
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_id(), "help" | "version"))
+        .all(|arg| {
+            TRANSCODE_ARGS.contains(&arg.get_id())
+                || matches.value_source(arg.get_id()) != Some(ValueSource::CommandLine)
+        })
+}
+
+fn main() {
+    let matches = Cli::command().get_matches();
+    let args = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    logging::init(args.log_json);
+    set_threads(args.threads);
+    info!("threads\t{}", rayon::current_num_threads());
+    if let Some(command) = args.command {
+        run_command(command);
+        return;
+    }
+    if args.stdio_json {
+        stdio_json();
+        return;
+    }
+
+    let width = args.width;
+    info!("width\t{:?}", width);
+    let auto_width = args.auto_width;
+    info!("auto width\t{}", auto_width);
+
+    let image_path = Path::new(args.image_path.as_ref().unwrap());
+    info!("image path\t{:?}", image_path);
+    let input_format = sniff_input_format(image_path);
+    if let Some(layout) = &args.layout {
+        write_layout(
+            &args,
+            image_path,
+            &input_format,
+            &read_layout(Path::new(layout)),
+        );
+        return;
+    }
+
+    // existing text art and cells json are rendered as is, so their own characters are used as
+    // the alphabet
+    let (text_input, text_colors) = match &input_format {
+        InputFormat::Text(text) => (Some(vec![text_to_char_rows(text)]), None),
+        InputFormat::Cells(json) => {
+            let cells = cells_from_json(json);
+            (
+                Some(
+                    cells
+                        .iter()
+                        .map(|cells| cells_to_char_rows(cells))
+                        .collect(),
+                ),
+                Some(
+                    cells
+                        .iter()
+                        .map(|cells| cells_to_color_img(cells))
+                        .collect::<Vec<DynamicImage>>(),
+                ),
+            )
+        }
+        _ => (None, None),
+    };
+    let mut font_str = args.font.clone();
+    let alphabet = if let Some(text) = &text_input {
+        let mut alphabet: Vec<char> = text.iter().flatten().flatten().cloned().collect();
+        alphabet.push(' ');
+        alphabet.sort();
+        alphabet.dedup();
+        alphabet
+    } else {
+        let alphabet = load_alphabet(&args.alphabet);
+        // unicode alphabets turn into mojibake on terminals which can't display them
+        if args.out_path.is_none()
+            && !alphabet.iter().all(|c| c.is_ascii())
+            && !locale_supports_utf8()
+        {
+            eprintln!(
+                "warning: the locale doesn't support UTF-8, so the ascii alphabet is used instead \
+                 of {:?}; set LANG to a UTF-8 locale to use it",
+                args.alphabet
+            );
+            // fonts like blocks only have glyphs for their unicode characters
+            if !read_font_chars(&font_str).iter().any(|c| c.value == 'A') {
+                font_str = String::from("bitocra-13");
+            }
+            load_alphabet("alphabet")
+        } else {
+            alphabet
+        }
+    };
+    let mode = get_render_mode(&args.mode);
+    info!("mode\t{}", args.mode);
+    // shade mode only ever uses the shade blocks, which not every font has glyphs for
+    let alphabet = match mode {
+        RenderMode::Shade if text_input.is_none() => {
+            if !read_font_chars(&font_str).iter().any(|c| c.value == '█') {
+                font_str = String::from("blocks");
+            }
+            SHADES.to_vec()
+        }
+        _ => alphabet,
+    };
+    let shade_fg = args.shade_fg.as_ref().map(|color| parse_hex_color(color));
+    let shade_bg = args.shade_bg.as_ref().map(|color| parse_hex_color(color));
+    info!("shade fg\t{:?}", args.shade_fg);
+    info!("shade bg\t{:?}", args.shade_bg);
+    if (shade_fg.is_some() || shade_bg.is_some()) && !matches!(mode, RenderMode::Shade) {
+        panic!("--shade-fg and --shade-bg need --mode shade");
+    }
+    let paper = args.paper.as_ref().map(|color| parse_hex_color(color));
+    info!("paper\t{:?}", args.paper);
+    // the paper is drawn like a shade background, with the colors adjusted to stand out from it
+    let background = shade_bg.or(paper);
+    let min_contrast = args.min_contrast.or(paper.map(|_| PAPER_MIN_CONTRAST));
+    info!("min contrast\t{:?}", min_contrast);
+    if min_contrast.is_some_and(|ratio| !(1. ..=21.).contains(&ratio)) {
+        panic!("Unsupported contrast ratio {}", min_contrast.unwrap());
+    }
+    info!("alphabet\t[{}]", alphabet.iter().collect::<String>());
+
+    let font_options = FontOptions {
+        glyph_tolerance: args.glyph_tolerance,
+        prefer_ascii: args.prefer_ascii,
+        safe_chars: args.safe_chars,
+    };
+    info!("glyph tolerance\t{}", font_options.glyph_tolerance);
+    info!("prefer ascii\t{}", font_options.prefer_ascii);
+    info!("safe chars\t{}", font_options.safe_chars);
+
+    let fit_pad = match args.fit.as_str() {
+        "resample" => false,
+        "pad" => true,
+        fit => panic!("Unsupported fit {}", fit),
+    };
+    info!("fit\t{}", args.fit);
+
+    let resize_filter = get_resize_filter(&args.resize);
+    info!("resize\t{}", args.resize);
+
+    let cell_size = args.cell_size.as_deref().map(parse_cell_size);
+    info!("cell size\t{:?}", cell_size);
+    // glyphs are resized once, so the rest of the conversion only ever sees the new cell size
+    let font_chars = match cell_size {
+        Some((cell_width, cell_height)) => {
+            resize_chars(&read_font_chars(&font_str), cell_width, cell_height)
+        }
+        None => read_font_chars(&font_str),
+    };
+    let missing = missing_chars(&font_chars, &alphabet);
+    info!("require full alphabet\t{}", args.require_full_alphabet);
+    if !missing.is_empty() {
+        let missing: String = missing.iter().collect();
+        if args.require_full_alphabet {
+            panic!(
+                "Unsupported alphabet; the font has no glyphs for {:?}",
+                missing
+            );
+        }
+        eprintln!(
+            "warning: the font has no glyphs for {:?}, so they are left out of the alphabet",
+            missing
+        );
+    }
+    let mut font = Font::with_options(&font_chars, &alphabet, &font_options);
+    if let Some(signature) = &args.signature {
+        if signature.is_empty() {
+            panic!("Unsupported signature; it can't be empty");
+        }
+        // the signature is drawn with the same font, even if it isn't in the alphabet
+        let signature_chars: Vec<char> = signature.chars().collect();
+        font.add_renderable_chars(&font_chars, &signature_chars);
+    }
+    if !font.duplicates.is_empty() {
+        let mut duplicates: Vec<(&char, &char)> = font.duplicates.iter().collect();
+        duplicates.sort();
+        info!("merged glyphs\t{:?}", duplicates);
+    }
+    let glyph_style = get_glyph_style(&args.glyph_style);
+    info!("glyph style\t{}", args.glyph_style);
+    let render_font = font.with_glyph_style(&glyph_style);
+
+    // text preserving conversion matches the rest of the image by brightness alone, which keeps
+    // it from competing with the strokes of the text
+    let metric = if args.preserve_text {
+        String::from("intensity")
+    } else {
+        args.metric
+    };
+    info!("metric\t{}", metric);
+
+    let out_path = args.out_path.as_ref().map(Path::new);
+    info!("out path\t{:?}", out_path);
+
+    // edge shading is shorthand for the gradient magnitude edge color
+    let edge_color = if args.edge_shading {
+        EdgeColor::GradientMagnitude
+    } else {
+        get_edge_color(&args.edge_color)
+    };
+    info!("edge color\t{}", args.edge_color);
+    info!("edge shading\t{}", args.edge_shading);
+
+    let poster = args.poster.as_deref().map(parse_poster_pages);
+    info!("poster\t{:?}", poster);
+
+    let scene_cuts_threshold = args.scene_cuts;
+    info!("scene cuts\t{:?}", scene_cuts_threshold);
+
+    let fps = args.fps;
+    if fps < 1. {
+        panic!("Unsupported fps {}; it must be at least 1", fps);
+    }
+    info!("fps\t{}", fps);
+
+    let color = !args.no_color;
+    info!("color\t{}", color);
+
+    let brightness_offset = args.brightness_offset;
+    info!("brightness\t{}", brightness_offset);
+
+    let conversion_algorithm = if args.pixel_art {
+        String::from("pixel-art")
+    } else if args.preserve_text {
+        String::from("preserve-text")
+    } else {
+        args.conversion_algorithm
+    };
+    info!("conversion alg\t{}", conversion_algorithm);
+    if args.edge_shading && !metric.starts_with("direction") && conversion_algorithm != "edge" {
+        eprintln!(
+            "warning: --edge-shading is meant for the direction metrics or the edge conversion \
+             algorithm, whose characters follow the strokes of the image"
+        );
+    }
+
+    let trim = args.trim;
+    info!("trim\t{}", trim);
+
+    let direction = get_text_direction(&args.direction);
+    info!("direction\t{}", args.direction);
+
+    let grid_transform = args.flip_output.as_ref().map(|flip| get_grid_flip(flip));
+    info!("flip output\t{:?}", args.flip_output);
+    let grid_rotation = args.rotate_output.map(get_grid_rotation);
+    info!("rotate output\t{:?}", args.rotate_output);
+    let mirror_glyphs = args.mirror_glyphs;
+    info!("mirror glyphs\t{}", mirror_glyphs);
+
+    let gradient = args
+        .gradient
+        .as_ref()
+        .map(|colors| parse_hex_colors(colors));
+    info!("gradient\t{:?}", args.gradient);
+
+    let json_format = args.json_format.as_str();
+    info!("json format\t{}", json_format);
+    if !["html", "cells", "planes", "planes-base64"].contains(&json_format) {
+        panic!("Unsupported json format {}", json_format);
+    }
+    // .msgpack and .cbor output store the cells json, or the planes json if it was asked for
+    let binary_format = out_path
+        .and_then(|path| path.extension())
+        .and_then(|extension| get_binary_format(extension.to_str().unwrap()));
+    let json_format = match json_format {
+        "html" if binary_format.is_some() => "cells",
+        json_format => json_format,
+    };
+    let compression = args.compress.as_deref().map(get_compression);
+    info!("compress\t{:?}", args.compress);
+    if compression.is_some() && binary_format.is_none() {
+        panic!("--compress needs .msgpack or .cbor output");
+    }
+
+    let autocrop = args.autocrop;
+    info!("autocrop\t{:?}", autocrop);
+    if autocrop.is_some_and(|threshold| !(0. ..=1.).contains(&threshold)) {
+        panic!(
+            "Unsupported autocrop threshold {:?}; it must be between 0 and 1",
+            autocrop.unwrap()
+        );
+    }
+    let match_histogram = args.match_histogram;
+    info!("match histogram\t{:?}", match_histogram);
+    if match_histogram.is_some_and(|tolerance| !(0. ..=1.).contains(&tolerance)) {
+        panic!(
+            "Unsupported histogram tolerance {:?}",
+            match_histogram.unwrap()
+        );
+    }
+    let focus_faces = args.focus_faces;
+    info!("focus faces\t{}", focus_faces);
+    if focus_faces && cfg!(not(feature = "focus-faces")) {
+        panic!("--focus-faces needs image-to-ascii to be built with the focus-faces feature");
+    }
+
+    let confidence = args.confidence;
+    info!("confidence\t{}", confidence);
+    let confidence_map = args.confidence_map.as_ref().map(Path::new);
+    info!("confidence map\t{:?}", confidence_map);
+
+    let error_map = args.error_map.as_ref().map(Path::new);
+    info!("error map\t{:?}", error_map);
+
+    // one seed drives every random choice of the run, and a seed picked for the run is printed so
+    // that its output can be made again
+    let uses_seed = args.tie_break == "random" || args.reveal.as_deref() == Some("random");
+    let seed = args.seed.unwrap_or_else(|| {
+        let seed = random::fresh_seed();
+        if uses_seed {
+            eprintln!(
+                "seed: {} (use --seed {} to get this output again)",
+                seed, seed
+            );
+        }
+        seed
+    });
+    info!("seed\t{}", seed);
+    let tie_break = get_tie_break(&args.tie_break, seed);
+    info!("tie break\t{}", args.tie_break);
+    info!("tie epsilon\t{}", args.tie_epsilon);
+    let repetition_penalty = args.repetition_penalty;
+    info!("repetition penalty\t{}", repetition_penalty);
+
+    let reveal = args.reveal.as_ref().map(|order| get_reveal_order(order));
+    info!("reveal\t{:?}", args.reveal);
+    let reveal_duration = parse_duration(&args.reveal_duration);
+    info!("reveal duration\t{}", reveal_duration);
+    let color_cycle = args
+        .color_cycle
+        .as_ref()
+        .map(|palette| get_palette(palette));
+    info!("color cycle\t{:?}", args.color_cycle);
+    let cycle_duration = parse_duration(&args.cycle_duration);
+    info!("cycle duration\t{}", cycle_duration);
+
+    info!("signature\t{:?}", args.signature);
+    info!("signature color\t{}", args.signature_color);
+
+    let max_frames = args.max_frames;
+    info!("max frames\t{:?}", max_frames);
+    let max_pixels = args.max_pixels;
+    info!("max pixels\t{:?}", max_pixels);
+    let strict = args.strict;
+    info!("strict\t{}", strict);
+    let checkpoint = args.checkpoint.as_ref().map(Path::new);
+    info!("checkpoint\t{:?}", checkpoint);
+    info!("resume\t{}", args.resume);
+    let links = args
+        .links
+        .as_ref()
+        .map(|path| read_link_map(Path::new(path)));
+    info!("links\t{:?}", args.links);
+    let anaglyph = args.anaglyph;
+    info!("anaglyph\t{}", anaglyph);
+    info!("right image\t{:?}", args.right_image);
+    if anaglyph && !color {
+        panic!("--anaglyph needs color output");
+    }
+    let layers: Vec<Layer> = args.layer.iter().map(|layer| parse_layer(layer)).collect();
+    info!("layers\t{:?}", args.layer);
+    if !layers.is_empty() && text_input.is_some() {
+        eprintln!("warning: --layer only applies to image input, so text input is left as it is");
+    }
+    let layer_frames: Vec<Vec<DynamicImage>> = layers
+        .iter()
+        .map(|layer| {
+            let layer_path = Path::new(&layer.path);
+            let layer_format = sniff_input_format(layer_path);
+            read_frames(layer_path, &layer_format, max_frames, max_pixels, strict)
+        })
+        .collect();
+    let depth_frames: Vec<LumaImage<f32>> = match &args.depth {
+        Some(depth_path) => {
+            let depth_path = Path::new(depth_path);
+            let depth_format = sniff_input_format(depth_path);
+            read_frames(depth_path, &depth_format, max_frames, max_pixels, strict)
+                .iter()
+                .map(LumaImage::from)
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    if !depth_frames.is_empty() && (autocrop.is_some() || fit_pad) {
+        panic!("--depth can't be used with --autocrop or --fit pad, which move the image");
+    }
+    info!("depth\t{:?}", args.depth);
+    info!("depth strength\t{}", args.depth_strength);
+
+    let gif_global_palette = args.gif_global_palette;
+    info!("gif global palette\t{}", gif_global_palette);
+
+    let auto_palette = args.auto_palette;
+    info!("auto palette\t{:?}", auto_palette);
+    if auto_palette == Some(0) {
+        panic!("The auto palette must have at least one color");
+    }
+    let palette = args.palette.as_ref().map(|palette| get_palette(palette));
+    info!("palette\t{:?}", args.palette);
+    if palette.is_some() && auto_palette.is_some() {
+        panic!("--palette and --auto-palette can't be used together");
+    }
+    let simulate = args
+        .simulate
+        .as_ref()
+        .map(|color_vision| get_color_vision(color_vision));
+    info!("simulate\t{:?}", args.simulate);
+
+    let convert = get_converter(&metric);
+    let chunk_overlap = args.chunk_overlap;
+    info!("chunk overlap\t{}", chunk_overlap);
+    let grid = get_grid(&args.grid);
+    info!("grid\t{}", args.grid);
+    // text can't be shifted by half a cell, so only image output shows the offset rows
+    let text_output = out_path.is_none_or(|path| {
+        let text_extensions = ["txt", "html", "json", "ascz", "msgpack", "cbor"];
+        text_extensions
+            .iter()
+            .any(|extension| path.extension().unwrap() == *extension)
+    });
+    if grid != Grid::Square && text_output {
+        eprintln!(
+            "warning: text output can't offset the rows of the {} grid, so its rows line up",
+            args.grid
+        );
+    }
+
+    let canvas = args.canvas.as_deref().map(parse_canvas_size);
+    info!("canvas\t{:?}", canvas);
+    info!("overlay safe area\t{}", args.overlay_safe_area);
+    info!("safe area guides\t{}", args.safe_area_guides);
+    let on_canvas = canvas.is_some() || args.overlay_safe_area || args.safe_area_guides;
+    if on_canvas && text_output {
+        eprintln!(
+            "warning: --canvas, --overlay-safe-area, and --safe-area-guides only apply to image \
+             output"
+        );
+    }
+    let selection = match tie_break {
+        TieBreak::Stable
+            if repetition_penalty == 0. && chunk_overlap == 0 && grid == Grid::Square =>
+        {
+            None
+        }
+        tie_break => Some(CharSelection {
+            scorer: get_scorer(&metric),
+            tie_break,
+            tie_epsilon: args.tie_epsilon,
+            repetition_penalty,
+            chunk_overlap,
+            grid,
+        }),
+    };
+
+    // scores are only needed when the confidence of each character is reported
+    let scorer = if confidence || confidence_map.is_some() {
+        Some(get_scorer(&metric))
+    } else {
+        None
+    };
+
+    // every rule converts the whole image with its own settings, and its region is copied over
+    // the characters of the main conversion; the glyphs of the rules' alphabets are added to the
+    // main font so that they can be rendered
+    let rules = args
+        .rules
+        .as_deref()
+        .map(|path| read_rules(Path::new(path)))
+        .unwrap_or_default();
+    info!("rules\t{}", rules.len());
+    if !rules.is_empty() && matches!(mode, RenderMode::Shade) {
+        panic!("--rules can't be used with --mode shade");
+    }
+    let rules: Vec<RuleConversion> = rules
+        .into_iter()
+        .map(|rule| {
+            let rule_font = match &rule.alphabet {
+                Some(rule_alphabet) => {
+                    let rule_alphabet = load_alphabet(rule_alphabet);
+                    font.add_renderable_chars(&font_chars, &rule_alphabet);
+                    Font::with_options(&font_chars, &rule_alphabet, &font_options)
+                }
+                None => font.clone(),
+            };
+            let rule_metric = rule.metric.as_ref().unwrap_or(&metric);
+            let rule_algorithm = rule
+                .conversion_algorithm
+                .as_ref()
+                .unwrap_or(&conversion_algorithm);
+            let rule_selection = selection.as_ref().map(|selection| CharSelection {
+                scorer: get_scorer(rule_metric),
+                tie_break: selection.tie_break,
+                tie_epsilon: selection.tie_epsilon,
+                repetition_penalty: selection.repetition_penalty,
+                chunk_overlap: selection.chunk_overlap,
+                grid: selection.grid,
+            });
+            let convert = get_converter(rule_metric);
+            let algorithm = get_conversion_algorithm(rule_algorithm);
+            RuleConversion {
+                rule,
+                font: rule_font,
+                convert,
+                algorithm,
+                selection: rule_selection,
+            }
+        })
+        .collect();
+
+    let conversion_algorithm = get_conversion_algorithm(&conversion_algorithm);
+    info!("converter\t{:?}", convert);
+
+    // everything needed to reproduce the output later, except for its size
+    let base_meta = || {
+        let source = fs::canonicalize(image_path).unwrap();
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "command": recorded_args(),
+            "source": source.to_str().unwrap(),
+            "source_crc32": file_crc32(&source),
+            "font": font_str,
+            "alphabet": args.alphabet,
+            "alphabet_chars": alphabet.iter().collect::<String>(),
+            "metric": metric,
+            "fps": fps,
+            "seed": (uses_seed || args.seed.is_some()).then_some(seed),
+        })
+    };
+
+    // gifs made from gifs are converted by the library one frame at a time, unless an option
+    // needs every frame at once or is only handled below
+    let streams_gif = matches!(input_format, InputFormat::Animation(ImageFormat::Gif))
+        && out_path.is_some_and(|path| path.extension().unwrap() == "gif")
+        && only_transcode_args(&matches);
+    info!("stream gif\t{}", streams_gif);
+    if streams_gif {
+        let path = out_path.unwrap();
+        let options = TranscodeOptions {
+            convert,
+            out_width: width,
+            brightness_offset: brightness_offset / 255.,
+            algorithm: conversion_algorithm,
+            color,
+            fps,
+            max_frames,
+            max_pixels,
+            strict,
+            selection,
+            rules,
+            palette,
+            global_palette: gif_global_palette,
+            meta: Some(base_meta()),
+            ..TranscodeOptions::new(&font)
+        };
+        logging::set_stage("convert");
+        // ctrl-c stops converting, and the frames written so far are still a whole gif
+        interrupt::catch_interrupts();
+        let n_written = transcode_ascii(image_path, path, &options);
+        if interrupt::take_interrupt() {
+            eprintln!("interrupted: wrote {} frames to {:?}", n_written, path);
+            std::process::exit(130);
+        }
+        return;
+    }
+
+    // cells json made with the intensity metric stores the brightness of every cell, so that
+    // re-render --alphabet can swap the alphabet without the original image; reveals and
+    // signatures replace cells, so their brightness wouldn't match, and color cycles only store
+    // it for their first frame
+    let store_intensity = (out_path.is_some_and(|path| path.extension().unwrap() == "json")
+        || binary_format.is_some())
+        && json_format == "cells"
+        && matches!(metric.as_str(), "intensity" | "fast")
+        && reveal.is_none()
+        && color_cycle.is_none()
+        && args.signature.is_none()
+        && rules.is_empty();
+    info!("store intensity\t{}", store_intensity);
+
+    // an interrupted run still writes what it has, then exits like ctrl-c would have
+    let mut interrupted = false;
+    let (frame_char_rows, frames, confidence_frames, intensity_frames) = if let Some(text) =
+        &text_input
+    {
+        let frame_char_rows: Vec<Vec<Vec<char>>> = text
+            .iter()
+            .map(|char_rows| text_to_renderable_char_rows(char_rows, &font))
+            .collect();
+        let frames = match (&gradient, &text_colors) {
+            (None, Some(colors)) => colors.clone(),
+            _ => {
+                let colors = gradient
+                    .clone()
+                    .unwrap_or_else(|| vec![Rgb([255, 255, 255])]);
+                frame_char_rows
+                    .iter()
+                    .map(|char_rows| {
+                        horizontal_gradient_img(&colors, char_rows[0].len(), char_rows.len())
+                    })
+                    .collect()
+            }
+        };
+        (frame_char_rows, frames, Vec::new(), Vec::new())
+    } else {
+        info!("converting frames to ascii...");
+        logging::set_stage("read");
+        let frames = read_frames(image_path, &input_format, max_frames, max_pixels, strict);
+        info!("frames\t{}", frames.len());
+
+        // layers are stacked in the order they're given, and animated layers loop over the frames
+        let frames: Vec<DynamicImage> = if layers.is_empty() {
+            frames
+        } else {
+            frames
+                .iter()
+                .enumerate()
+                .map(|(i, frame)| {
+                    layers.iter().zip(&layer_frames).fold(
+                        frame.clone(),
+                        |frame, (layer, layer_frames)| {
+                            let layer_frame = &layer_frames[i % layer_frames.len()];
+                            composite_layer(&frame, layer_frame, layer.x, layer.y, layer.alpha)
+                        },
+                    )
+                })
+                .collect()
+        };
+
+        // stereo input is converted one view at a time, and the right views are cropped and
+        // padded along with the left ones so the two stay aligned
+        let (frames, right_frames): (Vec<DynamicImage>, Vec<DynamicImage>) = if anaglyph {
+            match &args.right_image {
+                Some(right_path) => {
+                    let right_path = Path::new(right_path);
+                    let right_format = sniff_input_format(right_path);
+                    let right =
+                        read_frames(right_path, &right_format, max_frames, max_pixels, strict);
+                    let right = frames
+                        .iter()
+                        .enumerate()
+                        .map(|(i, frame)| {
+                            right[i % right.len()].resize_exact(
+                                frame.width(),
+                                frame.height(),
+                                FilterType::Triangle,
+                            )
+                        })
+                        .collect();
+                    (frames, right)
+                }
+                None => frames.iter().map(image::split_side_by_side).unzip(),
+            }
+        } else {
+            (frames, Vec::new())
+        };
 
-mod convert;
-mod font;
-mod gif;
-mod image;
-mod metrics;
-mod progress;
+        let (frames, right_frames) = if let Some(threshold) = autocrop {
+            // use the union of all frame bounds so animated output keeps a constant size
+            let bounds = frames
+                .iter()
+                .chain(&right_frames)
+                .map(|frame| image::content_bounds(frame, threshold))
+                .fold(None, union_bounds);
+            info!("autocrop bounds\t{:?}", bounds);
+            if let Some(bounds) = bounds {
+                (
+                    crop_frames(&frames, bounds),
+                    crop_frames(&right_frames, bounds),
+                )
+            } else {
+                (frames, right_frames)
+            }
+        } else {
+            (frames, right_frames)
+        };
 
-#[derive(Parser)]
-struct Cli {
-    image_path: String,
-    #[clap(short, long, default_value_t = String::from("bitocra-13"))]
-    font: String,
-    #[clap(short, long, default_value_t = String::from("alphabet"))]
-    alphabet: String,
-    #[clap(short, long)]
-    width: Option<usize>,
-    #[clap(short, long, default_value_t = String::from("direction-and-intensity"))]
-    metric: String,
-    #[clap(long)]
-    no_color: bool,
-    #[clap(short, long, default_value_t = 0.0)]
-    brightness_offset: f32,
-    #[clap(short, long)]
-    out_path: Option<String>,
-    #[clap(long, default_value_t = 30.0)]
-    fps: f64,
-    #[clap(short, long, default_value_t = String::from("edge-augmented"))]
-    conversion_algorithm: String,
-}
+        let (frames, right_frames) = match focus_faces.then(|| face_bounds(&frames)).flatten() {
+            Some(bounds) => (
+                crop_frames(&frames, bounds),
+                crop_frames(&right_frames, bounds),
+            ),
+            None => (frames, right_frames),
+        };
 
-const ALPHABETS: [(&str, &str); 6] = [
-    ("alphabet", include_str!("../alphabets/alphabet.txt")),
-    ("letters", include_str!("../alphabets/letters.txt")),
-    ("lowercase", include_str!("../alphabets/lowercase.txt")),
-    ("minimal", include_str!("../alphabets/minimal.txt")),
-    ("symbols", include_str!("../alphabets/symbols.txt")),
-    ("uppercase", include_str!("../alphabets/uppercase.txt")),
-];
+        let width = if auto_width {
+            Some(suggest_width(&LumaImage::from(&frames[0]), &font))
+        } else {
+            width
+        };
 
-const FONTS: [(&str, &str); 2] = [
-    ("courier", include_str!("../fonts/courier.bdf")),
-    ("bitocra-13", include_str!("../fonts/bitocra-13.bdf")),
-];
+        let (frames, right_frames) = if fit_pad {
+            let pad = |frames: &[DynamicImage]| -> Vec<DynamicImage> {
+                frames
+                    .iter()
+                    .map(|frame| image::pad_to_cells(frame, font.width, font.height, width))
+                    .collect()
+            };
+            (pad(&frames), pad(&right_frames))
+        } else {
+            (frames, right_frames)
+        };
 
-fn main() {
-    env_logger::init();
+        // a depth map shifts the brightness the characters are matched to, but not the colors;
+        // other resize filters resize to exactly the size the conversion would, so its own
+        // resize leaves the image as it is
+        let luma = |img: &DynamicImage, i: usize| {
+            let mut luma = LumaImage::from(img);
+            if !depth_frames.is_empty() {
+                luma.apply_depth(&depth_frames[i % depth_frames.len()], args.depth_strength);
+            }
+            if let ResizeFilter::DetailPreserve = resize_filter {
+                let (n_cols, n_rows) =
+                    convert::output_size(&font, luma.get_width(), luma.get_height(), width);
+                luma = luma.resize_with(n_cols * font.width, n_rows * font.height, &resize_filter);
+            }
+            luma
+        };
+
+        // the right views are merged into each frame's characters as it is converted
+        let right_char_rows: Vec<Vec<Vec<char>>> = right_frames
+            .iter()
+            .enumerate()
+            .map(|(i, img)| {
+                convert::img_to_char_rows(
+                    &font,
+                    &luma(img, i),
+                    convert,
+                    width,
+                    brightness_offset / 255.,
+                    &conversion_algorithm,
+                    selection.as_ref(),
+                )
+            })
+            .collect();
+        let with_rules = |ascii: Vec<Vec<char>>, luma_img: &LumaImage<f32>| {
+            apply_rules(
+                ascii,
+                luma_img,
+                &rules,
+                &font,
+                width,
+                brightness_offset / 255.,
+            )
+        };
+        let histogram_scorer = get_scorer(&metric);
+        let with_histogram =
+            |ascii: Vec<Vec<char>>, luma_img: &LumaImage<f32>| match match_histogram {
+                Some(tolerance) => convert::match_histogram(
+                    &ascii,
+                    &font,
+                    luma_img,
+                    histogram_scorer,
+                    width,
+                    brightness_offset / 255.,
+                    &conversion_algorithm,
+                    tolerance,
+                ),
+                None => ascii,
+            };
+        let with_right_view = |ascii: Vec<Vec<char>>, i: usize| match right_char_rows.get(i) {
+            Some(right) => combine_stereo_char_rows(&ascii, right, &font),
+            None => ascii,
+        };
 
-    let args = Cli::parse();
+        // .html output shows the frames as they are converted, until the final output replaces
+        // them
+        let vertical = matches!(direction, TextDirection::VerticalRl);
+        let mut live_html = out_path
+            .filter(|path| path.extension().unwrap() == "html")
+            .map(|path| HtmlWriter::create(path, fps, vertical, paper));
 
-    let width = args.width;
-    info!("width\t{:?}", width);
+        // frames saved by an interrupted run are reused instead of being converted again
+        let mut checkpoint_cells: Vec<CellRows> = match checkpoint {
+            Some(path) if args.resume && path.exists() => read_checkpoint(path),
+            _ => Vec::new(),
+        };
+        if checkpoint_cells.len() > frames.len() {
+            panic!("The checkpoint has more frames than the input");
+        }
+        info!("resumed frames\t{}", checkpoint_cells.len());
+        let mut last_checkpoint = Instant::now();
 
-    let image_path = Path::new(&args.image_path);
-    info!("image path\t{:?}", image_path);
-    let in_extension = image_path.extension().unwrap();
+        // previews are only shown for animations, whose conversion can take long enough to abort
+        let preview_every = args.preview_every.filter(|_| frames.len() > 1);
+        info!("preview every\t{:?}", args.preview_every);
+        info!("preview path\t{:?}", args.preview_path);
+        if preview_every == Some(0) {
+            panic!("Unsupported preview interval 0");
+        }
 
-    let alphabet_str = &args.alphabet;
-    let alphabet_map: HashMap<&str, &str> = ALPHABETS.iter().cloned().collect();
-    let alphabet: Vec<char> = if alphabet_map.contains_key(&alphabet_str.as_ref()) {
-        info!("alphabet name\t{:?}", alphabet_str);
-        alphabet_map
-            .get(&alphabet_str.as_ref())
-            .unwrap()
-            .chars()
-            .collect()
-    } else {
-        let alphabet_path = Path::new(alphabet_str);
-        info!("alphabet path\t{:?}", alphabet_path);
-        fs::read(&alphabet_path)
-            .unwrap()
-            .iter()
-            .map(|&b| b as char)
-            .collect()
+        let mut frame_char_rows: Vec<Vec<Vec<char>>> = Vec::new();
+        let mut confidence_frames: Vec<Vec<Vec<CharConfidence>>> = Vec::new();
+        let mut intensity_frames: Vec<Vec<Vec<f32>>> = Vec::new();
+        logging::set_stage("convert");
+        // ctrl-c stops converting an animation and writes out the frames converted so far
+        if frames.len() > 1 {
+            interrupt::catch_interrupts();
+        }
+        let progress = default_progress_bar("Frames", frames.len());
+        for (i, img) in frames.iter().enumerate().progress_with(progress.clone()) {
+            if interrupt::interrupted() {
+                break;
+            }
+            logging::set_frame(Some(i));
+            if let Some(cells) = checkpoint_cells.get(i) {
+                frame_char_rows.push(cells_to_char_rows(cells));
+                if scorer.is_some() {
+                    confidence_frames.push(cells_to_confidence(cells));
+                }
+            } else if let RenderMode::Shade = mode {
+                let ascii = shade_char_rows(&font, &luma(img, i), width, brightness_offset / 255.);
+                if checkpoint.is_some() {
+                    checkpoint_cells.push(char_rows_to_cells(&ascii, img));
+                }
+                frame_char_rows.push(ascii);
+            } else if let Some(scorer) = scorer {
+                let luma_img = luma(img, i);
+                let (ascii, confidence) = convert::img_to_char_rows_with_confidence(
+                    &font,
+                    &luma_img,
+                    convert,
+                    scorer,
+                    width,
+                    brightness_offset / 255.,
+                    &conversion_algorithm,
+                    selection.as_ref(),
+                );
+                let ascii = with_right_view(with_histogram(ascii, &luma_img), i);
+                if checkpoint.is_some() {
+                    let mut cells = char_rows_to_cells(&ascii, img);
+                    add_confidence(&mut cells, &confidence);
+                    checkpoint_cells.push(cells);
+                }
+                frame_char_rows.push(ascii);
+                confidence_frames.push(confidence);
+            } else {
+                let luma_img = luma(img, i);
+                let ascii = convert::img_to_char_rows(
+                    &font,
+                    &luma_img,
+                    convert,
+                    width,
+                    brightness_offset / 255.,
+                    &conversion_algorithm,
+                    selection.as_ref(),
+                );
+                let ascii =
+                    with_right_view(with_histogram(with_rules(ascii, &luma_img), &luma_img), i);
+                if checkpoint.is_some() {
+                    checkpoint_cells.push(char_rows_to_cells(&ascii, img));
+                }
+                frame_char_rows.push(ascii);
+            }
+            if store_intensity {
+                intensity_frames.push(convert::img_to_intensity_rows(
+                    &font,
+                    &luma(img, i),
+                    width,
+                    brightness_offset / 255.,
+                    &conversion_algorithm,
+                ));
+            }
+            if let Some(path) = checkpoint {
+                if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                    write_checkpoint(path, &checkpoint_cells);
+                    last_checkpoint = Instant::now();
+                }
+            }
+            if let Some(live_html) = &mut live_html {
+                let char_rows = frame_char_rows.last().unwrap();
+                let (char_rows, img) = char_rows_in_reading_order(char_rows, img, &direction);
+                live_html.push_frame(&if color {
+                    char_rows_to_html_color_string(&char_rows, &img)
+                } else {
+                    escape_html(&char_rows_to_string(&char_rows))
+                });
+            }
+            let char_rows = frame_char_rows.last().unwrap();
+            info!("cells\t{}x{}", char_rows[0].len(), char_rows.len());
+            if preview_every.is_some_and(|every| i % every == 0) {
+                match &args.preview_path {
+                    Some(path) => render_char_rows(char_rows, &render_font, img, color)
+                        .save(path)
+                        .unwrap(),
+                    None => {
+                        let preview = if color {
+                            char_rows_to_terminal_color_string(char_rows, img)
+                        } else {
+                            char_rows_to_string(char_rows)
+                        };
+                        // printed above the progress bar, which is redrawn below it
+                        let preview = format!("frame {}\n{}", i, preview);
+                        if progress.is_hidden() {
+                            eprintln!("{}", preview);
+                        } else {
+                            progress.println(preview);
+                        }
+                    }
+                }
+            }
+        }
+        logging::set_frame(None);
+        // an interruption while the output is encoded can resume without converting anything
+        if let Some(path) = checkpoint {
+            write_checkpoint(path, &checkpoint_cells);
+        }
+        if interrupt::take_interrupt() {
+            interrupted = true;
+            eprintln!(
+                "interrupted: converted {} of {} frames",
+                frame_char_rows.len(),
+                frames.len()
+            );
+            if frame_char_rows.is_empty() {
+                std::process::exit(130);
+            }
+        }
+        let mut frames = frames;
+        frames.truncate(frame_char_rows.len());
+        let frames: Vec<DynamicImage> = if anaglyph {
+            frames
+                .iter()
+                .zip(&right_frames)
+                .map(|(left, right)| anaglyph_img(left, right))
+                .collect()
+        } else {
+            frames
+        };
+        (frame_char_rows, frames, confidence_frames, intensity_frames)
     };
-    info!("alphabet\t[{}]", alphabet.iter().collect::<String>());
 
-    let font_str = &args.font;
-    let font_map: HashMap<&str, &str> = FONTS.iter().cloned().collect();
-    let font: font::Font = if font_map.contains_key(&font_str.as_ref()) {
-        info!("font name\t{:?}", font_str);
-        let font_data = font_map.get(&font_str.as_ref()).unwrap();
-        Font::from_bdf_stream(font_data.as_bytes(), &alphabet)
+    logging::set_stage("postprocess");
+
+    let frames: Vec<DynamicImage> = match edge_color {
+        EdgeColor::Source => frames,
+        EdgeColor::White => {
+            let (n_cols, n_rows) = (frame_char_rows[0][0].len(), frame_char_rows[0].len());
+            let white = RgbImage::from_pixel(n_cols as u32, n_rows as u32, Rgb([255, 255, 255]));
+            vec![DynamicImage::ImageRgb8(white); frames.len()]
+        }
+        EdgeColor::GradientAngle => {
+            let (n_cols, n_rows) = (frame_char_rows[0][0].len(), frame_char_rows[0].len());
+            frames
+                .iter()
+                .map(|frame| gradient_angle_img(&LumaImage::from(frame), n_cols, n_rows))
+                .collect()
+        }
+        EdgeColor::GradientMagnitude => {
+            let (n_cols, n_rows) = (frame_char_rows[0][0].len(), frame_char_rows[0].len());
+            frames
+                .iter()
+                .map(|frame| gradient_magnitude_img(&LumaImage::from(frame), n_cols, n_rows))
+                .collect()
+        }
+    };
+
+    let frames: Vec<DynamicImage> = match shade_fg {
+        Some(fg) => {
+            let fg = RgbImage::from_pixel(1, 1, fg);
+            vec![DynamicImage::ImageRgb8(fg); frames.len()]
+        }
+        None => frames,
+    };
+
+    let (frame_char_rows, frames, confidence_frames, intensity_frames) = if trim {
+        // use the union of all frame bounds so animated output keeps a constant size
+        let bounds = frame_char_rows
+            .iter()
+            .map(|char_rows| content_bounds(char_rows))
+            .fold(None, union_bounds);
+        info!("trim bounds\t{:?}", bounds);
+        if let Some(bounds) = bounds {
+            let (n_cols, n_rows) = (frame_char_rows[0][0].len(), frame_char_rows[0].len());
+            let frames = frames
+                .iter()
+                .map(|frame| crop_img_to_cells(frame, bounds, n_cols, n_rows))
+                .collect();
+            let confidence_frames = confidence_frames
+                .iter()
+                .map(|confidence| crop_cells(confidence, bounds))
+                .collect();
+            let intensity_frames = intensity_frames
+                .iter()
+                .map(|intensity| crop_cells(intensity, bounds))
+                .collect();
+            let frame_char_rows = frame_char_rows
+                .iter()
+                .map(|char_rows| crop_char_rows(char_rows, bounds))
+                .collect();
+            (frame_char_rows, frames, confidence_frames, intensity_frames)
+        } else {
+            (frame_char_rows, frames, confidence_frames, intensity_frames)
+        }
     } else {
-        let font_path = Path::new(font_str);
-        info!("font path\t{:?}", font_path);
-        Font::from_bdf(font_path, &alphabet)
+        (frame_char_rows, frames, confidence_frames, intensity_frames)
     };
+    let (frame_char_rows, frames, confidence_frames, intensity_frames) =
+        if let Some(transform) = &grid_transform {
+            let frames = frames
+                .iter()
+                .map(|frame| transform_img(frame, transform))
+                .collect();
+            let confidence_frames = confidence_frames
+                .iter()
+                .map(|confidence| transform_cells(confidence, transform))
+                .collect();
+            let intensity_frames = intensity_frames
+                .iter()
+                .map(|intensity| transform_cells(intensity, transform))
+                .collect();
+            let frame_char_rows = frame_char_rows
+                .iter()
+                .map(|char_rows| transform_char_rows(char_rows, transform, mirror_glyphs, &font))
+                .collect();
+            (frame_char_rows, frames, confidence_frames, intensity_frames)
+        } else {
+            (frame_char_rows, frames, confidence_frames, intensity_frames)
+        };
+    let (frame_char_rows, frames, confidence_frames, intensity_frames) =
+        if let Some(rotation) = &grid_rotation {
+            let frames = frames
+                .iter()
+                .map(|frame| transform_img(frame, rotation))
+                .collect();
+            let confidence_frames = confidence_frames
+                .iter()
+                .map(|confidence| transform_cells(confidence, rotation))
+                .collect();
+            let intensity_frames = intensity_frames
+                .iter()
+                .map(|intensity| transform_cells(intensity, rotation))
+                .collect();
+            let frame_char_rows = frame_char_rows
+                .iter()
+                .map(|char_rows| transform_char_rows(char_rows, rotation, mirror_glyphs, &font))
+                .collect();
+            (frame_char_rows, frames, confidence_frames, intensity_frames)
+        } else {
+            (frame_char_rows, frames, confidence_frames, intensity_frames)
+        };
 
-    let metric = args.metric;
-    info!("metric\t{}", metric);
+    if let Some(path) = &error_map {
+        if text_input.is_some() {
+            panic!("The error map can only be measured for images which are converted to ascii");
+        }
+        let heatmaps: Vec<DynamicImage> = frame_char_rows
+            .iter()
+            .zip(&frames)
+            .map(|(char_rows, frame)| {
+                error_heatmap(&char_rows_error(char_rows, &font, frame), &font)
+            })
+            .collect();
+        if path.extension().unwrap() == "gif" {
+            write_gif(path, &heatmaps, fps);
+        } else {
+            heatmaps[0].save(path).unwrap();
+        }
+    }
 
-    let out_path = args.out_path.as_ref().map(|name| Path::new(name));
-    info!("out path\t{:?}", out_path);
+    // the first frame of every scene, so that cross-frame features start over at hard cuts
+    let scene_starts: Vec<usize> = match scene_cuts_threshold {
+        Some(threshold) => {
+            let mut starts = vec![0];
+            starts.extend(scene_cuts(&frames, threshold));
+            starts
+        }
+        None => vec![0],
+    };
+    info!("scene starts\t{:?}", scene_starts);
 
-    let fps = args.fps;
-    info!("fps\t{}", fps);
+    let frames: Vec<DynamicImage> = if color && (auto_palette.is_some() || palette.is_some()) {
+        // colors are only sampled once per cell, so the palette is fit to the cell colors
+        let (n_cols, n_rows) = (frame_char_rows[0][0].len(), frame_char_rows[0].len());
+        let cell_frames: Vec<DynamicImage> = frames
+            .iter()
+            .map(|frame| frame.resize_exact(n_cols as u32, n_rows as u32, FilterType::Nearest))
+            .collect();
+        let fit_palette = |scene_frames: &[DynamicImage]| {
+            if let Some(palette) = &palette {
+                palette.clone()
+            } else {
+                let colors: Vec<Rgb<u8>> = scene_frames
+                    .iter()
+                    .flat_map(|frame| {
+                        frame
+                            .to_rgba8()
+                            .pixels()
+                            .filter(|pixel| pixel[3] > 0)
+                            .map(|pixel| Rgb([pixel[0], pixel[1], pixel[2]]))
+                            .collect::<Vec<Rgb<u8>>>()
+                    })
+                    .collect();
+                kmeans_palette(&colors, auto_palette.unwrap(), 32)
+            }
+        };
+        // one palette per scene
+        let scene_ends = scene_starts
+            .iter()
+            .skip(1)
+            .copied()
+            .chain([cell_frames.len()]);
+        scene_starts
+            .iter()
+            .zip(scene_ends)
+            .flat_map(|(&start, end)| {
+                let scene_frames = &cell_frames[start..end];
+                let palette = fit_palette(scene_frames);
+                info!("palette\t{:?}", palette);
+                if palette.is_empty() {
+                    scene_frames.to_vec()
+                } else {
+                    scene_frames
+                        .iter()
+                        .map(|frame| quantize_img(frame, &palette))
+                        .collect()
+                }
+            })
+            .collect()
+    } else {
+        frames
+    };
 
-    let color = !args.no_color;
-    info!("color\t{}", color);
+    let (n_cols, n_rows) = (frame_char_rows[0][0].len(), frame_char_rows[0].len());
+    if let Some(path) = &confidence_map {
+        let heatmaps: Vec<DynamicImage> = confidence_frames
+            .iter()
+            .map(|confidence| confidence_heatmap(confidence, &font))
+            .collect();
+        if heatmaps.is_empty() {
+            panic!("Confidence can only be measured for images which are converted to ascii");
+        } else if path.extension().unwrap() == "gif" {
+            write_gif(path, &heatmaps, fps);
+        } else {
+            heatmaps[0].save(path).unwrap();
+        }
+    }
 
-    let brightness_offset = args.brightness_offset;
-    info!("brightness\t{}", brightness_offset);
+    let (frame_char_rows, frames) = if let Some(order) = &reveal {
+        if frame_char_rows.len() > 1 {
+            panic!("Reveal animations can only be made from still input");
+        }
+        // the final output is held for a second before the animation loops
+        let n_frames = ((reveal_duration * fps).round() as usize).max(1);
+        let mut revealed = reveal_char_rows(
+            &frame_char_rows[0],
+            order,
+            n_frames,
+            font.height as f32 / font.width as f32,
+            seed,
+        );
+        revealed.extend(vec![frame_char_rows[0].clone(); fps.round() as usize]);
+        let frames = vec![frames[0].clone(); revealed.len()];
+        (revealed, frames)
+    } else {
+        (frame_char_rows, frames)
+    };
 
-    let conversion_algorithm = args.conversion_algorithm;
-    info!("conversion alg\t{}", conversion_algorithm);
+    let (frame_char_rows, frames) = if let Some(palette) = &color_cycle {
+        if frame_char_rows.len() > 1 {
+            panic!("Color cycles can only be made from still input");
+        }
+        if !color {
+            eprintln!("warning: --color-cycle only changes the colors of colored output");
+        }
+        // the chars are matched once, and only the colors of their cells change between frames
+        let (n_cols, n_rows) = (frame_char_rows[0][0].len(), frame_char_rows[0].len());
+        let cells = frames[0].resize_exact(n_cols as u32, n_rows as u32, FilterType::Nearest);
+        let n_frames = ((cycle_duration * fps).round() as usize).max(1);
+        let frames = cycle_color_frames(&cells, palette, n_frames);
+        (vec![frame_char_rows[0].clone(); frames.len()], frames)
+    } else {
+        (frame_char_rows, frames)
+    };
 
-    let convert = get_converter(&metric);
-    let conversion_algorithm = get_conversion_algorithm(&conversion_algorithm);
-    info!("converter\t{:?}", convert);
+    let (frame_char_rows, frames) = if let Some(signature) = &args.signature {
+        let (n_cols, n_rows) = (frame_char_rows[0][0].len(), frame_char_rows[0].len());
+        let signature_color = parse_hex_color(&args.signature_color);
+        frame_char_rows
+            .iter()
+            .zip(&frames)
+            .map(|(char_rows, frame)| {
+                let (char_rows, bounds) = sign_char_rows(char_rows, signature, &font);
+                let frame = fill_img_cells(frame, bounds, n_cols, n_rows, signature_color);
+                (char_rows, frame)
+            })
+            .unzip()
+    } else {
+        (frame_char_rows, frames)
+    };
+
+    // like palettes, the contrast with the background only needs to be fixed once per cell
+    let frames: Vec<DynamicImage> = match min_contrast {
+        Some(min_contrast) if color => frame_char_rows
+            .iter()
+            .zip(&frames)
+            .map(|(char_rows, frame)| {
+                let (n_cols, n_rows) = (char_rows[0].len() as u32, char_rows.len() as u32);
+                contrast_img(
+                    &frame.resize_exact(n_cols, n_rows, FilterType::Nearest),
+                    background.unwrap_or(Rgb([0, 0, 0])),
+                    min_contrast,
+                )
+            })
+            .collect(),
+        _ => frames,
+    };
 
-    info!("converting frames to ascii...");
-    let frames: Vec<DynamicImage> = if in_extension == "gif" {
-        let gif = gif::read_gif(image_path);
-        gif.iter().cloned().collect()
+    let frames: Vec<DynamicImage> = if let Some(color_vision) = &simulate {
+        frames
+            .iter()
+            .map(|frame| simulate_color_vision(frame, color_vision))
+            .collect()
     } else {
-        let img = ::image::open(image_path).unwrap();
-        vec![img]
+        frames
     };
 
-    let mut frame_char_rows: Vec<Vec<Vec<char>>> = Vec::new();
-    let progress = default_progress_bar("Frames", frames.len());
-    for img in frames.iter().progress_with(progress) {
-        let ascii = convert::img_to_char_rows(
-            &font,
-            &LumaImage::from(img),
-            convert,
-            width,
-            brightness_offset / 255.,
-            &conversion_algorithm,
-        );
-        frame_char_rows.push(ascii);
-    }
+    let to_plain_string = |char_rows: &[Vec<char>]| {
+        let s = match direction {
+            TextDirection::HorizontalTb => char_rows_to_string(char_rows),
+            TextDirection::VerticalRl => char_rows_to_string(&rotate_char_rows_ccw(char_rows)),
+        };
+        if trim {
+            trim_trailing_whitespace(&s)
+        } else {
+            s
+        }
+    };
+
+    // shade mode and paper can draw on a background color instead of black, where uncolored
+    // characters may no longer be white
+    let white = background.map_or(Rgb([255, 255, 255]), ink_color);
+    let white = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, white));
+    let render = |char_rows: &[Vec<char>], frame: &DynamicImage| {
+        let img = match background {
+            Some(background) => char_rows_to_color_bitmap_with_background(
+                char_rows,
+                &render_font,
+                if color { frame } else { &white },
+                background,
+            ),
+            None => render_char_rows(char_rows, &render_font, frame, color),
+        };
+        let img = match grid {
+            Grid::Square => img,
+            _ => offset_cell_rows(
+                &img,
+                render_font.width,
+                render_font.height,
+                grid,
+                background.unwrap_or(Rgb([0, 0, 0])),
+            ),
+        };
+        // overlays are transparent around the glyphs, unless they are drawn on a background
+        if on_canvas {
+            place_on_canvas(
+                &img,
+                canvas.unwrap_or((img.width(), img.height())),
+                background.is_none(),
+                args.overlay_safe_area,
+                args.safe_area_guides,
+            )
+        } else {
+            img
+        }
+    };
 
+    logging::set_stage("write");
     if let Some(path) = out_path {
         let out_extension = path.extension().unwrap();
 
-        if out_extension == "json" {
-            let out_frames: Vec<String> = if color {
+        // text outputs get a sidecar with everything needed to reproduce them later, and .gif and
+        // .png outputs get it embedded (posters are split over several files, so they don't)
+        let embeds_meta = (out_extension == "gif" || out_extension == "png") && poster.is_none();
+        let has_meta = out_extension == "html"
+            || out_extension == "json"
+            || out_extension == "ascz"
+            || binary_format.is_some()
+            || embeds_meta;
+        let meta =
+            has_meta.then(|| with_output_size(&base_meta(), n_cols, n_rows, frame_char_rows.len()));
+        // archives carry their metadata inside
+        if let Some(meta) = meta
+            .as_ref()
+            .filter(|_| out_extension != "ascz" && !embeds_meta)
+        {
+            write_sidecar(path, meta);
+        }
+        // and .gif and .png outputs have it written in while they are encoded
+        let embedded_meta = meta.as_ref().filter(|_| embeds_meta);
+
+        if out_extension == "html" {
+            let out_frames: Vec<String> = if let Some(links) = &links {
                 frame_char_rows
                     .iter()
                     .zip(frames)
-                    .map(|(char_rows, frame)| char_rows_to_html_color_string(char_rows, &frame))
+                    .map(|(char_rows, frame)| {
+                        let (char_rows, frame) =
+                            char_rows_in_reading_order(char_rows, &frame, &direction);
+                        let cells = if color {
+                            char_rows_to_html_color_cells(&char_rows, &frame)
+                        } else {
+                            char_rows_to_cell_strings(&char_rows)
+                                .iter()
+                                .map(|s| escape_html(s))
+                                .collect()
+                        };
+                        join_linked_cell_strings(
+                            &cells,
+                            char_rows[0].len(),
+                            links,
+                            &LinkFormat::Html,
+                        )
+                    })
+                    .collect()
+            } else if color {
+                par_map(&frame_char_rows, |i, char_rows| {
+                    let (char_rows, frame) =
+                        char_rows_in_reading_order(char_rows, &frames[i], &direction);
+                    char_rows_to_html_color_string(&char_rows, &frame)
+                })
+            } else {
+                frame_char_rows
+                    .iter()
+                    .map(|char_rows| escape_html(&to_plain_string(char_rows)))
                     .collect()
+            };
+            let vertical = matches!(direction, TextDirection::VerticalRl);
+            write_html(path, &out_frames, fps, vertical, paper);
+        } else if out_extension == "json" && json_format == "html" {
+            let out_frames: Vec<String> = if color {
+                par_map(&frame_char_rows, |i, char_rows| {
+                    let (char_rows, frame) =
+                        char_rows_in_reading_order(char_rows, &frames[i], &direction);
+                    char_rows_to_html_color_string(&char_rows, &frame)
+                })
             } else {
                 frame_char_rows
                     .iter()
-                    .map(|char_rows| char_rows_to_string(char_rows))
+                    .map(|char_rows| to_plain_string(char_rows))
                     .collect()
             };
             let json = serde_json::to_string(&out_frames).unwrap();
             fs::write(path, json).unwrap();
+        } else if (out_extension == "json" || binary_format.is_some()) && json_format != "html" {
+            let mut out_frames: Vec<CellRows> = frame_char_rows
+                .iter()
+                .zip(frames)
+                .map(|(char_rows, frame)| {
+                    char_rows_to_cells(char_rows, if color { &frame } else { &white })
+                })
+                .collect();
+            if confidence {
+                for (cells, confidence) in out_frames.iter_mut().zip(&confidence_frames) {
+                    add_confidence(cells, confidence);
+                }
+            }
+            for (cells, intensity) in out_frames.iter_mut().zip(&intensity_frames) {
+                add_intensity(cells, intensity);
+            }
+            let cell_size = Some((font.width, font.height));
+            let mut json = match json_format {
+                "cells" => cells_to_json(&out_frames, cell_size),
+                _ => cells_to_planes_json(&out_frames, cell_size, json_format == "planes-base64"),
+            };
+            if scene_cuts_threshold.is_some() {
+                for &i in &scene_starts {
+                    json["frames"][i]["keyframe"] = serde_json::Value::Bool(true);
+                }
+            }
+            let bytes = match &binary_format {
+                Some(binary_format) => binary::encode(&json, binary_format),
+                None => serde_json::to_vec(&json).unwrap(),
+            };
+            match &compression {
+                Some(compression) => fs::write(path, binary::compress(&bytes, compression)),
+                None => fs::write(path, bytes),
+            }
+            .unwrap();
+        } else if out_extension == "ascz" {
+            let cells: Vec<CellRows> = frame_char_rows
+                .iter()
+                .zip(&frames)
+                .map(|(char_rows, frame)| {
+                    char_rows_to_cells(char_rows, if color { frame } else { &white })
+                })
+                .collect();
+            let cells_json = cells_to_json(&cells, Some((font.width, font.height)));
+            let mut entries = vec![
+                (
+                    String::from("meta.json"),
+                    serde_json::to_vec_pretty(meta.as_ref().unwrap()).unwrap(),
+                ),
+                (
+                    String::from("cells.json"),
+                    serde_json::to_vec(&cells_json).unwrap(),
+                ),
+            ];
+            for (i, char_rows) in frame_char_rows.iter().enumerate() {
+                let text = to_plain_string(char_rows);
+                entries.push((format!("frames/{:06}.txt", i), text.into_bytes()));
+            }
+            if args.archive_bitmaps {
+                info!("converting ascii strings to bitmaps...");
+                let progress = default_progress_bar("Frames", frame_char_rows.len());
+                for (i, (char_rows, frame)) in frame_char_rows
+                    .iter()
+                    .zip(&frames)
+                    .enumerate()
+                    .progress_with(progress)
+                {
+                    let mut png = Cursor::new(Vec::new());
+                    render(char_rows, frame)
+                        .write_to(&mut png, ImageOutputFormat::Png)
+                        .unwrap();
+                    entries.push((format!("frames/{:06}.png", i), png.into_inner()));
+                }
+            }
+            write_archive(path, &entries);
         } else if out_extension == "gif" {
             info!("converting ascii strings to bitmaps...");
             let progress = default_progress_bar("Frames", frame_char_rows.len());
-            let out_frames: Vec<DynamicImage> = if color {
-                frame_char_rows
-                    .iter()
-                    .zip(frames)
-                    .progress_with(progress)
-                    .map(|(char_rows, frame)| char_rows_to_color_bitmap(&char_rows, &font, &frame))
-                    .collect()
+            let out_frames: Vec<DynamicImage> = frame_char_rows
+                .iter()
+                .zip(frames)
+                .progress_with(progress)
+                .take_while(|_| !interrupt::interrupted())
+                .map(|(char_rows, frame)| render(char_rows, &frame))
+                .collect();
+            let comment = embedded_meta.map(gif_comment);
+            let n_written = if gif_global_palette {
+                write_gif_with_global_palette(path, &out_frames, fps, comment.as_deref())
             } else {
-                frame_char_rows
-                    .iter()
-                    .progress_with(progress)
-                    .map(|char_rows| char_rows_to_bitmap(&char_rows, &font))
-                    .collect()
+                write_gif_with_comment(path, &out_frames, fps, comment.as_deref())
             };
-            write_gif(path, &out_frames, fps);
+            if interrupt::take_interrupt() {
+                interrupted = true;
+                eprintln!(
+                    "interrupted: wrote {} of {} frames to {:?}",
+                    n_written,
+                    frame_char_rows.len(),
+                    path
+                );
+            }
         } else if out_extension == "mp4" {
             info!("converting ascii strings to bitmaps...");
             let progress = default_progress_bar("Frames", frame_char_rows.len());
-            let out_frames: Vec<DynamicImage> = if color {
-                frame_char_rows
-                    .iter()
-                    .zip(frames)
-                    .progress_with(progress)
-                    .map(|(char_rows, frame)| char_rows_to_color_bitmap(&char_rows, &font, &frame))
-                    .collect()
-            } else {
-                frame_char_rows
-                    .iter()
-                    .progress_with(progress)
-                    .map(|char_rows| char_rows_to_bitmap(&char_rows, &font))
-                    .collect()
-            };
+            let out_frames: Vec<DynamicImage> = frame_char_rows
+                .iter()
+                .zip(frames)
+                .progress_with(progress)
+                .take_while(|_| !interrupt::interrupted())
+                .map(|(char_rows, frame)| render(char_rows, &frame))
+                .collect();
 
             let tmp_dir = temp_dir().join("image-to-ascii-frames");
             if !tmp_dir.exists() {
@@ -217,10 +2765,11 @@ fn main() {
                 .iter()
                 .enumerate()
                 .progress_with(default_progress_bar("Frames", out_frames.len()))
+                .take_while(|_| !interrupt::interrupted())
             {
                 frame
                     .save(tmp_dir.join(format!("{}.png", i)))
-                    .expect(&format!("Failed to write frame {}", i));
+                    .unwrap_or_else(|_| panic!("Failed to write frame {}", i));
             }
             let output = std::process::Command::new("ffmpeg")
                 .args([
@@ -236,29 +2785,97 @@ fn main() {
             if let Err(err) = output {
                 panic!("Error while writing mp4 frames with ffmpeg: {}", err);
             }
-        } else {
-            let img = if color {
-                char_rows_to_color_bitmap(&frame_char_rows[0], &font, &frames[0])
-            } else {
-                char_rows_to_bitmap(&frame_char_rows[0], &font)
+        } else if let Some((pages_wide, pages_high)) = poster {
+            let img = render(&frame_char_rows[0], &frames[0]);
+            write_poster(path, &img, pages_wide, pages_high);
+        } else if out_extension == "png" && grid == Grid::Square && !on_canvas {
+            let (char_rows, frame) = (&frame_char_rows[0], &frames[0]);
+            let (frame, color) = match background {
+                Some(_) if !color => (&white, true),
+                _ => (frame, color),
             };
+            let background = background.unwrap_or(Rgb([0, 0, 0]));
+            write_char_rows_png(
+                path,
+                char_rows,
+                &render_font,
+                frame,
+                color,
+                background,
+                1,
+                embedded_meta,
+            );
+        } else if out_extension == "png" {
+            let img = render(&frame_char_rows[0], &frames[0]);
+            write_png(path, &img, embedded_meta);
+        } else {
+            let img = render(&frame_char_rows[0], &frames[0]);
             img.save(path).unwrap();
         }
     } else {
-        let out_frames: Vec<String> = if color {
+        let out_frames: Vec<String> = if links.is_some() || background.is_some() {
             frame_char_rows
                 .iter()
                 .zip(frames)
-                .map(|(char_rows, frame)| char_rows_to_terminal_color_string(char_rows, &frame))
+                .map(|(char_rows, frame)| {
+                    let (char_rows, frame) =
+                        char_rows_in_reading_order(char_rows, &frame, &direction);
+                    let mut cells = if color {
+                        char_rows_to_terminal_color_cells(&char_rows, &frame)
+                    } else if background.is_some() {
+                        char_rows_to_terminal_color_cells(&char_rows, &white)
+                    } else {
+                        char_rows_to_cell_strings(&char_rows)
+                    };
+                    if let Some(background) = background {
+                        cells = with_terminal_background(&cells, background);
+                    }
+                    match &links {
+                        Some(links) => join_linked_cell_strings(
+                            &cells,
+                            char_rows[0].len(),
+                            links,
+                            &LinkFormat::Terminal,
+                        ),
+                        None => join_cell_strings(&cells, char_rows[0].len()),
+                    }
+                })
                 .collect()
+        } else if color {
+            par_map(&frame_char_rows, |i, char_rows| {
+                let (char_rows, frame) =
+                    char_rows_in_reading_order(char_rows, &frames[i], &direction);
+                char_rows_to_terminal_color_string(&char_rows, &frame)
+            })
         } else {
             frame_char_rows
                 .iter()
-                .map(|char_rows| char_rows_to_string(char_rows))
+                .map(|char_rows| to_plain_string(char_rows))
                 .collect()
         };
 
-        if in_extension == "gif" {
+        if args.inline_playback && (input_format.is_animation() || out_frames.len() > 1) {
+            // redraw each frame over the previous one with cursor-up instead of clearing the
+            // screen, clearing the rest of each line in case the previous frame's was longer
+            let mut n_lines = 0;
+            loop {
+                for frame in &out_frames {
+                    let t0 = Instant::now();
+                    if n_lines > 0 {
+                        print!("{}[{}A\r", 27 as char, n_lines);
+                    }
+                    for line in frame.lines() {
+                        println!("{}{}[K", line, 27 as char);
+                    }
+                    n_lines = frame.lines().count();
+                    let elapsed = t0.elapsed().as_secs_f64();
+                    let delay = (1.0 / fps) - elapsed;
+                    if delay > 0.0 {
+                        sleep(Duration::from_secs_f64(delay));
+                    }
+                }
+            }
+        } else if input_format.is_animation() || out_frames.len() > 1 {
             loop {
                 for frame in &out_frames {
                     let t0 = Instant::now();
@@ -274,4 +2891,7 @@ fn main() {
             println!("{}", out_frames[0]);
         }
     }
+    if interrupted || interrupt::interrupted() {
+        std::process::exit(130);
+    }
 }
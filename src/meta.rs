@@ -0,0 +1,183 @@
+use png::Encoder;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    // art.html gets art.html.meta.json, so outputs with the same stem don't share a sidecar
+    let mut name = path.file_name().unwrap().to_os_string();
+    name.push(".meta.json");
+    path.with_file_name(name)
+}
+
+pub fn file_crc32(path: &Path) -> String {
+    format!("{:08x}", crc32fast::hash(&fs::read(path).unwrap()))
+}
+
+pub fn write_sidecar(path: &Path, meta: &Value) {
+    fs::write(
+        sidecar_path(path),
+        serde_json::to_string_pretty(meta).unwrap(),
+    )
+    .unwrap();
+}
+
+pub fn read_sidecar(path: &Path) -> Value {
+    // either the output or its sidecar can be given
+    let path = if path.to_string_lossy().ends_with(".meta.json") {
+        path.to_path_buf()
+    } else {
+        sidecar_path(path)
+    };
+    let meta =
+        fs::read_to_string(&path).unwrap_or_else(|err| panic!("Error reading {:?}: {}", path, err));
+    serde_json::from_str(&meta)
+        .unwrap_or_else(|err| panic!("Unsupported sidecar {:?}: {}", path, err))
+}
+
+pub fn source_status(meta: &Value) -> &'static str {
+    // whether the source can still be used to reproduce the output
+    let source = match meta["source"].as_str() {
+        Some(source) => Path::new(source),
+        None => return "unknown",
+    };
+    if !source.exists() {
+        "missing"
+    } else if meta["source_crc32"] == json!(file_crc32(source)) {
+        "unchanged"
+    } else {
+        "changed"
+    }
+}
+
+// identifies the metadata among other comments and text chunks
+const EMBED_KEYWORD: &str = "image-to-ascii";
+
+fn ascii_json(meta: &Value) -> String {
+    // gif comments are meant to be ascii, so everything else is escaped, which is still valid json
+    let mut s = String::new();
+    for c in serde_json::to_string(meta).unwrap().chars() {
+        if c.is_ascii() {
+            s.push(c);
+        } else {
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units) {
+                s.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    s
+}
+
+fn gif_blocks_start(gif: &[u8]) -> usize {
+    // the blocks come after the header, the logical screen descriptor, and the global palette
+    let packed = gif[10];
+    if packed & 0x80 != 0 {
+        13 + 3 * (1 << ((packed & 7) + 1))
+    } else {
+        13
+    }
+}
+
+fn read_gif_comments(gif: &[u8]) -> Vec<Vec<u8>> {
+    let mut comments = Vec::new();
+    let mut i = gif_blocks_start(gif);
+    let sub_blocks = |mut i: usize, data: &mut Vec<u8>| {
+        while i < gif.len() && gif[i] != 0 {
+            let end = (i + 1 + gif[i] as usize).min(gif.len());
+            data.extend(&gif[i + 1..end]);
+            i = end;
+        }
+        i + 1
+    };
+    while i < gif.len() {
+        match gif[i] {
+            0x21 if i + 1 < gif.len() => {
+                let mut data = Vec::new();
+                let label = gif[i + 1];
+                i = sub_blocks(i + 2, &mut data);
+                if label == 0xfe {
+                    comments.push(data);
+                }
+            }
+            0x2c if i + 10 < gif.len() => {
+                // the image descriptor, its local palette, the lzw code size, and the image data
+                let packed = gif[i + 9];
+                i += 10;
+                if packed & 0x80 != 0 {
+                    i += 3 * (1 << ((packed & 7) + 1));
+                }
+                i = sub_blocks(i + 1, &mut Vec::new());
+            }
+            _ => break,
+        }
+    }
+    comments
+}
+
+fn read_png_texts(png: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    // the keyword and text of every uncompressed iTXt chunk
+    let mut texts = Vec::new();
+    let mut i = 8;
+    while i + 8 <= png.len() {
+        let length = u32::from_be_bytes(png[i..i + 4].try_into().unwrap()) as usize;
+        let end = (i + 8 + length).min(png.len());
+        let (kind, data) = (&png[i + 4..i + 8], &png[i + 8..end]);
+        if kind == b"iTXt" {
+            let mut fields = data.splitn(2, |&b| b == 0);
+            let (keyword, rest) = (fields.next().unwrap(), fields.next().unwrap_or(&[]));
+            // the compression flag and method, then the language and translated keyword
+            if rest.first() == Some(&0) && rest.len() >= 2 {
+                let mut fields = rest[2..].splitn(3, |&b| b == 0);
+                if let Some(text) = fields.nth(2) {
+                    texts.push((keyword.to_vec(), text.to_vec()));
+                }
+            }
+        }
+        i = end + 4;
+    }
+    texts
+}
+
+pub fn with_output_size(meta: &Value, n_cols: usize, n_rows: usize, n_frames: usize) -> Value {
+    let mut meta = meta.clone();
+    meta["columns"] = Value::from(n_cols);
+    meta["rows"] = Value::from(n_rows);
+    meta["frames"] = Value::from(n_frames);
+    meta
+}
+
+pub fn gif_comment(meta: &Value) -> String {
+    // .gif outputs get the metadata as a comment extension
+    format!("{} {}", EMBED_KEYWORD, ascii_json(meta))
+}
+
+pub fn add_png_meta<W: Write>(encoder: &mut Encoder<W>, meta: &Value) {
+    // and .png outputs as an uncompressed iTXt chunk before the image data, since the json can
+    // have non latin-1 chars in it
+    encoder
+        .add_itxt_chunk(
+            EMBED_KEYWORD.to_string(),
+            serde_json::to_string(meta).unwrap(),
+        )
+        .unwrap();
+}
+
+pub fn read_embedded_meta(path: &Path) -> Option<Value> {
+    let data = fs::read(path).unwrap_or_else(|err| panic!("Error reading {:?}: {}", path, err));
+    let text = if data.starts_with(b"GIF8") {
+        let prefix = format!("{} ", EMBED_KEYWORD);
+        read_gif_comments(&data)
+            .into_iter()
+            .find_map(|comment| comment.strip_prefix(prefix.as_bytes()).map(<[u8]>::to_vec))
+    } else if data.starts_with(b"\x89PNG") {
+        read_png_texts(&data)
+            .into_iter()
+            .find(|(keyword, _)| keyword == EMBED_KEYWORD.as_bytes())
+            .map(|(_, text)| text)
+    } else {
+        None
+    }?;
+    serde_json::from_slice(&text).ok()
+}
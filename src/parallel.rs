@@ -0,0 +1,28 @@
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+// all parallel work goes through here, so that output never depends on the number of threads: items
+// are mapped independently and collected back in their original order, and nothing (in particular
+// no floating point sum) is reduced across threads, since that would depend on how the work is split
+
+pub fn set_threads(n_threads: usize) {
+    // 0 lets rayon pick, which is one thread per cpu unless RAYON_NUM_THREADS is set
+    ThreadPoolBuilder::new()
+        .num_threads(n_threads)
+        .build_global()
+        .unwrap();
+}
+
+pub fn par_map<T, U, F>(items: &[T], f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(usize, &T) -> U + Sync + Send,
+{
+    // f gets the index of each item too, so that other slices can be read alongside it
+    items
+        .par_iter()
+        .enumerate()
+        .map(|(i, item)| f(i, item))
+        .collect()
+}
@@ -0,0 +1,170 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage};
+use indicatif::ProgressIterator;
+use log::info;
+use png::{BitDepth, ColorType, Encoder};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::convert::{char_rows_to_bitmap, char_rows_to_color_bitmap_with_background};
+use crate::font::Font;
+use crate::meta::add_png_meta;
+use crate::progress::default_progress_bar;
+
+#[allow(clippy::too_many_arguments)]
+pub fn write_char_rows_png(
+    path: &Path,
+    char_rows: &[Vec<char>],
+    font: &Font,
+    img: &DynamicImage,
+    color: bool,
+    background: Rgb<u8>,
+    scale: u32,
+    meta: Option<&Value>,
+) {
+    // renders and encodes one row of characters at a time, so the full bitmap never has to fit
+    // in memory, which matters for large outputs at a high scale
+    let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
+    let width = (n_cols * font.width) as u32 * scale;
+    let height = (n_rows * font.height) as u32 * scale;
+    let cell_colors = img.resize_exact(n_cols as u32, n_rows as u32, FilterType::Nearest);
+
+    let fp = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = Encoder::new(fp, width, height);
+    encoder.set_color(if color {
+        ColorType::Rgb
+    } else {
+        ColorType::Grayscale
+    });
+    encoder.set_depth(BitDepth::Eight);
+    if let Some(meta) = meta {
+        add_png_meta(&mut encoder, meta);
+    }
+    let mut writer = encoder.write_header().unwrap();
+    let mut stream = writer.stream_writer().unwrap();
+
+    for (j, row) in char_rows
+        .iter()
+        .enumerate()
+        .progress_with(default_progress_bar("Rows", n_rows))
+    {
+        let band_rows = std::slice::from_ref(row);
+        let band = if color {
+            let band_colors = cell_colors.crop_imm(0, j as u32, n_cols as u32, 1);
+            char_rows_to_color_bitmap_with_background(band_rows, font, &band_colors, background)
+        } else {
+            char_rows_to_bitmap(band_rows, font)
+        };
+        let band = if scale > 1 {
+            band.resize_exact(
+                band.width() * scale,
+                band.height() * scale,
+                FilterType::Nearest,
+            )
+        } else {
+            band
+        };
+        let bytes = if color {
+            band.to_rgb8().into_raw()
+        } else {
+            band.to_luma8().into_raw()
+        };
+        stream.write_all(&bytes).unwrap();
+    }
+    stream.finish().unwrap();
+}
+
+pub fn write_png(path: &Path, img: &DynamicImage, meta: Option<&Value>) {
+    let img = img.to_rgba8();
+    let fp = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = Encoder::new(fp, img.width(), img.height());
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    if let Some(meta) = meta {
+        add_png_meta(&mut encoder, meta);
+    }
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&img).unwrap();
+}
+
+pub fn parse_poster_pages(pages: &str) -> (u32, u32) {
+    // <pages_wide>x<pages_high>
+    let parse = |value: &str| match value.parse::<u32>() {
+        Ok(n) if n > 0 => n,
+        _ => panic!("Unsupported poster size {}", pages),
+    };
+    match pages.split_once('x') {
+        Some((wide, high)) => (parse(wide), parse(high)),
+        None => panic!("Unsupported poster size {}", pages),
+    }
+}
+
+fn draw_alignment_marks(page: &mut RgbImage, margin: u32, tile_width: u32, tile_height: u32) {
+    // crop marks in the margin, lined up with the corners of the tile
+    let gap = margin / 4;
+    let length = margin - gap;
+    let thickness = (margin / 16).max(1);
+    let black = Rgb([0, 0, 0]);
+    let xs = [margin, margin + tile_width - thickness];
+    let ys = [margin, margin + tile_height - thickness];
+    for (i, &x) in xs.iter().enumerate() {
+        for (j, &y) in ys.iter().enumerate() {
+            // horizontal marks go outwards from the left or right edge of the tile
+            let mark_x = if i == 0 { 0 } else { margin + tile_width + gap };
+            for dx in 0..length {
+                for dy in 0..thickness {
+                    page.put_pixel(mark_x + dx, y + dy, black);
+                }
+            }
+            // and vertical marks go outwards from the top or bottom edge
+            let mark_y = if j == 0 {
+                0
+            } else {
+                margin + tile_height + gap
+            };
+            for dy in 0..length {
+                for dx in 0..thickness {
+                    page.put_pixel(x + dx, mark_y + dy, black);
+                }
+            }
+        }
+    }
+}
+
+pub fn write_poster(path: &Path, img: &DynamicImage, pages_wide: u32, pages_high: u32) {
+    // splits the image into equally sized pages with a white margin and alignment marks,
+    // written next to path as <stem>-<row>-<column>.png
+    let img = img.to_rgb8();
+    let tile_width = img.width().div_ceil(pages_wide);
+    let tile_height = img.height().div_ceil(pages_high);
+    let margin = (tile_width.min(tile_height) / 20).max(16);
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+
+    for row in 0..pages_high {
+        for col in 0..pages_wide {
+            let mut page = RgbImage::from_pixel(
+                tile_width + 2 * margin,
+                tile_height + 2 * margin,
+                Rgb([255, 255, 255]),
+            );
+            // pages past the edge of the image are padded with black, like the background
+            for y in 0..tile_height {
+                for x in 0..tile_width {
+                    let (img_x, img_y) = (col * tile_width + x, row * tile_height + y);
+                    let pixel = if img_x < img.width() && img_y < img.height() {
+                        *img.get_pixel(img_x, img_y)
+                    } else {
+                        Rgb([0, 0, 0])
+                    };
+                    page.put_pixel(margin + x, margin + y, pixel);
+                }
+            }
+            draw_alignment_marks(&mut page, margin, tile_width, tile_height);
+            let page_path = path.with_file_name(format!("{}-{}-{}.png", stem, row + 1, col + 1));
+            info!("poster page\t{:?}", page_path);
+            page.save(page_path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// small deterministic random number generation, so that seeded output is reproducible across
+// platforms and doesn't depend on the order in which cells are processed
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+pub fn hash(seed: u64, value: u64) -> u64 {
+    splitmix64(splitmix64(seed) ^ value)
+}
+
+pub fn hash_below(seed: u64, value: u64, n: usize) -> usize {
+    (hash(seed, value) % n as u64) as usize
+}
+
+pub fn fresh_seed() -> u64 {
+    // a seed for runs which weren't given one, which is the only thing here that isn't
+    // reproducible; it should be reported so that the run can be repeated
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    // small enough to type back in
+    hash(nanos, std::process::id() as u64) % 1_000_000_000
+}
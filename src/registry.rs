@@ -0,0 +1,105 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::Command;
+
+use log::info;
+
+pub struct RegistryFont {
+    pub name: &'static str,
+    pub url: &'static str,
+    pub license: &'static str,
+}
+
+// curated list of monospace bdf fonts which are known to load correctly
+pub const REGISTRY: [RegistryFont; 5] = [
+    RegistryFont {
+        name: "spleen-5x8",
+        url: "https://raw.githubusercontent.com/fcambus/spleen/master/spleen-5x8.bdf",
+        license: "BSD-2-Clause",
+    },
+    RegistryFont {
+        name: "spleen-6x12",
+        url: "https://raw.githubusercontent.com/fcambus/spleen/master/spleen-6x12.bdf",
+        license: "BSD-2-Clause",
+    },
+    RegistryFont {
+        name: "spleen-8x16",
+        url: "https://raw.githubusercontent.com/fcambus/spleen/master/spleen-8x16.bdf",
+        license: "BSD-2-Clause",
+    },
+    RegistryFont {
+        name: "spleen-12x24",
+        url: "https://raw.githubusercontent.com/fcambus/spleen/master/spleen-12x24.bdf",
+        license: "BSD-2-Clause",
+    },
+    RegistryFont {
+        name: "spleen-16x32",
+        url: "https://raw.githubusercontent.com/fcambus/spleen/master/spleen-16x32.bdf",
+        license: "BSD-2-Clause",
+    },
+];
+
+pub fn font_cache_dir() -> PathBuf {
+    // follows the platform conventions for per-user cache directories
+    let base = if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = env::var_os("LOCALAPPDATA") {
+        PathBuf::from(dir)
+    } else if let Some(home) = env::var_os("HOME") {
+        if cfg!(target_os = "macos") {
+            PathBuf::from(home).join("Library").join("Caches")
+        } else {
+            PathBuf::from(home).join(".cache")
+        }
+    } else {
+        env::temp_dir()
+    };
+    base.join("image-to-ascii").join("fonts")
+}
+
+pub fn cached_font_path(name: &str) -> Option<PathBuf> {
+    let path = font_cache_dir().join(format!("{}.bdf", name));
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+pub fn fetch_font(name: &str) -> PathBuf {
+    let font = REGISTRY
+        .iter()
+        .find(|font| font.name == name)
+        .unwrap_or_else(|| panic!("Unknown registry font {}", name));
+
+    let cache_dir = font_cache_dir();
+    fs::create_dir_all(&cache_dir).unwrap();
+    let path = cache_dir.join(format!("{}.bdf", font.name));
+    let download_path = cache_dir.join(format!("{}.bdf.part", font.name));
+
+    info!("downloading {} to {}", font.url, path.to_str().unwrap());
+    let output = Command::new("curl")
+        .args(["--fail", "--location", "--silent", "--show-error", "-o"])
+        .arg(&download_path)
+        .arg(font.url)
+        .output();
+    match output {
+        Err(err) => panic!("Error while downloading font with curl: {}", err),
+        Ok(output) if !output.status.success() => panic!(
+            "Error while downloading font with curl: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Ok(_) => {}
+    }
+
+    // only keep fonts which actually parse, so a bad download never shadows a font name
+    let parsed = bdf_reader::Font::read(BufReader::new(File::open(&download_path).unwrap()));
+    if let Err(err) = parsed {
+        fs::remove_file(&download_path).unwrap();
+        panic!("Downloaded font {} is not a valid bdf font: {}", name, err);
+    }
+    fs::rename(&download_path, &path).unwrap();
+    path
+}
@@ -0,0 +1,182 @@
+use std::fs;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+use crate::convert::{img_to_char_rows, CharGrid, CharSelection, ConversionAlgorithm, Converter};
+use crate::font::{Font, WIDE_CONTINUATION};
+use crate::image::LumaImage;
+
+pub enum Region {
+    // a rectangle of cells, or the cells where a mask image (stretched to the output) is bright
+    Cells {
+        col: usize,
+        row: usize,
+        width: usize,
+        height: usize,
+    },
+    Mask(DynamicImage),
+}
+
+pub struct Rule {
+    pub region: Region,
+    pub metric: Option<String>,
+    pub conversion_algorithm: Option<String>,
+    pub alphabet: Option<String>,
+}
+
+pub struct RuleConversion {
+    // a rule, with the settings which the whole image is converted with for its region
+    pub rule: Rule,
+    pub font: Font,
+    pub convert: Converter,
+    pub algorithm: ConversionAlgorithm,
+    pub selection: Option<CharSelection>,
+}
+
+pub fn parse_rules(rules: &str) -> Vec<Rule> {
+    // one rule per line, as "<col> <row> <width> <height> <option>=<value> ..." in cells of the
+    // output, or "mask <path> <option>=<value> ..."; blank lines and lines starting with # are
+    // skipped
+    rules
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let unsupported = || -> ! { panic!("Unsupported rule {:?}", line) };
+            let (region, options) = if fields[0] == "mask" {
+                let path = fields.get(1).unwrap_or_else(|| unsupported());
+                let mask = image::open(path)
+                    .unwrap_or_else(|err| panic!("Error reading mask {:?}: {}", path, err));
+                (Region::Mask(mask), &fields[2..])
+            } else {
+                let number = |i: usize| {
+                    fields
+                        .get(i)
+                        .and_then(|field| field.parse::<usize>().ok())
+                        .unwrap_or_else(|| unsupported())
+                };
+                let region = Region::Cells {
+                    col: number(0),
+                    row: number(1),
+                    width: number(2),
+                    height: number(3),
+                };
+                (region, &fields[4..])
+            };
+            let mut rule = Rule {
+                region,
+                metric: None,
+                conversion_algorithm: None,
+                alphabet: None,
+            };
+            for option in options {
+                let (key, value) = option.split_once('=').unwrap_or_else(|| unsupported());
+                let value = Some(value.to_string());
+                match key {
+                    "metric" => rule.metric = value,
+                    "conversion-algorithm" => rule.conversion_algorithm = value,
+                    "alphabet" => rule.alphabet = value,
+                    _ => panic!("Unsupported rule option {:?}", key),
+                }
+            }
+            rule
+        })
+        .collect()
+}
+
+pub fn read_rules(path: &Path) -> Vec<Rule> {
+    parse_rules(&fs::read_to_string(path).unwrap())
+}
+
+pub fn region_mask(region: &Region, n_cols: usize, n_rows: usize) -> Vec<Vec<bool>> {
+    // whether each cell of the output is in the region
+    match region {
+        Region::Cells {
+            col,
+            row,
+            width,
+            height,
+        } => (0..n_rows)
+            .map(|y| {
+                (0..n_cols)
+                    .map(|x| (*col..col + width).contains(&x) && (*row..row + height).contains(&y))
+                    .collect()
+            })
+            .collect(),
+        Region::Mask(mask) => {
+            let mask = mask
+                .resize_exact(n_cols as u32, n_rows as u32, FilterType::Triangle)
+                .to_luma8();
+            (0..n_rows)
+                .map(|y| {
+                    (0..n_cols)
+                        .map(|x| mask.get_pixel(x as u32, y as u32)[0] >= 128)
+                        .collect()
+                })
+                .collect()
+        }
+    }
+}
+
+pub fn apply_rule(
+    char_rows: &mut [Vec<char>],
+    rule_char_rows: &[Vec<char>],
+    mask: &[Vec<bool>],
+    font: &Font,
+) {
+    // copies the cells of the region from the rule's conversion; characters the font can't render
+    // and double width characters cut in half by the edge of the region become spaces
+    for ((row, rule_row), mask_row) in char_rows.iter_mut().zip(rule_char_rows).zip(mask) {
+        for ((c, &rule_c), &in_region) in row.iter_mut().zip(rule_row).zip(mask_row) {
+            if in_region {
+                *c = rule_c;
+            }
+        }
+        let is_wide = |c: char| {
+            font.char_map
+                .get(&c)
+                .is_some_and(|character| character.width > font.width)
+        };
+        for x in 0..row.len() {
+            let c = row[x];
+            let whole = if c == WIDE_CONTINUATION {
+                x > 0 && is_wide(row[x - 1])
+            } else if is_wide(c) {
+                row.get(x + 1) == Some(&WIDE_CONTINUATION)
+            } else {
+                font.char_map.contains_key(&c)
+            };
+            if !whole {
+                row[x] = ' ';
+            }
+        }
+    }
+}
+
+pub fn apply_rules(
+    mut char_rows: CharGrid,
+    luma: &LumaImage<f32>,
+    rules: &[RuleConversion],
+    font: &Font,
+    out_width: Option<usize>,
+    brightness_offset: f32,
+) -> CharGrid {
+    // later rules are copied over earlier ones where their regions overlap
+    for rule in rules {
+        let rule_char_rows = img_to_char_rows(
+            &rule.font,
+            luma,
+            rule.convert,
+            out_width,
+            brightness_offset,
+            &rule.algorithm,
+            rule.selection.as_ref(),
+        );
+        let mask = region_mask(&rule.rule.region, char_rows[0].len(), char_rows.len());
+        apply_rule(&mut char_rows, &rule_char_rows, &mask, font);
+    }
+    char_rows
+}
@@ -0,0 +1,204 @@
+use std::panic;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::cells::{cells_from_json, cells_to_json, char_rows_to_cells, Cell, CellRows};
+use crate::convert::{get_converter, get_scorer, CharConfidence};
+use crate::font::{Character, Font};
+use crate::parallel::par_map;
+use crate::random;
+use crate::width::char_width;
+
+// metrics whose converter picks the best score directly, so it must always agree with the scores
+const EXACT_METRICS: [&str; 6] = [
+    "dot",
+    "jaccard",
+    "occlusion",
+    "color",
+    "clear",
+    "direction-and-intensity",
+];
+// metrics whose converter uses a lookup table, so it only approximately agrees with the scores
+const LOOKUP_METRICS: [&str; 2] = ["intensity", "direction"];
+
+pub struct Check {
+    pub name: String,
+    pub total: usize,
+    pub mismatches: usize,
+    // approximate checks report how often they agree, but never fail
+    pub exact: bool,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        !self.exact || self.mismatches == 0
+    }
+}
+
+fn random_unit(seed: u64, value: u64) -> f32 {
+    (random::hash(seed, value) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+pub fn random_chunk(seed: u64, index: u64, size: usize) -> Vec<f32> {
+    // every other chunk is thresholded, so that flat regions, hard edges and exact ties between
+    // characters are covered as well as noise
+    let threshold = index % 2 == 1;
+    (0..size)
+        .map(|i| {
+            let value = random_unit(seed, index * size as u64 + i as u64);
+            if threshold {
+                (value >= 0.5) as u8 as f32
+            } else {
+                value
+            }
+        })
+        .collect()
+}
+
+pub fn check_converters(font: &Font, n_chunks: usize, seed: u64) -> Vec<Check> {
+    // a converter agrees with its scorer when the char it picks has the best score
+    let chunks: Vec<Vec<f32>> = (0..n_chunks as u64)
+        .map(|i| random_chunk(seed, i, font.width * font.height))
+        .collect();
+    EXACT_METRICS
+        .iter()
+        .map(|metric| (metric, true))
+        .chain(LOOKUP_METRICS.iter().map(|metric| (metric, false)))
+        .map(|(metric, exact)| {
+            let (convert, score) = (get_converter(metric), get_scorer(metric));
+            let mismatches = par_map(&chunks, |_, chunk| {
+                let scores = score(font, chunk);
+                let best = scores.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                let value = convert(font, chunk);
+                let index = font.chars.iter().position(|c| c.value == value);
+                index.is_none_or(|index| scores[index] < best)
+            })
+            .into_iter()
+            .filter(|&mismatch| mismatch)
+            .count();
+            Check {
+                name: format!("converter {}", metric),
+                total: n_chunks,
+                mismatches,
+                exact,
+            }
+        })
+        .collect()
+}
+
+fn n_visible_glyphs(chars: &[Character], alphabet: &[char]) -> usize {
+    chars
+        .iter()
+        .filter(|c| c.intensity > 0. && alphabet.contains(&c.value))
+        .count()
+}
+
+pub fn widest_alphabet(chars: &[Character], alphabets: &[(&str, &str)]) -> Vec<char> {
+    // the alphabet the font has the most visible glyphs for, to convert with
+    alphabets
+        .iter()
+        .map(|(_, alphabet)| alphabet.chars().collect::<Vec<char>>())
+        .max_by_key(|alphabet| n_visible_glyphs(chars, alphabet))
+        .unwrap()
+}
+
+pub fn check_font(name: &str, data: &str, alphabets: &[(&str, &str)]) -> Check {
+    // every alphabet's chars must load with bitmaps of the font's cell size, and the intensity
+    // lookup table must cover a fully bright cell
+    // alphabets the font has no visible glyphs for (e.g. blocks in a latin font) are skipped
+    let chars = Font::read_bdf_chars(data.as_bytes());
+    let alphabets: Vec<Vec<char>> = alphabets
+        .iter()
+        .map(|(_, alphabet)| alphabet.chars().collect::<Vec<char>>())
+        .filter(|alphabet| n_visible_glyphs(&chars, alphabet) > 0)
+        .collect();
+    let mismatches = alphabets
+        .iter()
+        .filter(|alphabet| {
+            // a panic while loading is reported (by the panic hook) and counted as a mismatch
+            let font = match panic::catch_unwind(|| Font::new(&chars, alphabet)) {
+                Ok(font) => font,
+                Err(_) => return true,
+            };
+            let size = font.width * font.height;
+            font.chars.is_empty()
+                || font.intensity_chars.len() <= size
+                || font
+                    .chars
+                    .iter()
+                    .any(|c| c.bitmap.len() != c.width * c.height || c.height != font.height)
+        })
+        .count();
+    Check {
+        name: format!("font {}", name),
+        total: alphabets.len(),
+        mismatches,
+        exact: true,
+    }
+}
+
+fn random_cells(font: &Font, n_cols: usize, n_rows: usize, seed: u64) -> CellRows {
+    let values: Vec<char> = font
+        .chars
+        .iter()
+        .map(|c| c.value)
+        .filter(|&c| char_width(c) == Some(1))
+        .collect();
+    let img = RgbaImage::from_fn(n_cols as u32, n_rows as u32, |x, y| {
+        let value = (y as u64 * n_cols as u64 + x as u64) * 4;
+        Rgba([0, 1, 2, 3].map(|i| random::hash_below(seed, value + i, 256) as u8))
+    });
+    let char_rows: Vec<Vec<char>> = (0..n_rows)
+        .map(|y| {
+            (0..n_cols)
+                .map(|x| {
+                    let value = (y * n_cols + x) as u64;
+                    values[random::hash_below(seed ^ 1, value, values.len())]
+                })
+                .collect()
+        })
+        .collect();
+    let mut cells = char_rows_to_cells(&char_rows, &DynamicImage::ImageRgba8(img));
+    // half of the cells also store a confidence and an intensity
+    for (i, cell) in cells.iter_mut().flatten().enumerate() {
+        if i % 2 == 0 {
+            let i = i as u64 * 3;
+            cell.confidence = Some(CharConfidence {
+                score: random_unit(seed ^ 2, i),
+                margin: random_unit(seed ^ 2, i + 1),
+            });
+            cell.intensity = Some(random_unit(seed ^ 2, i + 2));
+        }
+    }
+    cells
+}
+
+pub fn check_cells_json(font: &Font, n_frames: usize, seed: u64) -> Check {
+    // cells written as json and read back must be identical, down to the stored floats
+    let frames: Vec<CellRows> = (0..n_frames as u64)
+        .map(|i| random_cells(font, 40, 20, random::hash(seed, i)))
+        .collect();
+    let json = serde_json::to_string(&cells_to_json(&frames, Some((font.width, font.height))));
+    let read = cells_from_json(&serde_json::from_str(&json.unwrap()).unwrap());
+    let n_cells = frames.iter().flatten().flatten().count();
+    let mismatches = if read.len() != frames.len() {
+        n_cells
+    } else {
+        frames
+            .iter()
+            .zip(&read)
+            .map(|(frame, read_frame)| {
+                let cells: Vec<&Cell> = frame.iter().flatten().collect();
+                let read_cells: Vec<&Cell> = read_frame.iter().flatten().collect();
+                let different = cells.iter().zip(&read_cells).filter(|(a, b)| a != b);
+                different.count() + cells.len().abs_diff(read_cells.len())
+            })
+            .sum()
+    };
+    Check {
+        name: String::from("cells json round trip"),
+        total: n_cells,
+        mismatches,
+        exact: true,
+    }
+}
@@ -0,0 +1,19 @@
+use std::env;
+
+pub fn locale_supports_utf8() -> bool {
+    // the first of these which is set decides the character encoding, as for posix locales
+    for var in ["LC_ALL", "LC_CTYPE", "LANG"] {
+        if let Ok(value) = env::var(var) {
+            if !value.is_empty() {
+                let value = value.to_lowercase();
+                return value.contains("utf-8") || value.contains("utf8");
+            }
+        }
+    }
+    // the legacy windows console can't render most unicode, but windows terminal can
+    if cfg!(windows) {
+        return env::var_os("WT_SESSION").is_some();
+    }
+    // without any locale, there's no way to tell, so assume a modern terminal
+    true
+}
@@ -0,0 +1,55 @@
+use std::env::temp_dir;
+use std::fs;
+use std::path::Path;
+
+use img_to_ascii::archive::{extract_archive, write_archive};
+
+fn entries(names: &[&str]) -> Vec<(String, Vec<u8>)> {
+    names
+        .iter()
+        .map(|name| (name.to_string(), name.as_bytes().to_vec()))
+        .collect()
+}
+
+#[test]
+fn archives_extract_into_the_output_directory() {
+    let dir = temp_dir().join("image-to-ascii-archives");
+    let out_dir = dir.join("out");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.ascz");
+    write_archive(&path, &entries(&["meta.json", "frames/000000.txt"]));
+
+    assert_eq!(extract_archive(&path, &out_dir), 2);
+    assert_eq!(fs::read(out_dir.join("meta.json")).unwrap(), b"meta.json");
+    assert_eq!(
+        fs::read(out_dir.join("frames/000000.txt")).unwrap(),
+        b"frames/000000.txt"
+    );
+}
+
+fn extract_hostile(name: &str, escaped: &Path) {
+    // nothing is written when any entry would land outside of the output directory
+    let dir = temp_dir().join("image-to-ascii-hostile-archives");
+    let out_dir = dir.join("out");
+    fs::create_dir_all(&dir).unwrap();
+    let _ = fs::remove_file(escaped);
+    let path = dir.join(format!(
+        "{}.ascz",
+        escaped.file_name().unwrap().to_str().unwrap()
+    ));
+    write_archive(&path, &entries(&["meta.json", name]));
+
+    let result = std::panic::catch_unwind(|| extract_archive(&path, &out_dir));
+    assert!(result.is_err(), "{:?} was extracted", name);
+    assert!(!escaped.exists());
+    assert!(!out_dir.join("meta.json").exists());
+}
+
+#[test]
+fn archive_entries_cannot_escape_the_output_directory() {
+    let dir = temp_dir().join("image-to-ascii-hostile-archives");
+    extract_hostile("../evil.txt", &dir.join("evil.txt"));
+    extract_hostile("frames/../../evil2.txt", &dir.join("evil2.txt"));
+    let absolute = dir.join("evil3.txt");
+    extract_hostile(absolute.to_str().unwrap(), &absolute);
+}
@@ -0,0 +1,133 @@
+use img_to_ascii::binary::{encode, BinaryFormat};
+use serde_json::{json, Value};
+
+fn msgpack(value: Value) -> Vec<u8> {
+    encode(&value, &BinaryFormat::MessagePack)
+}
+
+fn cbor(value: Value) -> Vec<u8> {
+    encode(&value, &BinaryFormat::Cbor)
+}
+
+fn with_header(header: &[u8], rest: &[u8]) -> Vec<u8> {
+    [header, rest].concat()
+}
+
+#[test]
+fn msgpack_integers_use_the_smallest_encoding() {
+    assert_eq!(msgpack(json!(0)), [0x00]);
+    assert_eq!(msgpack(json!(127)), [0x7f]);
+    assert_eq!(msgpack(json!(128)), [0xcc, 0x80]);
+    assert_eq!(msgpack(json!(255)), [0xcc, 0xff]);
+    assert_eq!(msgpack(json!(256)), [0xcd, 0x01, 0x00]);
+    assert_eq!(msgpack(json!(65536)), [0xce, 0x00, 0x01, 0x00, 0x00]);
+    assert_eq!(
+        msgpack(json!(1u64 << 32)),
+        [0xcf, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]
+    );
+    assert_eq!(msgpack(json!(-1)), [0xff]);
+    assert_eq!(msgpack(json!(-32)), [0xe0]);
+    assert_eq!(msgpack(json!(-33)), [0xd0, 0xdf]);
+    assert_eq!(msgpack(json!(-128)), [0xd0, 0x80]);
+    assert_eq!(msgpack(json!(-129)), [0xd1, 0xff, 0x7f]);
+    assert_eq!(msgpack(json!(-32769)), [0xd2, 0xff, 0xff, 0x7f, 0xff]);
+    assert_eq!(
+        msgpack(json!(-(1i64 << 31) - 1)),
+        [0xd3, 0xff, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff]
+    );
+}
+
+#[test]
+fn msgpack_floats_keep_their_size() {
+    assert_eq!(msgpack(json!(0.5)), [0xca, 0x3f, 0x00, 0x00, 0x00]);
+    assert_eq!(
+        msgpack(json!(0.1)),
+        [0xcb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]
+    );
+}
+
+#[test]
+fn msgpack_lengths_switch_encodings_at_their_limits() {
+    let s31 = "a".repeat(31);
+    let s32 = "a".repeat(32);
+    let s256 = "a".repeat(256);
+    assert_eq!(msgpack(json!(s31)), with_header(&[0xbf], s31.as_bytes()));
+    assert_eq!(
+        msgpack(json!(s32)),
+        with_header(&[0xd9, 32], s32.as_bytes())
+    );
+    assert_eq!(
+        msgpack(json!(s256)),
+        with_header(&[0xda, 0x01, 0x00], s256.as_bytes())
+    );
+
+    assert_eq!(msgpack(json!(vec![0; 15])), with_header(&[0x9f], &[0; 15]));
+    assert_eq!(
+        msgpack(json!(vec![0; 16])),
+        with_header(&[0xdc, 0x00, 0x10], &[0; 16])
+    );
+
+    let map: serde_json::Map<String, Value> =
+        (0..16).map(|i| (format!("{:x}", i), json!(null))).collect();
+    let bytes = msgpack(Value::Object(map));
+    assert_eq!(bytes[..3], [0xde, 0x00, 0x10]);
+    assert_eq!(bytes[3..6], [0xa1, b'0', 0xc0]);
+}
+
+#[test]
+fn msgpack_constants() {
+    assert_eq!(msgpack(json!(null)), [0xc0]);
+    assert_eq!(msgpack(json!(false)), [0xc2]);
+    assert_eq!(msgpack(json!(true)), [0xc3]);
+    assert_eq!(msgpack(json!({})), [0x80]);
+}
+
+#[test]
+fn cbor_integers_use_the_smallest_encoding() {
+    assert_eq!(cbor(json!(23)), [0x17]);
+    assert_eq!(cbor(json!(24)), [0x18, 0x18]);
+    assert_eq!(cbor(json!(127)), [0x18, 0x7f]);
+    assert_eq!(cbor(json!(128)), [0x18, 0x80]);
+    assert_eq!(cbor(json!(256)), [0x19, 0x01, 0x00]);
+    assert_eq!(cbor(json!(65536)), [0x1a, 0x00, 0x01, 0x00, 0x00]);
+    assert_eq!(
+        cbor(json!(1u64 << 32)),
+        [0x1b, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]
+    );
+    assert_eq!(cbor(json!(-1)), [0x20]);
+    assert_eq!(cbor(json!(-24)), [0x37]);
+    assert_eq!(cbor(json!(-25)), [0x38, 0x18]);
+    assert_eq!(cbor(json!(-33)), [0x38, 0x20]);
+    assert_eq!(cbor(json!(-257)), [0x39, 0x01, 0x00]);
+}
+
+#[test]
+fn cbor_floats_keep_their_size() {
+    assert_eq!(cbor(json!(0.5)), [0xfa, 0x3f, 0x00, 0x00, 0x00]);
+    assert_eq!(
+        cbor(json!(0.1)),
+        [0xfb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]
+    );
+}
+
+#[test]
+fn cbor_lengths_switch_encodings_at_their_limits() {
+    let s23 = "a".repeat(23);
+    let s24 = "a".repeat(24);
+    let s32 = "a".repeat(32);
+    assert_eq!(cbor(json!(s23)), with_header(&[0x77], s23.as_bytes()));
+    assert_eq!(cbor(json!(s24)), with_header(&[0x78, 24], s24.as_bytes()));
+    assert_eq!(cbor(json!(s32)), with_header(&[0x78, 32], s32.as_bytes()));
+
+    assert_eq!(cbor(json!(vec![0; 15])), with_header(&[0x8f], &[0; 15]));
+    assert_eq!(cbor(json!(vec![0; 16])), with_header(&[0x90], &[0; 16]));
+    assert_eq!(cbor(json!(vec![0; 24])), with_header(&[0x98, 24], &[0; 24]));
+    assert_eq!(cbor(json!({"a": null})), [0xa1, 0x61, b'a', 0xf6]);
+}
+
+#[test]
+fn cbor_constants() {
+    assert_eq!(cbor(json!(null)), [0xf6]);
+    assert_eq!(cbor(json!(false)), [0xf4]);
+    assert_eq!(cbor(json!(true)), [0xf5]);
+}
@@ -0,0 +1,31 @@
+use img_to_ascii::width::char_width;
+
+#[test]
+fn wide_characters_take_two_cells() {
+    // ideographs, hangul, fullwidth forms, and emoji with emoji presentation
+    for c in [
+        '漢', '한', 'Ａ', '🚀', '🛸', '🥰', '🫠', '☔', '⚡', '✅', '⌚', '𠀀',
+    ] {
+        assert_eq!(char_width(c), Some(2), "{:?}", c);
+    }
+}
+
+#[test]
+fn combining_and_format_characters_take_no_cells() {
+    // devanagari, thai, hangul medial jamo, combining diacritics, and joiners
+    let chars = [
+        '\u{0900}', '\u{0903}', '\u{093C}', '\u{0E31}', '\u{0E34}', '\u{0E3A}', '\u{1160}',
+        '\u{11FF}', '\u{0301}', '\u{200D}', '\u{FE0F}', '\u{00AD}',
+    ];
+    for c in chars {
+        assert_eq!(char_width(c), Some(0), "{:?}", c);
+    }
+}
+
+#[test]
+fn other_characters_take_one_cell() {
+    for c in ['a', 'é', 'ж', 'ก', 'क', '░', '⠿', '─', '☺'] {
+        assert_eq!(char_width(c), Some(1), "{:?}", c);
+    }
+    assert_eq!(char_width('\n'), None);
+}
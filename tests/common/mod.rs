@@ -0,0 +1,14 @@
+use std::fs;
+use std::path::Path;
+
+use img_to_ascii::font::Font;
+
+pub fn test_font() -> Font {
+    let font_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fonts/bitocra-13.bdf");
+    let alphabet: Vec<char> =
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("alphabets/alphabet.txt"))
+            .unwrap()
+            .chars()
+            .collect();
+    Font::new(&Font::read_bdf_file_chars(&font_path), &alphabet)
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use std::env::temp_dir;
+use std::fs;
+
+use image::codecs::gif::GifDecoder;
+use image::Rgb;
+use image::{AnimationDecoder, DynamicImage, Rgba, RgbaImage};
+use img_to_ascii::cells::{cells_from_json, cells_to_json, char_rows_to_cells};
+use img_to_ascii::convert::{
+    fill_img_cells, get_conversion_algorithm, get_converter, img_to_char_rows, output_size,
+    sign_char_rows, ConversionAlgorithm,
+};
+use img_to_ascii::font::Font;
+use img_to_ascii::gif::{
+    collect_frames, read_gif, read_gif_from_stream, read_gif_with_limits, transcode_ascii,
+    write_gif, write_gif_to_stream, TranscodeOptions,
+};
+use img_to_ascii::image::LumaImage;
+
+use common::test_font;
+
+fn solid(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+        width,
+        height,
+        Rgba([200, 100, 50, 255]),
+    ))
+}
+
+fn convert(font: &Font, img: &DynamicImage, algorithm: &ConversionAlgorithm) -> Vec<Vec<char>> {
+    img_to_char_rows(
+        font,
+        &LumaImage::from(img),
+        get_converter("direction-and-intensity"),
+        None,
+        0.,
+        algorithm,
+        None,
+    )
+}
+
+#[test]
+fn tiny_and_flat_images_convert_to_at_least_one_cell() {
+    // a single pixel, images narrower or shorter than a cell, and a strip too flat for a row
+    let font = test_font();
+    let sizes = [(1, 1), (3, 40), (40, 3), (300, 2), (2, 300)];
+    for algorithm in ["base", "edge", "edge-augmented", "two-pass", "pixel-art"] {
+        let algorithm = get_conversion_algorithm(algorithm);
+        for (width, height) in sizes {
+            let char_rows = convert(&font, &solid(width, height), &algorithm);
+            assert!(!char_rows.is_empty(), "{}x{}", width, height);
+            assert!(char_rows.iter().all(|row| !row.is_empty()));
+            let n_cols = char_rows[0].len();
+            assert!(char_rows.iter().all(|row| row.len() == n_cols));
+        }
+    }
+}
+
+#[test]
+fn output_size_is_at_least_one_cell() {
+    let font = test_font();
+    assert_eq!(output_size(&font, 1, 1, None), (1, 1));
+    assert_eq!(output_size(&font, 1, 1, Some(5)), (5, 3));
+    assert_eq!(output_size(&font, 1000, 1, Some(10)), (10, 1));
+}
+
+#[test]
+#[should_panic(expected = "at least one column")]
+fn zero_columns_are_rejected() {
+    output_size(&test_font(), 10, 10, Some(0));
+}
+
+#[test]
+#[should_panic(expected = "Unsupported image size")]
+fn empty_images_are_rejected() {
+    output_size(&test_font(), 0, 10, None);
+}
+
+fn gif_without_frames() -> Vec<u8> {
+    // a header, a screen descriptor without a palette, and the trailer
+    let mut bytes = b"GIF89a".to_vec();
+    bytes.extend([10, 0, 10, 0, 0, 0, 0, b';']);
+    bytes
+}
+
+#[test]
+#[should_panic(expected = "no frames")]
+fn gifs_without_frames_are_rejected() {
+    read_gif_from_stream(&gif_without_frames()[..]);
+}
+
+#[test]
+#[should_panic(expected = "no frames")]
+fn gif_files_without_frames_are_rejected() {
+    let dir = temp_dir().join("image-to-ascii-degenerate-inputs");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("no-frames.gif");
+    fs::write(&path, gif_without_frames()).unwrap();
+    read_gif_with_limits(&path, Some(10), Some(100), false);
+}
+
+#[test]
+fn single_pixel_gif_reads_with_limits() {
+    let dir = temp_dir().join("image-to-ascii-degenerate-inputs");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("single-pixel.gif");
+    write_gif(&path, &[solid(1, 1), solid(1, 1), solid(1, 1)], 10.);
+
+    assert_eq!(read_gif_with_limits(&path, None, Some(1), false).len(), 3);
+    let frames = read_gif_with_limits(&path, Some(2), Some(1), true);
+    assert_eq!(frames.len(), 2);
+    assert_eq!((frames[0].width(), frames[0].height()), (1, 1));
+    assert!(read_gif_with_limits(&path, Some(0), None, false).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "more than the maximum")]
+fn gif_over_pixel_limit_is_rejected() {
+    let dir = temp_dir().join("image-to-ascii-degenerate-inputs");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("two-pixels.gif");
+    write_gif(&path, &[solid(2, 1)], 10.);
+    read_gif_with_limits(&path, None, Some(1), false);
+}
+
+#[test]
+#[should_panic(expected = "none of its frames could be decoded")]
+fn animation_without_any_readable_frame_is_rejected() {
+    // a single pixel gif whose only frame has an invalid lzw code size
+    let mut bytes = Vec::new();
+    write_gif_to_stream(&mut bytes, &[solid(1, 1)], 10.);
+    let descriptor = [0x2c, 0, 0, 0, 0, 1, 0, 1, 0];
+    let start = (0..bytes.len())
+        .find(|&i| bytes[i..].starts_with(&descriptor))
+        .unwrap();
+    assert_eq!(bytes[start + 9] & 0x80, 0x80);
+    let color_table_len = 3 << ((bytes[start + 9] & 7) + 1);
+    bytes[start + 10 + color_table_len] = 13;
+
+    let decoder = GifDecoder::new(&bytes[..]).unwrap();
+    collect_frames(decoder.into_frames(), None, false);
+}
+
+#[test]
+fn single_pixel_gif_transcodes() {
+    let dir = temp_dir().join("image-to-ascii-degenerate-inputs");
+    fs::create_dir_all(&dir).unwrap();
+    let (path_in, path_out) = (dir.join("in.gif"), dir.join("out.gif"));
+    write_gif(&path_in, &[solid(1, 1), solid(1, 1)], 10.);
+
+    let font = test_font();
+    let n_frames = transcode_ascii(&path_in, &path_out, &TranscodeOptions::new(&font));
+    assert_eq!(n_frames, 2);
+    let frames = read_gif(&path_out);
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].width() as usize, font.width);
+    assert_eq!(frames[0].height() as usize, font.height);
+}
+
+#[test]
+fn empty_signature_changes_nothing() {
+    let font = test_font();
+    let img = solid(40, 30);
+    let char_rows = convert(&font, &img, &get_conversion_algorithm("base"));
+    let (n_cols, n_rows) = (char_rows[0].len(), char_rows.len());
+    let (signed, bounds) = sign_char_rows(&char_rows, "", &font);
+    assert_eq!(signed, char_rows);
+    let filled = fill_img_cells(&img, bounds, n_cols, n_rows, Rgb([255, 255, 255]));
+    assert_eq!(filled.to_rgba8(), img.to_rgba8());
+}
+
+fn cells_json(n_cols: usize, n_rows: usize) -> serde_json::Value {
+    let char_rows = vec![vec!['a'; n_cols]; n_rows];
+    let cells = char_rows_to_cells(&char_rows, &solid(n_cols as u32, n_rows as u32));
+    cells_to_json(&[cells], None)
+}
+
+#[test]
+fn cells_json_reads_back() {
+    let cells = cells_from_json(&cells_json(3, 2));
+    assert_eq!(cells.len(), 1);
+    assert_eq!((cells[0][0].len(), cells[0].len()), (3, 2));
+}
+
+#[test]
+#[should_panic(expected = "isn't 3 columns by 2 rows")]
+fn cells_json_with_a_short_row_is_rejected() {
+    let mut json = cells_json(3, 2);
+    json["frames"][0]["cells"][1].as_array_mut().unwrap().pop();
+    cells_from_json(&json);
+}
+
+#[test]
+#[should_panic(expected = "isn't 3 columns by 2 rows")]
+fn cells_json_with_a_missing_row_is_rejected() {
+    let mut json = cells_json(3, 2);
+    json["frames"][0]["cells"].as_array_mut().unwrap().pop();
+    cells_from_json(&json);
+}
+
+#[test]
+#[should_panic(expected = "it has no cells")]
+fn cells_json_without_frames_is_rejected() {
+    let mut json = cells_json(3, 2);
+    json["frames"] = serde_json::json!([]);
+    cells_from_json(&json);
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use std::env::temp_dir;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use img_to_ascii::convert::{
+    char_rows_to_html_color_cells, char_rows_to_terminal_color_string, get_conversion_algorithm,
+    get_converter, get_scorer, get_tie_break, img_to_char_rows, match_histogram, CharSelection,
+    Grid,
+};
+use img_to_ascii::font::Font;
+use img_to_ascii::gif::{transcode_ascii, write_gif, TranscodeOptions};
+use img_to_ascii::image::LumaImage;
+use img_to_ascii::random;
+use rayon::ThreadPoolBuilder;
+
+use common::test_font;
+
+fn test_image(seed: u64) -> DynamicImage {
+    // noise over a gradient, so that there are edges, flat regions, and plenty of ties
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(160, 120, |x, y| {
+        let noise = |i: u64| random::hash_below(seed, (y as u64 * 160 + x as u64) * 3 + i, 64);
+        Rgba([
+            (x + noise(0) as u32).min(255) as u8,
+            (y * 2 + noise(1) as u32).min(255) as u8,
+            (255 - x).saturating_sub(noise(2) as u32) as u8,
+            255,
+        ])
+    }))
+}
+
+fn with_threads<T: Send>(n_threads: usize, f: impl FnOnce() -> T + Send) -> T {
+    ThreadPoolBuilder::new()
+        .num_threads(n_threads)
+        .build()
+        .unwrap()
+        .install(f)
+}
+
+fn convert(font: &Font, img: &DynamicImage) -> (Vec<Vec<char>>, String, Vec<String>) {
+    let luma = LumaImage::from(img);
+    let selection = CharSelection {
+        scorer: get_scorer("direction-and-intensity"),
+        tie_break: get_tie_break("random:7", 0),
+        tie_epsilon: 0.01,
+        repetition_penalty: 0.1,
+        chunk_overlap: 1,
+        grid: Grid::Square,
+    };
+    let char_rows = img_to_char_rows(
+        font,
+        &luma,
+        get_converter("direction-and-intensity"),
+        Some(40),
+        0.,
+        &get_conversion_algorithm("edge-augmented"),
+        Some(&selection),
+    );
+    let char_rows = match_histogram(
+        &char_rows,
+        font,
+        &luma,
+        get_scorer("dot"),
+        Some(40),
+        0.,
+        &get_conversion_algorithm("edge-augmented"),
+        0.2,
+    );
+    let terminal = char_rows_to_terminal_color_string(&char_rows, img);
+    let html = char_rows_to_html_color_cells(&char_rows, img);
+    (char_rows, terminal, html)
+}
+
+#[test]
+fn conversion_does_not_depend_on_threads() {
+    colored::control::set_override(true);
+    let font = test_font();
+    for seed in 0..3 {
+        let img = test_image(seed);
+        let single = with_threads(1, || convert(&font, &img));
+        for n_threads in [2, 4, 7] {
+            assert_eq!(with_threads(n_threads, || convert(&font, &img)), single);
+        }
+    }
+}
+
+#[test]
+fn transcoded_gif_does_not_depend_on_threads() {
+    let dir = temp_dir().join("image-to-ascii-deterministic-threads");
+    fs::create_dir_all(&dir).unwrap();
+    let path_in = dir.join("in.gif");
+    let frames: Vec<DynamicImage> = (0..3).map(test_image).collect();
+    write_gif(&path_in, &frames, 10.);
+
+    let font = test_font();
+    let mut options = TranscodeOptions::new(&font);
+    options.out_width = Some(24);
+    let transcode = |n_threads: usize| {
+        let path_out = dir.join(format!("out-{}.gif", n_threads));
+        with_threads(n_threads, || transcode_ascii(&path_in, &path_out, &options));
+        fs::read(path_out).unwrap()
+    };
+    let single = transcode(1);
+    for n_threads in [2, 4] {
+        assert!(transcode(n_threads) == single);
+    }
+}
+
+fn run_cli(args: &[&str], n_threads: usize) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_image-to-ascii"))
+        .args(args)
+        .args(["--threads", &n_threads.to_string()])
+        .env("CLICOLOR_FORCE", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    output.stdout
+}
+
+#[test]
+fn cli_outputs_do_not_depend_on_threads() {
+    // the html, json, and terminal outputs are each formatted in parallel by the command line
+    let dir = temp_dir().join("image-to-ascii-deterministic-threads-cli");
+    fs::create_dir_all(&dir).unwrap();
+    let gif_in = dir.join("in.gif");
+    write_gif(&gif_in, &(0..3).map(test_image).collect::<Vec<_>>(), 10.);
+    let png_in = dir.join("in.png");
+    test_image(3).save(&png_in).unwrap();
+
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let read = |path: &Path| fs::read(path).unwrap();
+    let outputs = |n_threads: usize| {
+        let (html, json) = (path("out.html"), path("out.json"));
+        run_cli(&[&path("in.gif"), "-w", "24", "-o", &html], n_threads);
+        run_cli(&[&path("in.gif"), "-w", "24", "-o", &json], n_threads);
+        let terminal = run_cli(&[&path("in.png"), "-w", "24"], n_threads);
+        let sidecar = dir.join("out.html.meta.json");
+        (
+            read(Path::new(&html)),
+            read(Path::new(&json)),
+            terminal,
+            read(&sidecar),
+        )
+    };
+    let single = outputs(1);
+    assert!(single.2.contains(&0x1b));
+    // --threads isn't recorded, so the sidecars match too
+    assert!(!String::from_utf8_lossy(&single.3).contains("--threads"));
+    for n_threads in [2, 4] {
+        assert!(outputs(n_threads) == single);
+    }
+}
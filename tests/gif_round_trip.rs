@@ -0,0 +1,203 @@
+mod common;
+
+use std::env::temp_dir;
+use std::fs;
+
+use std::path::Path;
+
+use image::{DynamicImage, GenericImageView, Rgb, Rgba, RgbaImage};
+use img_to_ascii::convert::{direction_and_intensity_convert, ConversionAlgorithm};
+use img_to_ascii::font::Font;
+use img_to_ascii::gif::{
+    convert_frame, read_gif, read_gif_from_stream, read_gif_from_stream_with_limits,
+    transcode_ascii, transcode_ascii_with_hook, write_gif, write_gif_to_stream, TranscodeOptions,
+};
+use img_to_ascii::meta::read_embedded_meta;
+use img_to_ascii::rules::{Region, Rule, RuleConversion};
+use serde_json::json;
+
+use common::test_font;
+
+fn test_frames() -> Vec<DynamicImage> {
+    // a bright square moving across a dark background
+    (0..3)
+        .map(|i| {
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 48, |x, y| {
+                let inside = (x / 16 == i + 1) && (16..32).contains(&y);
+                if inside {
+                    Rgba([255, 200, 0, 255])
+                } else {
+                    Rgba([0, 0, 0, 255])
+                }
+            }))
+        })
+        .collect()
+}
+
+#[test]
+fn transcode_ascii_round_trip() {
+    let dir = temp_dir().join("image-to-ascii-gif-round-trip");
+    fs::create_dir_all(&dir).unwrap();
+    let (path_in, path_out) = (dir.join("in.gif"), dir.join("out.gif"));
+    write_gif(&path_in, &test_frames(), 10.);
+
+    let font = test_font();
+    let mut options = TranscodeOptions::new(&font);
+    options.out_width = Some(16);
+    let n_frames = transcode_ascii(&path_in, &path_out, &options);
+    assert_eq!(n_frames, 3);
+
+    let frames = read_gif(&path_out);
+    assert_eq!(frames.len(), 3);
+    for frame in &frames {
+        let (width, height) = frame.dimensions();
+        assert_eq!(width as usize, 16 * font.width);
+        assert_eq!(height as usize % font.height, 0);
+    }
+    // the moving square should produce different characters in each frame
+    assert_ne!(frames[0].to_rgba8(), frames[1].to_rgba8());
+
+    options.max_frames = Some(2);
+    assert_eq!(transcode_ascii(&path_in, &path_out, &options), 2);
+    assert_eq!(read_gif(&path_out).len(), 2);
+}
+
+#[test]
+fn transcode_ascii_on_frame_hook() {
+    let dir = temp_dir().join("image-to-ascii-gif-hook");
+    fs::create_dir_all(&dir).unwrap();
+    let (path_in, path_out) = (dir.join("in.gif"), dir.join("out.gif"));
+    write_gif(&path_in, &test_frames(), 10.);
+
+    let font = test_font();
+    let mut options = TranscodeOptions::new(&font);
+    options.out_width = Some(16);
+    options.color = false;
+
+    // blanking every grid should give empty frames, and the hook sees every frame in order
+    let mut indices = Vec::new();
+    transcode_ascii_with_hook(&path_in, &path_out, &options, |char_rows, i| {
+        indices.push(i);
+        for row in char_rows.iter_mut() {
+            row.fill(' ');
+        }
+    });
+    assert_eq!(indices, vec![0, 1, 2]);
+    for frame in read_gif(&path_out) {
+        assert!(frame.to_rgba8().pixels().all(|p| p[0] == 0));
+    }
+}
+
+#[test]
+fn transcode_ascii_palette_and_meta() {
+    let dir = temp_dir().join("image-to-ascii-gif-palette");
+    fs::create_dir_all(&dir).unwrap();
+    let (path_in, path_out) = (dir.join("in.gif"), dir.join("out.gif"));
+    write_gif(&path_in, &test_frames(), 10.);
+
+    let font = test_font();
+    let mut options = TranscodeOptions::new(&font);
+    options.out_width = Some(16);
+    options.palette = Some(vec![Rgb([255, 0, 0]), Rgb([0, 0, 255])]);
+    options.meta = Some(json!({ "fps": 10. }));
+    for global_palette in [false, true] {
+        options.global_palette = global_palette;
+        assert_eq!(transcode_ascii(&path_in, &path_out, &options), 3);
+        // the glyphs only have the palette's colors, drawn over black
+        for frame in read_gif(&path_out) {
+            assert!(frame
+                .to_rgba8()
+                .pixels()
+                .all(|p| p[1] == 0 && (p[0] == 0 || p[2] == 0)));
+        }
+        let meta = read_embedded_meta(&path_out).unwrap();
+        assert_eq!(meta["frames"], 3);
+        assert_eq!(meta["columns"], 16);
+        assert_eq!(meta["fps"], 10.);
+    }
+}
+
+#[test]
+fn transcode_ascii_hook_sees_rules() {
+    let dir = temp_dir().join("image-to-ascii-gif-hook-rules");
+    fs::create_dir_all(&dir).unwrap();
+    let (path_in, path_out) = (dir.join("in.gif"), dir.join("out.gif"));
+    write_gif(&path_in, &test_frames(), 10.);
+
+    // a rule whose alphabet only has # fills its region with it
+    let font = test_font();
+    let font_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fonts/bitocra-13.bdf");
+    let rule = Rule {
+        region: Region::Cells {
+            col: 0,
+            row: 0,
+            width: 4,
+            height: 2,
+        },
+        metric: None,
+        conversion_algorithm: None,
+        alphabet: None,
+    };
+    let mut options = TranscodeOptions::new(&font);
+    options.out_width = Some(16);
+    options.rules = vec![RuleConversion {
+        rule,
+        font: Font::new(&Font::read_bdf_file_chars(&font_path), &['#']),
+        convert: direction_and_intensity_convert,
+        algorithm: ConversionAlgorithm::EdgeAugmented,
+        selection: None,
+    }];
+
+    // the hook gets the grids after the rules, which are the ones that are rendered
+    let frames = read_gif(&path_in);
+    let mut grids = Vec::new();
+    transcode_ascii_with_hook(&path_in, &path_out, &options, |char_rows, i| {
+        assert_eq!(*char_rows, convert_frame(&frames[i], &options));
+        grids.push(char_rows.clone());
+    });
+    assert_eq!(grids.len(), 3);
+    for char_rows in &grids {
+        assert!(char_rows[..2].iter().all(|row| row[..4] == ['#'; 4]));
+    }
+}
+
+fn corrupt_frame(gif: &mut [u8], index: usize) {
+    // overwrites the start of the image data of a frame of test_frames with invalid lzw codes
+    let descriptor = [0x2c, 0, 0, 0, 0, 64, 0, 48, 0];
+    let start = (0..gif.len())
+        .filter(|&i| gif[i..].starts_with(&descriptor))
+        .nth(index)
+        .unwrap();
+    let packed = gif[start + 9];
+    let color_table_len = if packed & 0x80 == 0 {
+        0
+    } else {
+        3 << ((packed & 7) + 1)
+    };
+    let data = start + 12 + color_table_len;
+    gif[data..data + 6].fill(0xff);
+}
+
+#[test]
+fn corrupt_middle_frame_is_replaced() {
+    let mut gif = Vec::new();
+    write_gif_to_stream(&mut gif, &test_frames(), 10.);
+    let expected = read_gif_from_stream(&gif[..]);
+    corrupt_frame(&mut gif, 1);
+
+    // the decoder starts over after the corrupt frame, so the frames after it are still read
+    let frames = read_gif_from_stream(&gif[..]);
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[0].to_rgba8(), expected[0].to_rgba8());
+    assert_eq!(frames[1].to_rgba8(), expected[0].to_rgba8());
+    assert_eq!(frames[2].to_rgba8(), expected[2].to_rgba8());
+}
+
+#[test]
+#[should_panic(expected = "Error decoding frame 1")]
+fn corrupt_middle_frame_fails_when_strict() {
+    let mut gif = Vec::new();
+    write_gif_to_stream(&mut gif, &test_frames(), 10.);
+    corrupt_frame(&mut gif, 1);
+    read_gif_from_stream_with_limits(&gif[..], None, None, true);
+}